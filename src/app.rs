@@ -1,29 +1,81 @@
 use iced::widget::{column, container, row, stack, text, text_editor};
 use iced::widget::text::Wrapping;
-use iced::{Element, Fill, Task, Theme};
+use iced::{Element, Fill, Task, Theme, theme};
 use iced::widget;
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
-use crate::cursor_editor::CursorEditor;
-use crate::highlight::{FindHighlightSettings, FindHighlighter, format_highlight};
+use crate::brackets::BracketMap;
+use crate::cursor_editor::{CursorEditor, TAB_WIDTH};
+use crate::ex::{Substitute, SubstituteSession};
+use crate::history::History;
+use crate::indent::Indent;
+use crate::highlight::{BracketSettings, EditorHighlightSettings, EditorHighlighter, FindHighlightSettings, format_highlight};
+use crate::syntax::{Language, LineSpans, SyntaxSettings};
 use crate::message::{LineNumbers, Message, PendingAction, VimMode, VimPending};
+use crate::search::{FindMatch, TextLines, grapheme_column};
+use crate::theme::EditorTheme;
+use crate::undo_tree::{Coalescer, UndoGranularity};
 
 pub const EDITOR_ID: widget::Id = widget::Id::new("editor");
 
 pub struct App {
     pub content: text_editor::Content,
     pub current_file: Option<PathBuf>,
+    pub language: Option<Language>,
+    pub formatters: BTreeMap<String, String>,
+    pub indent: Indent,
+    pub indent_defaults: Indent,
+    pub syntax_spans: Option<LineSpans>,
+    pub brackets: BracketMap,
+    pub unmatched_marks: Vec<(usize, usize)>,
+    pub brackets_key: Option<(u64, Option<Language>)>,
+    pub rainbow_brackets: bool,
+    pub highlight_current_line: bool,
+    pub show_rulers: bool,
+    pub rulers: Vec<usize>,
+    pub indent_guides: bool,
+    pub show_whitespace: bool,
+    pub themes: Vec<Arc<EditorTheme>>,
+    pub theme_name: String,
+    pub follow_system_theme: bool,
+    pub system_theme: theme::Mode,
+    #[cfg(feature = "tree-sitter")]
+    pub syntax_tree: Option<crate::treesitter::SyntaxTree>,
+    #[cfg(feature = "tree-sitter")]
+    pub syntax_tree_tick: Option<u64>,
     pub is_modified: bool,
     pub show_panel: bool,
     pub find_query: String,
     pub replace_text: String,
     pub case_sensitive: bool,
+    pub use_regex: bool,
+    pub whole_word: bool,
+    pub find_in_selection: bool,
+    pub show_find_results: bool,
+    pub find_scope: Option<Range<(usize, usize)>>,
+    pub find_error: Option<String>,
     pub goto_line: String,
-    pub find_matches: Vec<(usize, usize)>,
+    pub find_matches: Arc<Vec<FindMatch>>,
+    pub find_truncated: bool,
+    pub find_pending: bool,
+    pub find_generation: u64,
+    pub find_cancel: Arc<AtomicBool>,
+    pub find_tick: Option<u64>,
+    pub text_lines: TextLines,
+    pub text_lines_tick: Option<u64>,
     pub current_match: Option<usize>,
+    pub find_results_focused: bool,
+    pub selected_result: Option<usize>,
     pub word_wrap: bool,
     pub scale: f32,
     pub ctrl_held: bool,
+    pub alt_held: bool,
+    pub extra_cursors: Vec<text_editor::Cursor>,
     pub show_about: bool,
     pub pending_action: Option<PendingAction>,
     pub vim_enabled: bool,
@@ -40,33 +92,118 @@ pub struct App {
     pub line_numbers: LineNumbers,
     pub vim_search_query: String,
     pub vim_search_forward: bool,
+    pub vim_search_pattern: String,
+    pub vim_search_matches: Arc<Vec<FindMatch>>,
+    pub vim_search_current: Option<usize>,
+    pub highlight_word: bool,
+    pub word_matches: Arc<Vec<FindMatch>>,
+    pub word_key: Option<(u64, usize, usize)>,
+    pub word_searched: Option<(u64, String)>,
+    pub word_due: Option<Instant>,
+    pub word_generation: u64,
+    pub word_cancel: Arc<AtomicBool>,
+    pub vim_search_origin: (usize, usize),
+    pub vim_search_tick: Option<u64>,
+    pub vim_search_pending: bool,
+    pub vim_search_generation: u64,
+    pub vim_search_cancel: Arc<AtomicBool>,
+    pub vim_hlsearch: bool,
+    pub vim_visual_marks: Option<(usize, usize)>,
+    pub vim_last_substitute: Option<Substitute>,
+    pub vim_last_replacement: String,
+    pub vim_substitute: Option<SubstituteSession>,
     pub undo_tree: crate::undo_tree::UndoTree,
     pub show_undo_panel: bool,
+    pub show_preview: bool,
+    pub preview: Arc<crate::markdown::Document>,
+    pub preview_tick: Option<u64>,
+    pub preview_line: usize,
     pub selected_undo_node: Option<usize>,
     pub undo_panel_focused: bool,
     pub undo_preview_text: String,
+    pub undo_label_input: String,
+    pub undo_search_query: String,
+    pub undo_search_hits: Vec<usize>,
+    pub undo_collapsed: HashSet<usize>,
+    pub undo_zoom: f32,
+    pub undo_viewport: Option<(f32, f32, f32)>,
+    pub status_message: String,
+    pub history: History,
     pub changedtick: u64,
     pub last_snapshot_tick: u64,
+    pub undo_granularity: UndoGranularity,
+    pub undo_pause_ms: u64,
+    pub undo_insert_session: bool,
+    pub undo_coalescer: Coalescer,
+    pub undo_batch: Option<(u64, bool)>,
+    pub edited_lines: Option<Range<usize>>,
+    pub format_cancel: Option<Arc<AtomicBool>>,
+    pub format_generation: u64,
 }
 
 impl App {
     pub fn new() -> (Self, Task<Message>) {
         let settings = crate::persistence::load_settings();
+        let (themes, broken_themes) = crate::theme::load_themes();
+        let indent = Indent {
+            shiftwidth: settings.shiftwidth.max(1),
+            expandtab: settings.expandtab,
+            softtabstop: settings.softtabstop,
+        };
         (
             Self {
                 content: text_editor::Content::new(),
                 current_file: None,
+                language: None,
+                formatters: settings.formatters,
+                indent,
+                indent_defaults: indent,
+                syntax_spans: None,
+                brackets: BracketMap::default(),
+                unmatched_marks: Vec::new(),
+                brackets_key: None,
+                rainbow_brackets: settings.rainbow_brackets,
+                highlight_current_line: settings.highlight_current_line,
+                show_rulers: settings.show_rulers,
+                rulers: settings.rulers,
+                indent_guides: settings.indent_guides,
+                show_whitespace: settings.show_whitespace,
+                themes,
+                theme_name: settings.theme.clone(),
+                follow_system_theme: settings.follow_system_theme,
+                system_theme: theme::Mode::None,
+                #[cfg(feature = "tree-sitter")]
+                syntax_tree: None,
+                #[cfg(feature = "tree-sitter")]
+                syntax_tree_tick: None,
                 is_modified: false,
                 show_panel: false,
                 find_query: String::new(),
                 replace_text: String::new(),
-                case_sensitive: false,
+                case_sensitive: settings.find_case_sensitive,
+                use_regex: settings.find_regex,
+                whole_word: settings.find_whole_word,
+                find_in_selection: settings.find_in_selection,
+                show_find_results: settings.find_show_results,
+                find_scope: None,
+                find_error: None,
                 goto_line: String::new(),
-                find_matches: Vec::new(),
+                find_matches: Arc::default(),
+                find_truncated: false,
+                find_pending: false,
+                find_generation: 0,
+                find_cancel: Arc::default(),
+                find_tick: None,
+                text_lines: TextLines::default(),
+                text_lines_tick: None,
                 current_match: None,
+                find_results_focused: false,
+                selected_result: None,
                 word_wrap: settings.word_wrap,
                 scale: settings.scale,
                 ctrl_held: false,
+                alt_held: false,
+                extra_cursors: Vec::new(),
                 show_about: false,
                 pending_action: None,
                 vim_enabled: settings.vim_enabled,
@@ -83,19 +220,63 @@ impl App {
                 line_numbers: settings.line_numbers,
                 vim_search_query: String::new(),
                 vim_search_forward: true,
+                vim_search_pattern: String::new(),
+                vim_search_matches: Arc::default(),
+                vim_search_current: None,
+                highlight_word: settings.highlight_word,
+                word_matches: Arc::default(),
+                word_key: None,
+                word_searched: None,
+                word_due: None,
+                word_generation: 0,
+                word_cancel: Arc::default(),
+                vim_search_origin: (0, 0),
+                vim_search_tick: None,
+                vim_search_pending: false,
+                vim_search_generation: 0,
+                vim_search_cancel: Arc::default(),
+                vim_hlsearch: false,
+                vim_visual_marks: None,
+                vim_last_substitute: None,
+                vim_last_replacement: String::new(),
+                vim_substitute: None,
                 undo_tree: crate::undo_tree::UndoTree::new(crate::undo_tree::Snapshot {
                     text: String::new(),
                     cursor_line: 0,
                     cursor_col: 0,
                 }),
                 show_undo_panel: false,
+                show_preview: false,
+                preview: Arc::default(),
+                preview_tick: None,
+                preview_line: 0,
                 selected_undo_node: None,
                 undo_panel_focused: false,
                 undo_preview_text: String::new(),
+                undo_label_input: String::new(),
+                undo_search_query: String::new(),
+                undo_search_hits: Vec::new(),
+                undo_collapsed: HashSet::new(),
+                undo_zoom: 1.0,
+                undo_viewport: None,
+                status_message: if broken_themes.is_empty() {
+                    String::new()
+                } else {
+                    format!("Could not parse theme {}", broken_themes.join(", "))
+                },
+                history: crate::persistence::load_history(),
                 changedtick: 0,
                 last_snapshot_tick: 0,
+                undo_granularity: settings.undo_granularity,
+                undo_pause_ms: settings.undo_pause_ms,
+                undo_insert_session: settings.undo_insert_session,
+                undo_coalescer: Coalescer::default(),
+                undo_batch: None,
+                edited_lines: None,
+                format_cancel: None,
+                format_generation: 0,
             },
-            Task::none(),
+            iced::system::theme().map(Message::SystemThemeChanged),
         )
     }
 
@@ -115,7 +296,7 @@ impl App {
     }
 
     pub fn theme(&self) -> Theme {
-        Theme::Dark
+        self.editor_theme().iced.clone()
     }
 
     pub fn view(&self) -> Element<'_, Message> {
@@ -123,6 +304,9 @@ impl App {
 
         if self.show_panel {
             col = col.push(self.search_panel());
+            if self.show_find_results && !self.find_query.is_empty() {
+                col = col.push(self.search_results());
+            }
         }
 
         let wrapping = if self.word_wrap {
@@ -133,7 +317,7 @@ impl App {
 
         let vim_normal_or_visual = self.vim_enabled && matches!(
             self.vim_mode,
-            VimMode::Normal | VimMode::Visual | VimMode::VisualLine
+            VimMode::Normal | VimMode::Visual | VimMode::VisualLine | VimMode::Confirm
         );
 
         let editor = text_editor(&self.content)
@@ -142,6 +326,15 @@ impl App {
             .wrapping(wrapping)
             .on_action(Message::Edit)
             .key_binding(move |key_press| {
+                if !vim_normal_or_visual
+                    && let iced::keyboard::Key::Named(iced::keyboard::key::Named::Tab) = key_press.key
+                {
+                    return match key_press.modifiers {
+                        m if m.is_empty() => Some(text_editor::Binding::Custom(Message::Indent)),
+                        m if m == iced::keyboard::Modifiers::SHIFT => Some(text_editor::Binding::Custom(Message::Unindent)),
+                        _ => None,
+                    };
+                }
                 if vim_normal_or_visual {
                     if matches!(
                         key_press.key,
@@ -160,12 +353,25 @@ impl App {
             });
 
         let active = self.show_panel && !self.find_query.is_empty();
+        let vim_highlight = self.vim_enabled && (self.vim_hlsearch || self.vim_mode == VimMode::Search);
+        let (matches, current_match) = match &self.vim_substitute {
+            Some(session) => (session.matches.clone(), Some(session.current)),
+            None if active => (self.find_matches.clone(), self.current_match),
+            None if vim_highlight => (self.vim_search_matches.clone(), self.vim_search_current),
+            None => (Arc::default(), None),
+        };
         let editor: Element<'_, Message> = editor
-            .highlight_with::<FindHighlighter>(
-                FindHighlightSettings {
-                    matches: if active { self.find_matches.clone() } else { vec![] },
-                    query_len: if active { self.find_query.len() } else { 0 },
-                    current_match: if active { self.current_match } else { None },
+            .highlight_with::<EditorHighlighter>(
+                EditorHighlightSettings {
+                    syntax: SyntaxSettings { language: self.language, spans: self.syntax_spans.clone() },
+                    brackets: BracketSettings {
+                        brackets: self.brackets.clone(),
+                        rainbow: self.rainbow_brackets,
+                        unmatched: self.language.is_some(),
+                    },
+                    find: FindHighlightSettings { matches, current_match },
+                    word: FindHighlightSettings { matches: self.word_matches.clone(), current_match: None },
+                    theme: self.editor_theme(),
                 },
                 format_highlight,
             )
@@ -173,16 +379,42 @@ impl App {
 
         let show_block = self.vim_enabled && matches!(
             self.vim_mode,
-            VimMode::Normal | VimMode::Visual | VimMode::VisualLine
+            VimMode::Normal | VimMode::Visual | VimMode::VisualLine | VimMode::Confirm
         );
         let cursor = self.content.cursor();
         let current_line = cursor.position.line;
+        let block_col = self.content.line(current_line).map_or(0, |l| grapheme_column(&l.text, self.vim_col));
+        let cursor_text = self.content.line(current_line).map(|l| l.text.into_owned()).unwrap_or_default();
+        let indent_width = if self.indent.expandtab { self.indent.shiftwidth } else { TAB_WIDTH };
+        // The overlay places rows at line * line_height, which only holds without wrapping.
+        let rows_align = !self.word_wrap;
+        let extra_cursors = self.extra_cursors.iter().filter(|_| rows_align).filter_map(|c| {
+            let line = self.content.line(c.position.line)?;
+            let col = grapheme_column(&line.text, c.position.column);
+            let anchor = c.selection
+                .filter(|s| s.line == c.position.line)
+                .map(|s| grapheme_column(&line.text, s.column));
+            Some((c.position.line, col, anchor))
+        }).collect();
         let editor_widget: Element<'_, Message> = CursorEditor::new(
             editor,
             current_line,
-            self.vim_col,
+            block_col,
             show_block,
-        ).into();
+            self.editor_theme(),
+        )
+        .extra_cursors(extra_cursors)
+        .bracket_marks(
+            if rows_align { self.bracket_pair() } else { Vec::new() },
+            if rows_align { self.unmatched_brackets() } else { Vec::new() },
+        )
+        .cursor(current_line, grapheme_column(&cursor_text, cursor.position.column), self.content.line_count())
+        .current_line(self.highlight_current_line && rows_align)
+        .rulers(if self.show_rulers { self.rulers.clone() } else { Vec::new() })
+        .text(&self.content)
+        .indent_guides(self.indent_guides && rows_align, indent_width)
+        .whitespace(self.show_whitespace && rows_align)
+        .into();
 
         let editor_area: Element<'_, Message> = if self.line_numbers != LineNumbers::None {
             let total = self.content.line_count();
@@ -192,6 +424,7 @@ impl App {
             let line_count = if last_line_empty && total > 1 { total - 1 } else { total };
             let font_size = 16.0_f32;
             let line_height = font_size * 1.3;
+            let palette = self.editor_theme();
             let gutter_col = (0..line_count).fold(
                 column![].spacing(0),
                 |col, i| {
@@ -203,9 +436,9 @@ impl App {
                         LineNumbers::None => unreachable!(),
                     };
                     let color = if i == current_line {
-                        iced::Color::from_rgb(0.7, 0.7, 0.7)
+                        palette.gutter_current
                     } else {
-                        iced::Color::from_rgb(0.4, 0.4, 0.4)
+                        palette.gutter
                     };
                     col.push(
                         container(
@@ -231,11 +464,13 @@ impl App {
             editor_widget
         };
 
-        let main_row: Element<'_, Message> = if self.show_undo_panel {
-            row![editor_area, self.undo_tree_panel()].into()
-        } else {
-            editor_area
-        };
+        let mut main_row = row![editor_area];
+        if self.show_preview {
+            main_row = main_row.push(self.markdown_preview());
+        }
+        if self.show_undo_panel {
+            main_row = main_row.push(self.undo_tree_panel());
+        }
         col = col.push(main_row);
         if self.vim_enabled && self.vim_mode == VimMode::Command {
             col = col.push(self.command_bar());
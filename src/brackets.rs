@@ -0,0 +1,422 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::app::App;
+use crate::line_map::{LineMap, RowEdit};
+use crate::message::VimMode;
+use crate::search::grapheme_column;
+use crate::syntax::{self, Language, LineState, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bracket {
+    pub col: usize,
+    pub depth: usize,
+    partner: Option<(isize, usize)>,
+    byte: u8,
+}
+
+impl Bracket {
+    pub fn is_matched(&self) -> bool {
+        self.partner.is_some()
+    }
+
+    fn is_open(&self) -> bool {
+        opening(self.byte) == self.byte
+    }
+
+    fn partner_of(&self, line: usize) -> Option<(usize, usize)> {
+        let (offset, col) = self.partner?;
+        Some((line.checked_add_signed(offset)?, col))
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    byte: u8,
+    len: usize,
+    below: Stack,
+}
+
+impl PartialEq for Frame {
+    fn eq(&self, other: &Self) -> bool {
+        self.byte == other.byte && self.len == other.len && same_kinds(&self.below, &other.below)
+    }
+}
+
+type Stack = Option<Arc<Frame>>;
+
+fn stack_len(stack: &Stack) -> usize {
+    stack.as_ref().map_or(0, |frame| frame.len)
+}
+
+fn same_kinds(mut a: &Stack, mut b: &Stack) -> bool {
+    loop {
+        match (a, b) {
+            (None, None) => return true,
+            (Some(x), Some(y)) if Arc::ptr_eq(x, y) => return true,
+            (Some(x), Some(y)) if x.byte == y.byte && x.len == y.len => (a, b) = (&x.below, &y.below),
+            _ => return false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BracketLine {
+    pub brackets: Vec<Bracket>,
+    state: LineState,
+    stack: Stack,
+}
+
+impl BracketLine {
+    pub fn same_marks(&self, other: &Self) -> bool {
+        self.brackets.len() == other.brackets.len()
+            && self.brackets.iter().zip(&other.brackets).all(|(a, b)| {
+                a.col == b.col && a.depth == b.depth && a.partner.is_some() == b.partner.is_some()
+            })
+    }
+}
+
+// One row per line plus a final row holding the state and open brackets at the end of the text.
+pub type BracketMap = LineMap<BracketLine>;
+
+fn opening(byte: u8) -> u8 {
+    match byte {
+        b')' => b'(',
+        b']' => b'[',
+        b'}' => b'{',
+        byte => byte,
+    }
+}
+
+fn offset(from: usize, to: usize) -> isize {
+    to as isize - from as isize
+}
+
+fn line_brackets(language: Option<Language>, line: &str, state: LineState) -> (Vec<(usize, u8)>, LineState) {
+    let (skipped, next): (Vec<_>, _) = match language {
+        Some(language) => {
+            let (spans, next) = syntax::highlight_line(language, line, state);
+            let skipped = spans
+                .into_iter()
+                .filter(|(_, token)| matches!(token, Token::String | Token::Comment | Token::Code))
+                .map(|(range, _)| range)
+                .collect();
+            (skipped, next)
+        }
+        None => (Vec::new(), state),
+    };
+    let brackets = line
+        .bytes()
+        .enumerate()
+        .filter(|&(col, b)| b"()[]{}".contains(&b) && !skipped.iter().any(|r| r.contains(&col)))
+        .collect();
+    (brackets, next)
+}
+
+struct Scan {
+    language: Option<Language>,
+    from: usize,
+    rows: Vec<BracketLine>,
+    state: LineState,
+    stack: Stack,
+    // The opener of every open level, `None` for levels opened above `from`.
+    open: Vec<Option<(usize, usize)>>,
+    // Closers of levels opened above `from`, as (level, line, index).
+    head_closers: Vec<(usize, usize, usize)>,
+    low: usize,
+}
+
+impl Scan {
+    fn new(language: Option<Language>, from: usize, start: Option<&BracketLine>) -> Self {
+        let (state, stack) = start.map_or_else(Default::default, |row| (row.state, row.stack.clone()));
+        let levels = stack_len(&stack);
+        Self { language, from, rows: Vec::new(), state, stack, open: vec![None; levels], head_closers: Vec::new(), low: levels }
+    }
+
+    fn next_line(&self) -> usize {
+        self.from + self.rows.len()
+    }
+
+    fn position(&self, line: usize, index: usize) -> (usize, usize) {
+        (line, self.rows[line - self.from].brackets[index].col)
+    }
+
+    fn push_line(&mut self, text: &str) {
+        let line = self.next_line();
+        let (found, next) = line_brackets(self.language, text, self.state);
+        let mut row = BracketLine { brackets: Vec::new(), state: self.state, stack: self.stack.clone() };
+        self.state = next;
+        for (col, byte) in found {
+            let depth = stack_len(&self.stack);
+            if opening(byte) == byte {
+                row.brackets.push(Bracket { col, depth, partner: None, byte });
+                self.stack = Some(Arc::new(Frame { byte, len: depth + 1, below: self.stack.take() }));
+                self.open.push(Some((line, row.brackets.len() - 1)));
+                continue;
+            }
+            let Some(frame) = self.stack.clone().filter(|frame| frame.byte == opening(byte)) else {
+                row.brackets.push(Bracket { col, depth, partner: None, byte });
+                continue;
+            };
+            self.stack = frame.below.clone();
+            let level = depth - 1;
+            self.low = self.low.min(level);
+            let partner = match self.open.pop().flatten() {
+                Some((open_line, index)) => {
+                    let opener = match self.rows.get_mut(open_line - self.from) {
+                        Some(open_row) => &mut open_row.brackets[index],
+                        None => &mut row.brackets[index],
+                    };
+                    opener.partner = Some((offset(open_line, line), col));
+                    Some((offset(line, open_line), opener.col))
+                }
+                None => {
+                    self.head_closers.push((level, line, row.brackets.len()));
+                    None
+                }
+            };
+            row.brackets.push(Bracket { col, depth: level, partner, byte });
+        }
+        self.rows.push(row);
+    }
+
+    fn finish(&mut self) {
+        self.rows.push(BracketLine { brackets: Vec::new(), state: self.state, stack: self.stack.clone() });
+    }
+}
+
+fn unmatched_in(map: &BracketMap, lines: Range<usize>) -> Vec<(usize, usize)> {
+    (lines.start..)
+        .zip(map.iter_from(lines.start).take(lines.len()))
+        .flat_map(|(line, row)| row.brackets.iter().filter(|b| !b.is_matched()).map(move |b| (line, b.col)))
+        .collect()
+}
+
+fn scan_all<S: AsRef<str>>(language: Option<Language>, lines: impl IntoIterator<Item = S>) -> (BracketMap, Vec<(usize, usize)>) {
+    let mut scan = Scan::new(language, 0, None);
+    for text in lines {
+        scan.push_line(text.as_ref());
+    }
+    scan.finish();
+    let mut map = BracketMap::default();
+    map.splice(0..0, scan.rows);
+    let unmatched = unmatched_in(&map, 0..usize::MAX);
+    (map, unmatched)
+}
+
+fn find_openers(map: &BracketMap, before: usize, wanted: &[usize], found: &mut [Option<(usize, usize)>]) {
+    let mut missing = wanted.len();
+    for line in (0..before).rev() {
+        if missing == 0 {
+            break;
+        }
+        let Some(row) = map.get(line) else { continue };
+        for b in row.brackets.iter().rev() {
+            if b.is_open() && wanted.contains(&b.depth) && found[b.depth].is_none() {
+                found[b.depth] = Some((line, b.col));
+                missing -= 1;
+            }
+        }
+    }
+}
+
+fn set_partner(map: &mut BracketMap, (line, col): (usize, usize), partner: Option<(usize, usize)>) {
+    let partner = partner.map(|(to, to_col)| (offset(line, to), to_col));
+    let stale = |row: &BracketLine| row.brackets.iter().position(|b| b.col == col && b.partner != partner);
+    if let Some(index) = map.get(line).and_then(stale)
+        && let Some(row) = map.get_mut(line)
+    {
+        row.brackets[index].partner = partner;
+    }
+}
+
+// Rescans from the edit until the lexer state and open brackets line up with the old rows again,
+// then relinks only the pairs that cross the rescanned rows.
+fn rescan<S: AsRef<str>>(
+    map: &mut BracketMap,
+    unmatched: &mut Vec<(usize, usize)>,
+    language: Option<Language>,
+    edit: RowEdit,
+    line_count: usize,
+    line: impl Fn(usize) -> S,
+) -> bool {
+    let from = edit.from;
+    let shift = offset(edit.old_end, edit.new_end);
+    let moved = |line: usize| line.checked_add_signed(shift).unwrap_or(line);
+    let Some(start) = map.get(from) else { return false };
+    let mut scan = Scan::new(language, from, Some(start));
+    let levels = scan.open.len();
+    let resync = loop {
+        let next = scan.next_line();
+        if next >= edit.new_end {
+            let old = next - edit.new_end + edit.old_end;
+            if map.get(old).is_some_and(|row| row.state == scan.state && same_kinds(&row.stack, &scan.stack)) {
+                break Some(old);
+            }
+        }
+        if next == line_count {
+            scan.finish();
+            break None;
+        }
+        scan.push_line(line(next).as_ref());
+    };
+    let old_end = resync.unwrap_or(usize::MAX);
+
+    let mut old_open: Vec<Option<(usize, Bracket)>> = vec![None; levels];
+    let mut low_old = levels;
+    let mut head: Vec<Option<(usize, usize)>> = vec![None; levels];
+    for (line, row) in (from..old_end).zip(map.iter_from(from)) {
+        for &b in &row.brackets {
+            if b.is_open() {
+                old_open.truncate(b.depth);
+                old_open.push(Some((line, b)));
+            } else if b.is_matched() && b.depth < old_open.len() {
+                if old_open[b.depth].is_none() {
+                    head[b.depth] = b.partner_of(line);
+                }
+                old_open.truncate(b.depth);
+                low_old = low_old.min(b.depth);
+            }
+        }
+    }
+    // Openers that never close sit at the bottom of every later stack.
+    let split = unmatched.partition_point(|&(line, _)| line < from);
+    let never_closed: Vec<_> = unmatched[..split]
+        .iter()
+        .copied()
+        .filter(|&(line, col)| map.get(line).and_then(|row| row.brackets.iter().find(|b| b.col == col)).is_some_and(Bracket::is_open))
+        .take(levels)
+        .collect();
+    for (slot, &mark) in head.iter_mut().zip(&never_closed) {
+        *slot = Some(mark);
+    }
+    let never_closed = never_closed.len();
+    let untouched = low_old.min(scan.low);
+    let wanted: Vec<_> = (0..levels)
+        .filter(|&level| head[level].is_none() && (level >= untouched || (resync.is_some() && shift != 0)))
+        .collect();
+    find_openers(map, from, &wanted, &mut head);
+    let tail_closer = |level: usize| {
+        let (line, bracket) = match old_open.get(level).copied().flatten() {
+            Some(opener) => opener,
+            None => {
+                let (line, col) = head[level]?;
+                (line, *map.get(line)?.brackets.iter().find(|b| b.col == col)?)
+            }
+        };
+        let (line, col) = bracket.partner_of(line)?;
+        Some((moved(line), col))
+    };
+
+    let mut links = Vec::new();
+    let mut closed = Vec::new();
+    let mut opened = Vec::new();
+    for &(level, line, index) in &scan.head_closers {
+        let closer = scan.position(line, index);
+        if let Some(opener) = head[level] {
+            links.push((closer, Some(opener)));
+            links.push((opener, Some(closer)));
+            closed.push(opener);
+        }
+    }
+    match resync {
+        Some(_) => {
+            for (level, open) in scan.open.iter().enumerate() {
+                if level < untouched && (shift == 0 || level < never_closed) {
+                    continue;
+                }
+                let opener = match *open {
+                    Some((line, index)) => scan.position(line, index),
+                    None => match head[level] {
+                        Some(opener) => opener,
+                        None => continue,
+                    },
+                };
+                let closer = tail_closer(level);
+                links.push((opener, closer));
+                match closer {
+                    Some(closer) => links.push((closer, Some(opener))),
+                    None if open.is_none() => opened.push(opener),
+                    None => {}
+                }
+            }
+        }
+        None => {
+            for opener in head.iter().take(scan.low).skip(never_closed).flatten() {
+                links.push((*opener, None));
+                opened.push(*opener);
+            }
+        }
+    }
+
+    let rows = scan.rows.len();
+    map.splice(from..old_end, scan.rows);
+    for (bracket, partner) in links {
+        set_partner(map, bracket, partner);
+    }
+    let tail = unmatched.partition_point(|&(line, _)| line < old_end);
+    let mut marks: Vec<_> = unmatched[..split].iter().copied().filter(|mark| !closed.contains(mark)).collect();
+    marks.extend(opened);
+    marks.sort_unstable();
+    marks.extend(unmatched_in(map, from..from + rows));
+    marks.extend(unmatched[tail..].iter().map(|&(line, col)| (moved(line), col)));
+    *unmatched = marks;
+    true
+}
+
+impl App {
+    pub fn refresh_brackets(&mut self, edit: Option<RowEdit>) {
+        let key = (self.changedtick, self.language);
+        if self.brackets_key == Some(key) {
+            return;
+        }
+        let previous = self.brackets_key.replace(key).filter(|&(_, language)| language == self.language);
+        let language = self.language;
+        let line_count = self.content.line_count();
+        let content = &self.content;
+        let line = |i: usize| content.line(i).map(|line| line.text).unwrap_or_default();
+        let mut brackets = self.brackets.clone();
+        let mut unmatched = std::mem::take(&mut self.unmatched_marks);
+        let patched = edit
+            .filter(|edit| edit.applies_to(previous.map(|(tick, _)| tick)))
+            .is_some_and(|edit| rescan(&mut brackets, &mut unmatched, language, edit, line_count, line));
+        if !patched {
+            (brackets, unmatched) = scan_all(language, (0..line_count).map(line));
+        }
+        self.brackets = brackets;
+        self.unmatched_marks = unmatched;
+    }
+
+    fn bracket_at(&self, line: usize, col: usize) -> Option<&Bracket> {
+        self.brackets.get(line)?.brackets.iter().find(|b| b.col == col)
+    }
+
+    pub fn bracket_pair(&self) -> Vec<(usize, usize)> {
+        let position = self.content.cursor().position;
+        let block = self.vim_enabled && self.vim_mode != VimMode::Insert;
+        let (line, col) = if block { (position.line, self.vim_col) } else { (position.line, position.column) };
+        let bracket = self
+            .bracket_at(line, col)
+            .or_else(|| if block { None } else { col.checked_sub(1).and_then(|c| self.bracket_at(line, c)) });
+        let Some(bracket) = bracket.copied() else {
+            return Vec::new();
+        };
+        let mut marks = vec![(line, bracket.col)];
+        marks.extend(bracket.partner_of(line));
+        self.grapheme_marks(marks)
+    }
+
+    pub fn unmatched_brackets(&self) -> Vec<(usize, usize)> {
+        match self.language {
+            Some(_) => self.grapheme_marks(self.unmatched_marks.iter().take(1000).copied().collect()),
+            None => Vec::new(),
+        }
+    }
+
+    fn grapheme_marks(&self, marks: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        marks
+            .into_iter()
+            .filter_map(|(line, col)| Some((line, grapheme_column(&self.content.line(line)?.text, col))))
+            .collect()
+    }
+}
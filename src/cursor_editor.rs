@@ -1,18 +1,57 @@
 use iced::advanced::layout::{self, Layout};
 use iced::advanced::renderer::{self, Renderer as _};
 use iced::advanced::text::{self, Paragraph as _, Renderer as _};
-use iced::advanced::widget::{self, Widget};
+use iced::advanced::widget::{self, tree, Widget};
 use iced::advanced::{self, Clipboard, Shell};
 use iced::mouse;
-use iced::{Color, Element, Event, Font, Length, Rectangle, Size, Theme, Vector};
+use iced::widget::text_editor;
+use iced::{Border, Color, Element, Event, Font, Length, Point, Rectangle, Size, Theme, Vector};
+use std::cell::Cell;
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::theme::EditorTheme;
 
 type Renderer = iced::Renderer;
 
+pub const TAB_WIDTH: usize = 8;
+
+const PADDING: f32 = 5.0;
+
+// The text editor keeps its scroll offset private, so the overlay mirrors it:
+// wheel scrolls are replayed here the way the editor converts them, and when
+// the cursor moves the offset is pulled just far enough to keep it in view.
+#[derive(Default)]
+struct State {
+    top: Cell<f32>,
+    partial_scroll: f32,
+    cursor: Cell<Option<(usize, usize)>>,
+}
+
 pub struct CursorEditor<'a, Message> {
     inner: Element<'a, Message>,
     vim_line: usize,
     vim_col: usize,
     show_cursor: bool,
+    theme: Arc<EditorTheme>,
+    extra_cursors: Vec<(usize, usize, Option<usize>)>,
+    bracket_pair: Vec<(usize, usize)>,
+    unmatched: Vec<(usize, usize)>,
+    cursor: (usize, usize),
+    line_count: usize,
+    current_line: bool,
+    rulers: Vec<usize>,
+    text: Option<&'a text_editor::Content>,
+    indent_guides: bool,
+    indent_width: usize,
+    whitespace: bool,
+}
+
+struct Whitespace {
+    marks: Vec<(usize, char)>,
+    trailing: Range<usize>,
 }
 
 impl<'a, Message> CursorEditor<'a, Message> {
@@ -21,8 +60,69 @@ impl<'a, Message> CursorEditor<'a, Message> {
         vim_line: usize,
         vim_col: usize,
         show_cursor: bool,
+        theme: Arc<EditorTheme>,
     ) -> Self {
-        Self { inner, vim_line, vim_col, show_cursor }
+        Self {
+            inner,
+            vim_line,
+            vim_col,
+            show_cursor,
+            theme,
+            extra_cursors: Vec::new(),
+            bracket_pair: Vec::new(),
+            unmatched: Vec::new(),
+            cursor: (vim_line, vim_col),
+            line_count: vim_line + 1,
+            current_line: false,
+            rulers: Vec::new(),
+            text: None,
+            indent_guides: false,
+            indent_width: 4,
+            whitespace: false,
+        }
+    }
+
+    pub fn cursor(mut self, line: usize, column: usize, line_count: usize) -> Self {
+        self.cursor = (line, column);
+        self.line_count = line_count;
+        self
+    }
+
+    pub fn current_line(mut self, enabled: bool) -> Self {
+        self.current_line = enabled;
+        self
+    }
+
+    pub fn rulers(mut self, rulers: Vec<usize>) -> Self {
+        self.rulers = rulers;
+        self
+    }
+
+    pub fn text(mut self, content: &'a text_editor::Content) -> Self {
+        self.text = Some(content);
+        self
+    }
+
+    pub fn indent_guides(mut self, enabled: bool, width: usize) -> Self {
+        self.indent_guides = enabled;
+        self.indent_width = width.max(1);
+        self
+    }
+
+    pub fn whitespace(mut self, enabled: bool) -> Self {
+        self.whitespace = enabled;
+        self
+    }
+
+    pub fn extra_cursors(mut self, cursors: Vec<(usize, usize, Option<usize>)>) -> Self {
+        self.extra_cursors = cursors;
+        self
+    }
+
+    pub fn bracket_marks(mut self, pair: Vec<(usize, usize)>, unmatched: Vec<(usize, usize)>) -> Self {
+        self.bracket_pair = pair;
+        self.unmatched = unmatched;
+        self
     }
 }
 
@@ -61,10 +161,6 @@ impl<Message> Widget<Message, Theme, Renderer> for CursorEditor<'_, Message>
             viewport,
         );
 
-        if !self.show_cursor {
-            return;
-        }
-
         let bounds = layout.bounds();
         let font_size = renderer.default_size();
         let line_height = font_size.0 * 1.3;
@@ -84,16 +180,154 @@ impl<Message> Widget<Message, Theme, Renderer> for CursorEditor<'_, Message>
         );
         let char_width = char_para.min_bounds().width;
 
-        let padding = 5.0;
-        let x = bounds.x + padding + self.vim_col as f32 * char_width;
-        let y = bounds.y + padding + self.vim_line as f32 * line_height;
+        let state = tree.state.downcast_ref::<State>();
+        let height = (bounds.height - 2.0 * PADDING).max(0.0);
+        let mut top = state.top.get();
+        if state.cursor.get() != Some(self.cursor) {
+            state.cursor.set(Some(self.cursor));
+            let y = self.cursor.0 as f32 * line_height;
+            if y < top {
+                top = y;
+            } else if y + line_height > top + height {
+                top = y + line_height - height;
+            }
+        }
+        let top = top.min(self.line_count as f32 * line_height - height).max(0.0);
+        state.top.set(top);
 
-        let cursor_rect = Rectangle {
-            x,
-            y,
-            width: char_width,
+        let cell = |line: usize, col: usize, width: f32| Rectangle {
+            x: bounds.x + PADDING + col as f32 * char_width,
+            y: bounds.y + PADDING + line as f32 * line_height - top,
+            width,
             height: line_height,
         };
+        let mut fill = |rect: Rectangle, color: Color| {
+            if let Some(visible) = rect.intersection(&bounds) {
+                renderer.fill_quad(
+                    renderer::Quad {
+                        bounds: visible,
+                        ..Default::default()
+                    },
+                    color,
+                );
+            }
+        };
+
+        if self.current_line {
+            let row = cell(self.cursor.0, 0, 0.0);
+            fill(Rectangle { x: bounds.x, width: bounds.width, ..row }, self.theme.current_line);
+        }
+
+        let first = (top / line_height) as usize;
+        let last = ((top + height) / line_height).ceil() as usize + 1;
+        let text = self.text.filter(|_| self.indent_guides);
+        for (line, indent) in text.map(|text| indent_columns(text, first..last)).unwrap_or_default() {
+            for col in (0..indent).step_by(self.indent_width) {
+                fill(cell(line, col, 1.0), self.theme.indent_guide);
+            }
+        }
+
+        for &ruler in &self.rulers {
+            let x = cell(0, ruler, 1.0).x;
+            fill(Rectangle { x, width: 1.0, ..bounds }, self.theme.ruler);
+        }
+
+        let text = self.text.filter(|_| self.whitespace);
+        let visible_whitespace = text.map(|text| whitespace_marks(text, first..last)).unwrap_or_default();
+        for (line, whitespace) in &visible_whitespace {
+            let line = *line;
+            let Range { start, end } = whitespace.trailing;
+            fill(cell(line, start, (end - start) as f32 * char_width), self.theme.trailing_whitespace);
+        }
+        for (line, whitespace) in visible_whitespace {
+            for (col, glyph) in whitespace.marks {
+                let rect = cell(line, col, char_width);
+                renderer.fill_text(
+                    text::Text {
+                        content: glyph.to_string(),
+                        bounds: rect.size(),
+                        size: font_size,
+                        line_height: text::LineHeight::default(),
+                        font: Font::MONOSPACE,
+                        align_x: iced::alignment::Horizontal::Left.into(),
+                        align_y: iced::alignment::Vertical::Top,
+                        shaping: text::Shaping::Advanced,
+                        wrapping: text::Wrapping::None,
+                    },
+                    Point::new(rect.x, rect.y),
+                    self.theme.whitespace,
+                    bounds,
+                );
+            }
+        }
+
+        for &(line, col) in &self.bracket_pair {
+            let rect = cell(line, col, char_width);
+            if rect.intersection(&bounds).is_some() {
+                renderer.fill_quad(
+                    renderer::Quad {
+                        bounds: rect,
+                        border: Border {
+                            color: self.theme.bracket_match,
+                            width: 1.0,
+                            radius: 2.0.into(),
+                        },
+                        ..Default::default()
+                    },
+                    self.theme.bracket_match.scale_alpha(0.15),
+                );
+            }
+        }
+
+        for &(line, col) in &self.unmatched {
+            let rect = cell(line, col, char_width);
+            let underline = Rectangle { y: rect.y + rect.height - 2.0, height: 2.0, ..rect };
+            if let Some(visible) = underline.intersection(&bounds) {
+                renderer.fill_quad(
+                    renderer::Quad {
+                        bounds: visible,
+                        ..Default::default()
+                    },
+                    self.theme.unmatched,
+                );
+            }
+        }
+
+        for &(line, col, anchor) in &self.extra_cursors {
+            if let Some(anchor) = anchor {
+                let start = anchor.min(col);
+                let selection = cell(line, start, (anchor.max(col) - start) as f32 * char_width);
+                if let Some(visible) = selection.intersection(&bounds) {
+                    renderer.fill_quad(
+                        renderer::Quad {
+                            bounds: visible,
+                            ..Default::default()
+                        },
+                        self.theme.selection,
+                    );
+                }
+            }
+            let (width, color) = if self.show_cursor {
+                (char_width, self.theme.cursor)
+            } else {
+                (2.0, self.theme.palette.text)
+            };
+            if let Some(visible) = cell(line, col, width).intersection(&bounds) {
+                renderer.fill_quad(
+                    renderer::Quad {
+                        bounds: visible,
+                        ..Default::default()
+                    },
+                    color,
+                );
+            }
+        }
+
+        if !self.show_cursor {
+            return;
+        }
+
+        let cursor_rect = cell(self.vim_line, self.vim_col, char_width);
 
         if cursor_rect.intersection(&bounds).is_none() {
             return;
@@ -104,16 +338,16 @@ impl<Message> Widget<Message, Theme, Renderer> for CursorEditor<'_, Message>
                 bounds: cursor_rect,
                 ..Default::default()
             },
-            Color::from_rgba(0.7, 0.7, 0.7, 0.5),
+            self.theme.cursor,
         );
     }
 
-    fn tag(&self) -> widget::tree::Tag {
-        self.inner.as_widget().tag()
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
     }
 
-    fn state(&self) -> widget::tree::State {
-        self.inner.as_widget().state()
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
     }
 
     fn children(&self) -> Vec<widget::Tree> {
@@ -145,6 +379,20 @@ impl<Message> Widget<Message, Theme, Renderer> for CursorEditor<'_, Message>
         shell: &mut Shell<'_, Message>,
         viewport: &Rectangle,
     ) {
+        if let Event::Mouse(mouse::Event::WheelScrolled { delta }) = event
+            && cursor.is_over(layout.bounds())
+        {
+            let lines = match *delta {
+                mouse::ScrollDelta::Lines { y, .. } if y.abs() > 0.0 => y.signum() * -(y.abs() * 4.0).max(1.0),
+                mouse::ScrollDelta::Lines { .. } => 0.0,
+                mouse::ScrollDelta::Pixels { y, .. } => -y / 4.0,
+            };
+            let state = tree.state.downcast_mut::<State>();
+            let lines = lines + state.partial_scroll;
+            state.partial_scroll = lines.fract();
+            let line_height = renderer.default_size().0 * 1.3;
+            state.top.set(state.top.get() + lines.trunc() * line_height);
+        }
         self.inner.as_widget_mut().update(
             &mut tree.children[0],
             event,
@@ -192,6 +440,78 @@ impl<Message> Widget<Message, Theme, Renderer> for CursorEditor<'_, Message>
     }
 }
 
+fn leading_width(line: &str) -> Option<usize> {
+    let rest = line.trim_start_matches([' ', '\t']);
+    if rest.is_empty() {
+        return None;
+    }
+    let lead = &line[..line.len() - rest.len()];
+    Some(lead.chars().fold(0, |col, c| if c == '\t' { (col / TAB_WIDTH + 1) * TAB_WIDTH } else { col + 1 }))
+}
+
+fn indent_columns(content: &text_editor::Content, lines: Range<usize>) -> Vec<(usize, usize)> {
+    let indent = |line: usize| content.line(line).map(|l| leading_width(&l.text));
+    let mut previous = (0..lines.start).rev().find_map(|line| indent(line).flatten()).unwrap_or(0);
+    let mut next = (0, 0);
+    let mut result = Vec::new();
+    for line in lines {
+        let width = match indent(line) {
+            None => break,
+            Some(Some(width)) => {
+                previous = width;
+                width
+            }
+            Some(None) => {
+                if next.0 <= line {
+                    next = (line + 1..)
+                        .map(|below| (below, indent(below)))
+                        .find_map(|(below, width)| match width {
+                            None => Some((usize::MAX, 0)),
+                            Some(width) => width.map(|width| (below, width)),
+                        })
+                        .unwrap_or((usize::MAX, 0));
+                }
+                previous.min(next.1)
+            }
+        };
+        result.push((line, width));
+    }
+    result
+}
+
+fn whitespace_marks(content: &text_editor::Content, lines: Range<usize>) -> Vec<(usize, Whitespace)> {
+    let last = content.line_count().saturating_sub(1);
+    lines
+        .map_while(|line| Some((line, content.line(line)?)))
+        .map(|(line, text)| {
+            let mut marks = Vec::new();
+            let mut col = 0;
+            let mut trailing_start = 0;
+            for grapheme in text.text.trim_end_matches('\r').graphemes(true) {
+                let width = match grapheme {
+                    " " => {
+                        marks.push((col, '·'));
+                        1
+                    }
+                    "\t" => {
+                        marks.push((col, '→'));
+                        TAB_WIDTH - col % TAB_WIDTH
+                    }
+                    grapheme => grapheme.width().max(1),
+                };
+                col += width;
+                if grapheme != " " && grapheme != "\t" {
+                    trailing_start = col;
+                }
+            }
+            if line < last {
+                marks.push((col, '¬'));
+            }
+            (line, Whitespace { marks, trailing: trailing_start..col })
+        })
+        .collect()
+}
+
 impl<'a, Message: 'a> From<CursorEditor<'a, Message>> for Element<'a, Message>
 where
     Message: Clone,
@@ -0,0 +1,487 @@
+use regex::{Captures, Regex};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::search::{FindMatch, LineIndex};
+
+pub type LineRange = (usize, usize);
+
+pub struct LineContext {
+    pub current: usize,
+    pub last: usize,
+    pub visual: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub global: bool,
+    pub case_sensitive: bool,
+    pub confirm: bool,
+    pub count_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub invert: bool,
+    pub pattern: String,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineCommand {
+    Delete,
+    Move(String),
+    Copy(String),
+    Substitute(String),
+    Normal(String),
+    Print,
+}
+
+pub struct SubstituteSession {
+    pub text: String,
+    pub edits: Vec<(Range<usize>, String)>,
+    pub matches: Arc<Vec<FindMatch>>,
+    pub accepted: Vec<bool>,
+    pub current: usize,
+}
+
+fn parse_number(input: &str) -> (Option<usize>, &str) {
+    let digits = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    (input[..digits].parse().ok(), &input[digits..])
+}
+
+fn parse_address<'a>(input: &'a str, ctx: &LineContext) -> Result<(Option<isize>, &'a str), String> {
+    let (base, mut rest) = match input.chars().next() {
+        Some('.') => (Some(ctx.current as isize), &input[1..]),
+        Some('$') => (Some(ctx.last as isize), &input[1..]),
+        Some('\'') => {
+            let (start, end) = ctx.visual.ok_or("E20: Mark not set")?;
+            match input[1..].chars().next() {
+                Some('<') => (Some(start as isize), &input[2..]),
+                Some('>') => (Some(end as isize), &input[2..]),
+                _ => return Err(String::from("E20: Mark not set")),
+            }
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let (n, rest) = parse_number(input);
+            (n.map(|n| n as isize - 1), rest)
+        }
+        _ => (None, input),
+    };
+
+    let mut offset = 0;
+    let mut has_offset = false;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let (n, after) = parse_number(&rest[1..]);
+        let n = n.unwrap_or(1) as isize;
+        offset += if sign == '+' { n } else { -n };
+        has_offset = true;
+        rest = after;
+    }
+
+    match (base, has_offset) {
+        (None, false) => Ok((None, rest)),
+        (base, _) => Ok((Some(base.unwrap_or(ctx.current as isize) + offset), rest)),
+    }
+}
+
+pub fn parse_range<'a>(input: &'a str, ctx: &LineContext) -> Result<(Option<LineRange>, &'a str), String> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((Some((0, ctx.last)), rest));
+    }
+
+    let (start, rest) = parse_address(input, ctx)?;
+    let (end, rest) = match rest.strip_prefix([',', ';']) {
+        Some(after) => {
+            let (end, rest) = parse_address(after, ctx)?;
+            (Some(end.unwrap_or(ctx.current as isize)), rest)
+        }
+        None => (start, rest),
+    };
+    let Some(end) = end else { return Ok((None, rest)) };
+    let start = start.unwrap_or(ctx.current as isize);
+
+    let in_bounds = |line: isize| line >= 0 && line as usize <= ctx.last;
+    if !in_bounds(start) || !in_bounds(end) {
+        return Err(String::from("E16: Invalid range"));
+    }
+    let (start, end) = (start as usize, end as usize);
+    Ok((Some((start.min(end), start.max(end))), rest))
+}
+
+pub fn parse_line_address(input: &str, ctx: &LineContext) -> Result<isize, String> {
+    match parse_address(input.trim(), ctx)? {
+        (Some(line), rest) if rest.trim().is_empty() && (-1..=ctx.last as isize).contains(&line) => Ok(line),
+        _ => Err(String::from("E14: Invalid address")),
+    }
+}
+
+fn valid_delimiter(delim: char) -> bool {
+    !(delim.is_alphanumeric() || delim.is_whitespace() || matches!(delim, '\\' | '"' | '|'))
+}
+
+fn split_delimited(input: &str, delim: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delim {
+            return (part, Some(&input[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delim => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    (part, None)
+}
+
+pub fn parse_substitute(command: &str) -> Option<Result<Substitute, String>> {
+    let name_len = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    let name = &command[..name_len];
+    if name.is_empty() || !"substitute".starts_with(name) {
+        return None;
+    }
+    let rest = &command[name_len..];
+    let delim = rest.chars().next()?;
+    if !valid_delimiter(delim) {
+        return Some(Err(String::from("E146: Regular expressions can't be delimited by letters")));
+    }
+
+    let (pattern, rest) = split_delimited(&rest[delim.len_utf8()..], delim);
+    let (replacement, flags) = match rest {
+        Some(rest) => {
+            let (replacement, flags) = split_delimited(rest, delim);
+            (replacement, flags.unwrap_or(""))
+        }
+        None => (String::new(), ""),
+    };
+
+    let mut sub = Substitute {
+        pattern,
+        replacement,
+        global: false,
+        case_sensitive: true,
+        confirm: false,
+        count_only: false,
+    };
+    for flag in flags.trim().chars() {
+        match flag {
+            'g' => sub.global = !sub.global,
+            'i' => sub.case_sensitive = false,
+            'I' => sub.case_sensitive = true,
+            'c' => sub.confirm = true,
+            'n' => sub.count_only = true,
+            'e' | '&' => {}
+            _ => return Some(Err(format!("E488: Trailing characters: {}", flags.trim()))),
+        }
+    }
+    Some(Ok(sub))
+}
+
+pub fn parse_global(command: &str) -> Option<Result<Global, String>> {
+    let name_len = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    let name = &command[..name_len];
+    let (bang, rest) = match command[name_len..].strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, &command[name_len..]),
+    };
+    let invert = if !name.is_empty() && "global".starts_with(name) {
+        bang
+    } else if !name.is_empty() && "vglobal".starts_with(name) {
+        true
+    } else {
+        return None;
+    };
+
+    let delim = rest.chars().next()?;
+    if !valid_delimiter(delim) {
+        return Some(Err(String::from("E146: Regular expressions can't be delimited by letters")));
+    }
+    let (pattern, command) = split_delimited(&rest[delim.len_utf8()..], delim);
+    Some(Ok(Global {
+        invert,
+        pattern,
+        command: command.unwrap_or("").trim().to_string(),
+    }))
+}
+
+pub fn parse_line_command(command: &str) -> Result<LineCommand, String> {
+    let name_len = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    let (name, args) = command.split_at(name_len);
+    let is = |full: &str, min: usize| name.len() >= min && full.starts_with(name);
+    if command.trim().is_empty() || is("print", 1) && args.trim().is_empty() {
+        Ok(LineCommand::Print)
+    } else if is("delete", 1) {
+        if !args.trim().is_empty() {
+            return Err(format!("E488: Trailing characters: {}", args.trim()));
+        }
+        Ok(LineCommand::Delete)
+    } else if is("move", 1) {
+        Ok(LineCommand::Move(args.trim().to_string()))
+    } else if name == "t" || is("copy", 2) {
+        Ok(LineCommand::Copy(args.trim().to_string()))
+    } else if is("normal", 4) {
+        let keys = args.strip_prefix('!').unwrap_or(args);
+        Ok(LineCommand::Normal(keys.trim_start().to_string()))
+    } else if is("substitute", 1) || command.starts_with('&') {
+        Ok(LineCommand::Substitute(command.to_string()))
+    } else {
+        Err(format!("E492: Not an editor command: {}", command))
+    }
+}
+
+pub fn resolve_tilde(template: &str, previous: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                result.push('\\');
+                if let Some(next) = chars.next() {
+                    result.push(next);
+                }
+            }
+            '~' => result.push_str(previous),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+pub fn expand_vim_replacement(caps: &Captures, template: &str) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => result.push_str(&caps[0]),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let group = d.to_digit(10).unwrap_or(0) as usize;
+                    result.push_str(caps.get(group).map_or("", |m| m.as_str()));
+                }
+                Some('n' | 'r') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(next) => result.push(next),
+                None => result.push('\\'),
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+pub fn plan_substitution(
+    regex: &Regex,
+    text: &str,
+    index: &LineIndex,
+    lines: LineRange,
+    global: bool,
+    template: &str,
+) -> Vec<(Range<usize>, String)> {
+    let start = index.line_start(lines.0);
+    let end = index.line_start(lines.1 + 1);
+    let mut edits = Vec::new();
+    let mut last_line = None;
+    for caps in regex.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        if m.start() < start {
+            continue;
+        }
+        if m.start() >= end {
+            break;
+        }
+        let line = index.line_of(m.start());
+        if !global && last_line == Some(line) {
+            continue;
+        }
+        last_line = Some(line);
+        edits.push((m.range(), expand_vim_replacement(&caps, template)));
+    }
+    edits
+}
+
+pub fn edit_matches(edits: &[(Range<usize>, String)], index: &LineIndex) -> Vec<FindMatch> {
+    edits
+        .iter()
+        .map(|(range, _)| {
+            let (line, col) = index.position(range.start);
+            let (end_line, end_col) = index.position(range.end);
+            FindMatch { line, col, end_line, end_col }
+        })
+        .collect()
+}
+
+pub fn apply_edits<'a>(text: &str, edits: impl Iterator<Item = &'a (Range<usize>, String)>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (range, replacement) in edits {
+        result.push_str(&text[last..range.start]);
+        result.push_str(replacement);
+        last = range.end;
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+pub fn changed_lines(edits: &[(Range<usize>, String)], index: &LineIndex) -> usize {
+    let mut lines: Vec<usize> = edits.iter().map(|(range, _)| index.line_of(range.start)).collect();
+    lines.dedup();
+    lines.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTX: LineContext = LineContext { current: 4, last: 9, visual: Some((2, 5)) };
+
+    #[test]
+    fn ranges() {
+        let cases: &[(&str, Option<LineRange>, &str)] = &[
+            ("", None, ""),
+            ("d", None, "d"),
+            ("%d", Some((0, 9)), "d"),
+            (".", Some((4, 4)), ""),
+            ("$", Some((9, 9)), ""),
+            ("3", Some((2, 2)), ""),
+            ("2,5s/a/b/", Some((1, 4)), "s/a/b/"),
+            ("2;5", Some((1, 4)), ""),
+            (".+2", Some((6, 6)), ""),
+            ("-", Some((3, 3)), ""),
+            ("+3-1", Some((6, 6)), ""),
+            (".,$-1", Some((4, 8)), ""),
+            ("'<,'>normal x", Some((2, 5)), "normal x"),
+            ("5,2", Some((1, 4)), ""),
+            (",3", Some((2, 4)), ""),
+            ("3,", Some((2, 4)), ""),
+        ];
+        for &(input, range, rest) in cases {
+            assert_eq!(parse_range(input, &CTX), Ok((range, rest)), "{input}");
+        }
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let cases = [
+            ("11", "E16: Invalid range"),
+            ("0", "E16: Invalid range"),
+            ("$+1", "E16: Invalid range"),
+            ("'x", "E20: Mark not set"),
+        ];
+        for (input, error) in cases {
+            assert_eq!(parse_range(input, &CTX), Err(String::from(error)), "{input}");
+        }
+        let ctx = LineContext { visual: None, ..CTX };
+        assert_eq!(parse_range("'<,'>", &ctx), Err(String::from("E20: Mark not set")));
+    }
+
+    #[test]
+    fn line_addresses() {
+        assert_eq!(parse_line_address("0", &CTX), Ok(-1));
+        assert_eq!(parse_line_address(" $ ", &CTX), Ok(9));
+        assert_eq!(parse_line_address("'>-1", &CTX), Ok(4));
+        assert_eq!(parse_line_address("12", &CTX), Err(String::from("E14: Invalid address")));
+        assert_eq!(parse_line_address("3x", &CTX), Err(String::from("E14: Invalid address")));
+    }
+
+    #[test]
+    fn substitute_commands() {
+        let sub = |pattern: &str, replacement: &str| Substitute {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            global: false,
+            case_sensitive: true,
+            confirm: false,
+            count_only: false,
+        };
+        let cases = [
+            ("s/a/b/", sub("a", "b")),
+            ("s/a", sub("a", "")),
+            ("s/a/b/gi", Substitute { global: true, case_sensitive: false, ..sub("a", "b") }),
+            ("s/a/b/gg", sub("a", "b")),
+            ("s/a/b/iI", sub("a", "b")),
+            ("s/a/b/cn", Substitute { confirm: true, count_only: true, ..sub("a", "b") }),
+            (r"s/a\/b/c", sub("a/b", "c")),
+            (r"s/\d+/<&>/", sub(r"\d+", "<&>")),
+            (r"substitute#a\#b#c#", sub("a#b", "c")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_substitute(input), Some(Ok(expected)), "{input}");
+        }
+        assert_eq!(parse_substitute("s/a/b/x"), Some(Err(String::from("E488: Trailing characters: x"))));
+        assert!(matches!(parse_substitute("s1a1b1"), Some(Err(e)) if e.starts_with("E146")));
+        assert_eq!(parse_substitute("set ts=8"), None);
+    }
+
+    #[test]
+    fn global_commands() {
+        let global = |invert: bool, command: &str| Global { invert, pattern: String::from("a"), command: command.to_string() };
+        let cases = [("g/a/", global(false, "")), ("g!/a/d", global(true, "d")), ("v/a/ m0", global(true, "m0"))];
+        for (input, expected) in cases {
+            assert_eq!(parse_global(input), Some(Ok(expected)), "{input}");
+        }
+        let commands = [
+            ("", LineCommand::Print),
+            ("p", LineCommand::Print),
+            ("d", LineCommand::Delete),
+            ("m0", LineCommand::Move(String::from("0"))),
+            ("t$", LineCommand::Copy(String::from("$"))),
+            ("s//x/", LineCommand::Substitute(String::from("s//x/"))),
+            ("norm Ax", LineCommand::Normal(String::from("Ax"))),
+        ];
+        for (input, expected) in commands {
+            assert_eq!(parse_line_command(input), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn tilde_is_the_previous_replacement() {
+        let cases = [("a~b", "X", "aXb"), ("~~", "ab", "abab"), (r"\~", "X", r"\~"), ("plain", "X", "plain")];
+        for (template, previous, expected) in cases {
+            assert_eq!(resolve_tilde(template, previous), expected, "{template}");
+        }
+    }
+
+    #[test]
+    fn vim_replacements() {
+        let regex = Regex::new(r"(\w+)=(\w+)").unwrap();
+        let caps = regex.captures("key=val").unwrap();
+        let cases = [
+            ("&", "key=val"),
+            (r"\2=\1", "val=key"),
+            (r"[\0]", "[key=val]"),
+            (r"\&", "&"),
+            (r"\9", ""),
+            (r"a\nb\tc", "a\nb\tc"),
+            ("x\\", "x\\"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(expand_vim_replacement(&caps, template), expected, "{template}");
+        }
+    }
+
+    #[test]
+    fn substitution_plans() {
+        let text = "a a\nb a\na a";
+        let index = LineIndex::new(text);
+        let regex = Regex::new("a").unwrap();
+        let edits = plan_substitution(&regex, text, &index, (1, 2), false, "x");
+        assert_eq!(edits, [(6..7, String::from("x")), (8..9, String::from("x"))]);
+        let edits = plan_substitution(&regex, text, &index, (1, 2), true, "x");
+        assert_eq!(apply_edits(text, edits.iter()), "a a\nb x\nx x");
+        assert_eq!(changed_lines(&edits, &index), 2);
+        let matches = edit_matches(&edits, &index);
+        assert_eq!(matches[2], FindMatch { line: 2, col: 2, end_line: 2, end_col: 3 });
+    }
+}
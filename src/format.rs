@@ -1,59 +1,680 @@
-pub fn format_document(text: &str) -> String {
-    let indent_size = 4;
-    let mut level: usize = 0;
-    text.lines()
+use iced::futures::channel::oneshot;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::syntax::Language;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Keywords {
+    None,
+    Shell,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Continuation {
+    None,
+    Backslash,
+    Operators,
+}
+
+struct Lexicon {
+    line_comments: &'static [&'static str],
+    block_comments: bool,
+    nested_comments: bool,
+    quotes: &'static str,
+    multiline_quotes: &'static str,
+    char_literals: bool,
+    raw_strings: bool,
+    triple_quotes: bool,
+    keywords: Keywords,
+    continuation: Continuation,
+}
+
+const GENERIC: Lexicon = Lexicon {
+    line_comments: &["//", "#"],
+    block_comments: true,
+    nested_comments: false,
+    quotes: "\"",
+    multiline_quotes: "",
+    char_literals: true,
+    raw_strings: false,
+    triple_quotes: false,
+    keywords: Keywords::None,
+    continuation: Continuation::Backslash,
+};
+
+// Recipe lines in Makefiles must start with a tab, so they are never re-indented.
+fn is_makefile(path: &Path) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_ascii_lowercase());
+    matches!(name.as_deref(), Some("makefile" | "gnumakefile")) || path.extension().is_some_and(|ext| ext == "mk")
+}
+
+fn lexicon(language: Option<Language>, path: Option<&Path>) -> Option<Lexicon> {
+    if path.is_some_and(is_makefile) {
+        return None;
+    }
+    Some(match language? {
+        Language::Rust => Lexicon {
+            line_comments: &["//"],
+            nested_comments: true,
+            multiline_quotes: "\"",
+            raw_strings: true,
+            keywords: Keywords::None,
+            continuation: Continuation::Operators,
+            ..GENERIC
+        },
+        Language::JavaScript | Language::TypeScript => Lexicon {
+            line_comments: &["//"],
+            quotes: "\"'`",
+            multiline_quotes: "`",
+            char_literals: false,
+            keywords: Keywords::None,
+            continuation: Continuation::Operators,
+            ..GENERIC
+        },
+        Language::Json => Lexicon {
+            line_comments: &["//"],
+            char_literals: false,
+            keywords: Keywords::None,
+            continuation: Continuation::None,
+            ..GENERIC
+        },
+        Language::Css => Lexicon {
+            line_comments: &[],
+            quotes: "\"'",
+            char_literals: false,
+            keywords: Keywords::None,
+            continuation: Continuation::None,
+            ..GENERIC
+        },
+        Language::Toml => Lexicon {
+            line_comments: &["#"],
+            block_comments: false,
+            quotes: "\"'",
+            char_literals: false,
+            triple_quotes: true,
+            keywords: Keywords::None,
+            continuation: Continuation::None,
+            ..GENERIC
+        },
+        Language::Shell => Lexicon {
+            line_comments: &["#"],
+            block_comments: false,
+            quotes: "\"'",
+            multiline_quotes: "\"'",
+            char_literals: false,
+            keywords: Keywords::Shell,
+            ..GENERIC
+        },
+        Language::Python | Language::Yaml | Language::Markdown | Language::Html => return None,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Quote {
+    Plain(char),
+    Triple(char),
+    Raw(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LexState {
+    Code,
+    Comment(usize),
+    Str(Quote),
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn lex_line(line: &str, mut state: LexState, lexicon: &Lexicon) -> (String, LexState) {
+    let chars: Vec<char> = line.chars().collect();
+    let starts = |i: usize, pattern: &str| pattern.chars().enumerate().all(|(k, p)| chars.get(i + k) == Some(&p));
+    let mut code = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match state {
+            LexState::Comment(depth) => {
+                if starts(i, "*/") {
+                    state = if depth > 1 { LexState::Comment(depth - 1) } else { LexState::Code };
+                    i += 2;
+                } else if lexicon.nested_comments && starts(i, "/*") {
+                    state = LexState::Comment(depth + 1);
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            }
+            LexState::Str(quote) => {
+                let (closing, escapes) = match quote {
+                    Quote::Plain(q) => (q.to_string(), !(q == '\'' && lexicon.keywords == Keywords::Shell)),
+                    Quote::Triple(q) => (q.to_string().repeat(3), q == '"'),
+                    Quote::Raw(hashes) => (format!("\"{}", "#".repeat(hashes)), false),
+                };
+                if escapes && c == '\\' {
+                    i += 2;
+                } else if starts(i, &closing) {
+                    code.push('"');
+                    state = LexState::Code;
+                    i += closing.chars().count();
+                } else {
+                    i += 1;
+                }
+            }
+            LexState::Code => {
+                let comment = lexicon
+                    .line_comments
+                    .iter()
+                    .any(|marker| starts(i, marker) && (*marker != "#" || i == 0 || chars[i - 1].is_whitespace()));
+                if comment {
+                    break;
+                }
+                if lexicon.block_comments && starts(i, "/*") {
+                    code.push(' ');
+                    state = LexState::Comment(1);
+                    i += 2;
+                    continue;
+                }
+                if lexicon.raw_strings && c == 'r' && (i == 0 || !is_ident(chars[i - 1])) {
+                    let hashes = chars[i + 1..].iter().take_while(|&&h| h == '#').count();
+                    if chars.get(i + 1 + hashes) == Some(&'"') {
+                        code.push('"');
+                        state = LexState::Str(Quote::Raw(hashes));
+                        i += hashes + 2;
+                        continue;
+                    }
+                }
+                if lexicon.triple_quotes && (starts(i, "\"\"\"") || starts(i, "'''")) {
+                    code.push('"');
+                    state = LexState::Str(Quote::Triple(c));
+                    i += 3;
+                    continue;
+                }
+                // 'x' and '\n' are characters; any other quote is a lifetime
+                // or an apostrophe.
+                if lexicon.char_literals && c == '\'' {
+                    let end = if chars.get(i + 1) == Some(&'\\') {
+                        chars[i + 2..].iter().position(|&q| q == '\'').map(|p| i + 2 + p)
+                    } else {
+                        Some(i + 2).filter(|&e| chars.get(e) == Some(&'\''))
+                    };
+                    if let Some(end) = end {
+                        code.push_str("''");
+                        i = end + 1;
+                        continue;
+                    }
+                }
+                if lexicon.quotes.contains(c) {
+                    code.push('"');
+                    state = LexState::Str(Quote::Plain(c));
+                } else {
+                    code.push(c);
+                }
+                i += 1;
+            }
+        }
+    }
+    if let LexState::Str(Quote::Plain(q)) = state
+        && !lexicon.multiline_quotes.contains(q)
+    {
+        state = LexState::Code;
+    }
+    (code, state)
+}
+
+fn opener_of(c: char) -> Option<char> {
+    match c {
+        '}' => Some('{'),
+        ']' => Some('['),
+        ')' => Some('('),
+        _ => None,
+    }
+}
+
+struct Open {
+    token: char,
+    level: usize,
+}
+
+const BLOCK: char = 'k';
+
+fn close(stack: &mut Vec<Open>, token: char) -> Option<usize> {
+    let at = stack.iter().rposition(|open| open.token == token)?;
+    let level = stack[at].level;
+    stack.truncate(at);
+    Some(level)
+}
+
+fn words(code: &str) -> Vec<&str> {
+    code.split(|c: char| !is_ident(c)).filter(|w| !w.is_empty()).collect()
+}
+
+fn keyword_closer(keywords: Keywords, word: &str) -> bool {
+    match keywords {
+        Keywords::None => false,
+        Keywords::Shell => matches!(word, "fi" | "done" | "esac"),
+    }
+}
+
+fn keyword_middle(keywords: Keywords, word: &str) -> bool {
+    match keywords {
+        Keywords::None => false,
+        Keywords::Shell => matches!(word, "else" | "elif"),
+    }
+}
+
+fn keyword_opener(keywords: Keywords, words: &[&str]) -> bool {
+    let first = words.first().copied().unwrap_or("");
+    let last = words.last().copied().unwrap_or("");
+    match keywords {
+        Keywords::None => false,
+        Keywords::Shell => matches!(last, "then" | "do") || (first == "case" && last == "in"),
+    }
+}
+
+fn continues(code: &str, continuation: Continuation) -> bool {
+    match continuation {
+        Continuation::None => false,
+        Continuation::Backslash => code.ends_with('\\'),
+        Continuation::Operators => {
+            code.ends_with('\\')
+                || (["&&", "||", "+", "-", "*", "/", "%", "=", "."].iter().any(|op| code.ends_with(op))
+                    && !code.ends_with("++")
+                    && !code.ends_with("--")
+                    && !code.ends_with(".."))
+        }
+    }
+}
+
+fn continues_previous(code: &str, continuation: Continuation) -> bool {
+    continuation == Continuation::Operators
+        && ((code.starts_with('.') && !code.starts_with("..")) || ["?", "&&", "||"].iter().any(|op| code.starts_with(op)))
+}
+
+// Only languages with a known lexicon are re-indented; everything else is left alone.
+pub fn format_document(text: &str, unit: &str, language: Option<Language>, path: Option<&Path>) -> Option<String> {
+    let lexicon = lexicon(language, path)?;
+    let mut state = LexState::Code;
+    let codes: Vec<(LexState, String)> = text
+        .lines()
         .map(|line| {
+            let start = state;
+            let (code, end) = lex_line(line, state, &lexicon);
+            state = end;
+            (start, code)
+        })
+        .collect();
+    let mut stack: Vec<Open> = Vec::new();
+    let mut continued = false;
+    let formatted = text
+        .lines()
+        .zip(codes)
+        .map(|(line, (start, code))| {
             let trimmed = line.trim();
-            if trimmed.is_empty() {
-                return String::new();
-            }
-            let first_word = trimmed.split_whitespace().next().unwrap_or("");
-            let closes_brace = trimmed.starts_with('}')
-                || trimmed.starts_with(']')
-                || trimmed.starts_with(')');
-            let closes_keyword = matches!(
-                first_word,
-                "end" | "end;" | "end," | "endif"
-                    | "endfor" | "endwhile" | "endfunction"
-                    | "else" | "elseif" | "elif"
-                    | "elsif" | "except" | "catch"
-                    | "finally" | "when" | "rescue"
-            );
-            if closes_brace || closes_keyword {
-                level = level.saturating_sub(1);
-            }
-            let result = format!("{}{}", " ".repeat(level * indent_size), trimmed);
-            for ch in trimmed.chars() {
-                match ch {
-                    '{' | '[' | '(' => level += 1,
-                    '}' | ']' | ')' => level = level.saturating_sub(1),
-                    _ => {}
-                }
-            }
-            let opens_keyword = matches!(
-                first_word,
-                "if" | "else" | "elseif" | "elif"
-                    | "elsif" | "for" | "while" | "do"
-                    | "loop" | "begin" | "case" | "switch"
-                    | "try" | "catch" | "except" | "finally"
-                    | "def" | "class" | "module" | "when"
-                    | "rescue" | "unless" | "until"
-            );
-            let ends_with_opener = trimmed.ends_with("then")
-                || trimmed.ends_with("do")
-                || trimmed.ends_with("repeat");
-            let has_function = trimmed.contains("function")
-                && !trimmed.starts_with("end");
-            if opens_keyword || ends_with_opener || has_function {
-                if !trimmed.contains("end")
-                    || trimmed.contains("function")
-                    || closes_keyword
-                {
-                    level += 1;
-                }
-            }
-            result
+            match start {
+                LexState::Str(_) => return line.to_string(),
+                _ if trimmed.is_empty() => return String::new(),
+                LexState::Comment(_) => {
+                    let level = stack.last().map_or(0, |open| open.level + 1) + continued as usize;
+                    let pad = if trimmed.starts_with('*') { " " } else { "" };
+                    return format!("{}{}{}", unit.repeat(level), pad, trimmed);
+                }
+                LexState::Code => {}
+            }
+
+            let code = code.trim();
+            let mut rest = code;
+            let mut level = None;
+            while let Some(opener) = rest.chars().next().and_then(opener_of) {
+                level = level.or(close(&mut stack, opener));
+                rest = rest[1..].trim_start();
+            }
+            let words = words(rest);
+            let first = words.first().copied().unwrap_or("");
+            let mut reopen = false;
+            if stack.last().is_some_and(|open| open.token == BLOCK) {
+                if keyword_closer(lexicon.keywords, first) {
+                    level = level.or(close(&mut stack, BLOCK));
+                } else if keyword_middle(lexicon.keywords, first) {
+                    level = level.or(close(&mut stack, BLOCK));
+                    reopen = true;
+                }
+            }
+            let level = level.unwrap_or_else(|| {
+                let base = stack.last().map_or(0, |open| open.level + 1);
+                base + (continued || continues_previous(rest, lexicon.continuation)) as usize
+            });
+
+            let depth = stack.len();
+            for c in rest.chars() {
+                if matches!(c, '{' | '[' | '(') {
+                    stack.push(Open { token: c, level });
+                } else if let Some(opener) = opener_of(c) {
+                    close(&mut stack, opener);
+                }
+            }
+            let one_liner = words.iter().skip(1).any(|w| keyword_closer(lexicon.keywords, w));
+            if reopen || (stack.len() <= depth && !one_liner && keyword_opener(lexicon.keywords, &words)) {
+                stack.push(Open { token: BLOCK, level });
+            }
+            continued = continues(code, lexicon.continuation);
+            format!("{}{}", unit.repeat(level), trimmed)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(formatted)
+}
+
+pub fn strip_trailing_whitespace(text: &str) -> String {
+    text.split('\n')
+        .map(|line| match line.strip_suffix('\r') {
+            Some(line) => format!("{}\r", line.trim_end_matches([' ', '\t'])),
+            None => line.trim_end_matches([' ', '\t']).to_string(),
         })
         .collect::<Vec<_>>()
         .join("\n")
 }
+
+#[derive(Debug, Clone)]
+pub enum FormatOutcome {
+    Formatted(String),
+    Failed(String),
+    Missing(String),
+}
+
+pub fn default_formatters() -> BTreeMap<String, String> {
+    let prettier = "prettier --stdin-filepath {file}";
+    [
+        ("rs", "rustfmt --edition 2024"),
+        ("py", "black --quiet -"),
+        ("go", "gofmt"),
+        ("json", "jq ."),
+        ("js", prettier),
+        ("jsx", prettier),
+        ("ts", prettier),
+        ("tsx", prettier),
+        ("css", prettier),
+        ("html", prettier),
+        ("md", prettier),
+        ("yaml", prettier),
+        ("yml", prettier),
+    ]
+    .into_iter()
+    .map(|(ext, command)| (ext.to_string(), command.to_string()))
+    .collect()
+}
+
+const FORMAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn format_in_background(command: String, text: String, file: PathBuf, cancel: Arc<AtomicBool>) -> FormatOutcome {
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(run_formatter(&command, &text, &file, &cancel));
+    });
+    rx.await.unwrap_or_else(|_| FormatOutcome::Failed(String::from("formatter stopped unexpectedly")))
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+// `{file}` in `command` is replaced with the file's path.
+pub fn run_formatter(command: &str, text: &str, file: &Path, cancel: &AtomicBool) -> FormatOutcome {
+    let mut args = command.split_whitespace().map(|arg| arg.replace("{file}", &file.to_string_lossy()));
+    let Some(program) = args.next() else { return FormatOutcome::Missing(String::new()) };
+    let child = Command::new(&program)
+        .args(args)
+        .current_dir(file.parent().filter(|d| d.is_dir()).unwrap_or(Path::new(".")))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return FormatOutcome::Missing(program),
+        Err(e) => return FormatOutcome::Failed(format!("{}: {}", program, e)),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = text.to_string();
+        std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    }
+    let (stdout, stderr) = (drain(child.stdout.take()), drain(child.stderr.take()));
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(e) => return FormatOutcome::Failed(format!("{}: {}", program, e)),
+        }
+        let reason = if cancel.load(Ordering::Relaxed) {
+            String::from("cancelled")
+        } else if started.elapsed() > FORMAT_TIMEOUT {
+            format!("timed out after {}s", FORMAT_TIMEOUT.as_secs())
+        } else {
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        return FormatOutcome::Failed(format!("{}: {}", program, reason));
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    if status.success() {
+        return match String::from_utf8(stdout) {
+            Ok(formatted) => FormatOutcome::Formatted(formatted),
+            Err(_) => FormatOutcome::Failed(format!("{}: output is not valid UTF-8", program)),
+        };
+    }
+    let stderr = stderr.join().unwrap_or_default();
+    let message = String::from_utf8_lossy(&stderr)
+        .lines()
+        .chain(String::from_utf8_lossy(&stdout).lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map_or_else(|| status.to_string(), str::to_string);
+    FormatOutcome::Failed(format!("{}: {}", program, message))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+const DIFF_LIMIT: usize = 4_000_000;
+
+pub fn line_hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let a: Vec<&str> = old.split('\n').collect();
+    let b: Vec<&str> = new.split('\n').collect();
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if a_mid.is_empty() && b_mid.is_empty() {
+        return Vec::new();
+    }
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n == 0 || m == 0 || n * m > DIFF_LIMIT {
+        return vec![Hunk { old: prefix..prefix + n, new: prefix..prefix + m }];
+    }
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * (m + 1) + j] = if a_mid[i] == b_mid[j] {
+                lcs[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+            };
+        }
+    }
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a_mid[i] == b_mid[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_i, start_j) = (i, j);
+        while (i < n || j < m) && !(i < n && j < m && a_mid[i] == b_mid[j]) {
+            if j == m || (i < n && lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        hunks.push(Hunk { old: prefix + start_i..prefix + i, new: prefix + start_j..prefix + j });
+    }
+    hunks
+}
+
+// One-for-one hunks are clipped to `lines`; any other hunk reaching outside
+// them is dropped so nothing beyond the range changes.
+pub fn restrict_hunks(hunks: Vec<Hunk>, lines: &Range<usize>) -> Vec<Hunk> {
+    hunks
+        .into_iter()
+        .filter_map(|hunk| {
+            if hunk.old.len() == hunk.new.len() {
+                let start = hunk.old.start.max(lines.start);
+                let end = hunk.old.end.min(lines.end);
+                if start >= end {
+                    return None;
+                }
+                let new = hunk.new.start + (start - hunk.old.start);
+                return Some(Hunk { old: start..end, new: new..new + (end - start) });
+            }
+            let inside = hunk.old.start >= lines.start && hunk.old.start < lines.end && hunk.old.end <= lines.end;
+            inside.then_some(hunk)
+        })
+        .collect()
+}
+
+pub fn map_line(hunks: &[Hunk], old: &str, new: &str, line: usize) -> (usize, isize) {
+    let indent = |text: &str, line: usize| {
+        text.split('\n').nth(line).map_or(0, |l| l.len() - l.trim_start().len()) as isize
+    };
+    let mut delta = 0isize;
+    for hunk in hunks {
+        if line < hunk.old.start {
+            break;
+        }
+        if line < hunk.old.end {
+            let offset = (line - hunk.old.start).min(hunk.new.len().saturating_sub(1));
+            let shift = if hunk.new.is_empty() { 0 } else { indent(new, hunk.new.start + offset) - indent(old, line) };
+            return ((hunk.old.start + offset).saturating_add_signed(delta), shift);
+        }
+        delta += hunk.new.len() as isize - hunk.old.len() as isize;
+    }
+    (line.saturating_add_signed(delta), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats(language: Option<Language>, input: &str, expected: &str) {
+        assert_eq!(format_document(input, "    ", language, None).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments_are_ignored() {
+        assert_formats(
+            Some(Language::Rust),
+            "fn main() {\nlet s = \"{\";\nlet c = '}';\n// {\n/* } */\ncall();\n}",
+            "fn main() {\n    let s = \"{\";\n    let c = '}';\n    // {\n    /* } */\n    call();\n}",
+        );
+    }
+
+    #[test]
+    fn lifetimes_are_not_char_literals() {
+        assert_formats(
+            Some(Language::Rust),
+            "fn f<'a>(x: &'a str) -> char {\nif x.is_empty() {\nreturn '{';\n}\n'x'\n}",
+            "fn f<'a>(x: &'a str) -> char {\n    if x.is_empty() {\n        return '{';\n    }\n    'x'\n}",
+        );
+    }
+
+    #[test]
+    fn raw_strings_and_nested_comments_are_skipped() {
+        assert_formats(
+            Some(Language::Rust),
+            "fn f() {\nlet s = r#\"{ \"}\" \"#;\n/* a /* b } */ c { */\ng();\n}",
+            "fn f() {\n    let s = r#\"{ \"}\" \"#;\n    /* a /* b } */ c { */\n    g();\n}",
+        );
+    }
+
+    #[test]
+    fn shell_keyword_blocks() {
+        assert_formats(
+            Some(Language::Shell),
+            "if true; then\necho hi\nfi\ncase \"$x\" in\na) echo a ;;\nesac",
+            "if true; then\n    echo hi\nfi\ncase \"$x\" in\n    a) echo a ;;\nesac",
+        );
+    }
+
+    #[test]
+    fn closers_line_up_with_their_opener() {
+        assert_formats(
+            Some(Language::JavaScript),
+            "if (a) {\nb();\n} else {\nc();\n}\nfoo(function () {\nbar();\n});",
+            "if (a) {\n    b();\n} else {\n    c();\n}\nfoo(function () {\n    bar();\n});",
+        );
+    }
+
+    #[test]
+    fn continuation_lines_are_indented() {
+        assert_formats(
+            Some(Language::Rust),
+            "fn f() {\nlet x = a +\nb;\nlet y = z\n.map(g)\n.collect();\n}",
+            "fn f() {\n    let x = a +\n        b;\n    let y = z\n        .map(g)\n        .collect();\n}",
+        );
+    }
+
+    #[test]
+    fn indentation_sensitive_and_unknown_files_are_left_alone() {
+        let python = "def f():\n  x = {\n      1\n  }\n  return x";
+        assert_eq!(format_document(python, "    ", Some(Language::Python), None), None);
+        let yaml = "a:\n  - b: {\n  }\n  - c";
+        assert_eq!(format_document(yaml, "    ", Some(Language::Yaml), None), None);
+        let make = "all: {a,b}.o\n\tcc -o all a.o b.o";
+        assert_eq!(format_document(make, "    ", None, Some(Path::new("src/Makefile"))), None);
+        assert_eq!(format_document(make, "    ", None, Some(Path::new("GNUmakefile"))), None);
+        assert_eq!(format_document(make, "    ", Some(Language::Shell), Some(Path::new("rules.mk"))), None);
+        assert_eq!(format_document(make, "    ", None, None), None);
+    }
+
+    #[test]
+    fn restricted_hunks_stay_inside_the_range() {
+        let hunk = |old: Range<usize>, new: Range<usize>| Hunk { old, new };
+        let hunks = vec![hunk(0..4, 0..4), hunk(5..8, 5..6), hunk(9..10, 7..9), hunk(12..12, 11..12)];
+        assert_eq!(restrict_hunks(hunks.clone(), &(2..7)), vec![hunk(2..4, 2..4)]);
+        assert_eq!(restrict_hunks(hunks.clone(), &(5..11)), vec![hunk(5..8, 5..6), hunk(9..10, 7..9)]);
+        assert_eq!(restrict_hunks(hunks, &(11..13)), vec![hunk(12..12, 11..12)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn formatter_can_be_cancelled() {
+        let file = Path::new("untitled.txt");
+        let outcome = run_formatter("cat", "text\n", file, &AtomicBool::new(false));
+        assert!(matches!(outcome, FormatOutcome::Formatted(ref text) if text == "text\n"));
+        let started = Instant::now();
+        let outcome = run_formatter("sleep 5", "", file, &AtomicBool::new(true));
+        assert!(matches!(outcome, FormatOutcome::Failed(ref error) if error == "sleep: cancelled"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,14 +1,27 @@
-use iced::Color;
+use iced::{Color, Font, font};
 use iced::advanced::text::highlighter::{self, Highlighter};
 use std::ops::Range;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq)]
+use crate::brackets::{BracketLine, BracketMap};
+use crate::search::{FindMatch, char_boundary};
+use crate::syntax::{SyntaxHighlighter, SyntaxSettings};
+use crate::theme::EditorTheme;
+
+#[derive(Debug, Clone)]
 pub struct FindHighlightSettings {
-    pub matches: Vec<(usize, usize)>,
-    pub query_len: usize,
+    pub matches: Arc<Vec<FindMatch>>,
     pub current_match: Option<usize>,
 }
 
+impl PartialEq for FindHighlightSettings {
+    fn eq(&self, other: &Self) -> bool {
+        let same_matches = Arc::ptr_eq(&self.matches, &other.matches)
+            || (self.matches.is_empty() && other.matches.is_empty());
+        same_matches && self.current_match == other.current_match
+    }
+}
+
 pub struct FindHighlighter {
     settings: FindHighlightSettings,
     current_line: usize,
@@ -41,40 +54,168 @@ impl Highlighter for FindHighlighter {
         self.current_line = line;
     }
 
-    fn highlight_line(&mut self, _line: &str) -> Self::Iterator<'_> {
+    fn highlight_line(&mut self, text: &str) -> Self::Iterator<'_> {
         let line = self.current_line;
         self.current_line += 1;
 
+        let matches = &self.settings.matches;
+        let first = matches.partition_point(|m| m.end_line < line);
         let mut spans = Vec::new();
-        if self.settings.query_len == 0 {
-            return spans.into_iter();
+        for (i, m) in matches.iter().enumerate().skip(first) {
+            if m.line > line {
+                break;
+            }
+            let start = if m.line == line { char_boundary(text, m.col) } else { 0 };
+            let end = if m.end_line == line { char_boundary(text, m.end_col) } else { text.len() };
+            let is_current = self.settings.current_match == Some(i);
+            spans.push((start..end, FindHighlight { is_current }));
         }
 
-        for (i, &(match_line, col)) in self.settings.matches.iter().enumerate() {
-            if match_line == line {
-                let is_current = self.settings.current_match == Some(i);
-                spans.push((col..col + self.settings.query_len, FindHighlight { is_current }));
+        spans.into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BracketSettings {
+    pub brackets: BracketMap,
+    pub rainbow: bool,
+    pub unmatched: bool,
+}
+
+impl PartialEq for BracketSettings {
+    fn eq(&self, other: &Self) -> bool {
+        self.brackets.ptr_eq(&other.brackets) && self.rainbow == other.rainbow && self.unmatched == other.unmatched
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EditorHighlightSettings {
+    pub syntax: SyntaxSettings,
+    pub brackets: BracketSettings,
+    pub find: FindHighlightSettings,
+    pub word: FindHighlightSettings,
+    pub theme: Arc<EditorTheme>,
+}
+
+impl PartialEq for EditorHighlightSettings {
+    fn eq(&self, other: &Self) -> bool {
+        self.syntax == other.syntax
+            && self.brackets == other.brackets
+            && self.find == other.find
+            && self.word == other.word
+            && Arc::ptr_eq(&self.theme, &other.theme)
+    }
+}
+
+pub struct EditorHighlighter {
+    syntax: SyntaxHighlighter,
+    brackets: BracketSettings,
+    find: FindHighlighter,
+    word: FindHighlighter,
+    theme: Arc<EditorTheme>,
+}
+
+type Format = highlighter::Format<Font>;
+
+fn colored(color: Color) -> Format {
+    Format { color: Some(color), font: None }
+}
+
+impl Highlighter for EditorHighlighter {
+    type Settings = EditorHighlightSettings;
+    type Highlight = Format;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Format)>;
+
+    fn new(settings: &Self::Settings) -> Self {
+        Self {
+            syntax: SyntaxHighlighter::new(&settings.syntax),
+            brackets: settings.brackets.clone(),
+            find: FindHighlighter::new(&settings.find),
+            word: FindHighlighter::new(&settings.word),
+            theme: settings.theme.clone(),
+        }
+    }
+
+    fn update(&mut self, new_settings: &Self::Settings) {
+        self.syntax.update(&new_settings.syntax);
+        if self.brackets != new_settings.brackets {
+            let (old, new) = (&self.brackets, &new_settings.brackets);
+            let line = if old.rainbow == new.rainbow && old.unmatched == new.unmatched {
+                old.brackets.first_difference_by(&new.brackets, BracketLine::same_marks)
+            } else {
+                Some(0)
+            };
+            self.brackets = new_settings.brackets.clone();
+            if let Some(line) = line {
+                self.syntax.rewind(line);
+            }
+        }
+        if self.find.settings != new_settings.find || !Arc::ptr_eq(&self.theme, &new_settings.theme) {
+            self.find.update(&new_settings.find);
+            self.word.update(&new_settings.word);
+            self.theme = new_settings.theme.clone();
+            self.syntax.rewind(0);
+        } else if self.word.settings != new_settings.word {
+            let line = first_changed_line(&self.word.settings.matches, &new_settings.word.matches);
+            self.word.update(&new_settings.word);
+            if let Some(line) = line {
+                self.syntax.rewind(line);
             }
         }
+    }
 
+    fn change_line(&mut self, line: usize) {
+        self.syntax.change_line(line);
+    }
+
+    fn highlight_line(&mut self, text: &str) -> Self::Iterator<'_> {
+        let line = self.syntax.current_line();
+        self.find.change_line(line);
+        self.word.change_line(line);
+        let theme = self.theme.clone();
+        let mut spans: Vec<_> = self
+            .syntax
+            .highlight_line(text)
+            .map(|(range, token)| (range, colored(theme.token(token))))
+            .collect();
+        let brackets = self.brackets.brackets.get(line).map_or(&[][..], |row| row.brackets.as_slice());
+        for bracket in brackets.iter().filter(|b| b.col < text.len()) {
+            let color = match bracket.is_matched() {
+                false if self.brackets.unmatched => theme.unmatched,
+                false => continue,
+                true if self.brackets.rainbow => theme.rainbow[bracket.depth % theme.rainbow.len()],
+                true => continue,
+            };
+            spans.push((bracket.col..bracket.col + 1, colored(color)));
+        }
+        let bold = Font { weight: font::Weight::Bold, ..Font::MONOSPACE };
+        spans.extend(
+            self.word
+                .highlight_line(text)
+                .map(|(range, _)| (range, Format { color: Some(theme.word_highlight), font: Some(bold) })),
+        );
+        spans.extend(self.find.highlight_line(text).map(|(range, find)| {
+            (range, colored(if find.is_current { theme.find_current } else { theme.find_match }))
+        }));
         spans.into_iter()
     }
 
     fn current_line(&self) -> usize {
-        self.current_line
+        self.syntax.current_line()
     }
 }
 
-pub fn format_highlight(
-    highlight: &FindHighlight,
-    _theme: &iced::Theme,
-) -> highlighter::Format<iced::Font> {
-    highlighter::Format {
-        color: Some(if highlight.is_current {
-            Color::from_rgb(1.0, 0.6, 0.0)
-        } else {
-            Color::from_rgb(1.0, 0.9, 0.2)
-        }),
-        font: None,
+fn first_changed_line(old: &[FindMatch], new: &[FindMatch]) -> Option<usize> {
+    match old.iter().zip(new).position(|(a, b)| a != b) {
+        Some(i) => Some(old[i].line.min(new[i].line)),
+        None => old.get(new.len()).or(new.get(old.len())).map(|m| m.line),
     }
 }
+
+pub fn format_highlight(format: &Format, _theme: &iced::Theme) -> Format {
+    *format
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryKind {
+    Find,
+    Replace,
+    Command,
+    Search,
+}
+
+struct Browse {
+    kind: HistoryKind,
+    index: usize,
+    prefix: String,
+    shown: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    find: Vec<String>,
+    replace: Vec<String>,
+    command: Vec<String>,
+    search: Vec<String>,
+    #[serde(skip)]
+    browse: Option<Browse>,
+}
+
+impl History {
+    fn entries(&self, kind: HistoryKind) -> &Vec<String> {
+        match kind {
+            HistoryKind::Find => &self.find,
+            HistoryKind::Replace => &self.replace,
+            HistoryKind::Command => &self.command,
+            HistoryKind::Search => &self.search,
+        }
+    }
+
+    fn entries_mut(&mut self, kind: HistoryKind) -> &mut Vec<String> {
+        match kind {
+            HistoryKind::Find => &mut self.find,
+            HistoryKind::Replace => &mut self.replace,
+            HistoryKind::Command => &mut self.command,
+            HistoryKind::Search => &mut self.search,
+        }
+    }
+
+    pub fn push(&mut self, kind: HistoryKind, entry: &str) {
+        self.browse = None;
+        if entry.is_empty() {
+            return;
+        }
+        let entries = self.entries_mut(kind);
+        entries.retain(|e| e != entry);
+        entries.push(entry.to_string());
+        if entries.len() > MAX_ENTRIES {
+            entries.remove(0);
+        }
+    }
+
+    pub fn step(&mut self, kind: HistoryKind, delta: i32, current: &str) -> Option<String> {
+        let entries = self.entries(kind);
+        let (index, prefix) = match &self.browse {
+            Some(b) if b.kind == kind && b.shown == current => (b.index, b.prefix.clone()),
+            _ => (entries.len(), current.to_string()),
+        };
+        let matches = |e: &String| e.starts_with(&prefix) && e != current;
+        let found = if delta < 0 {
+            entries[..index].iter().rposition(matches)
+        } else {
+            entries.iter().enumerate().skip(index + 1).find(|(_, e)| matches(e)).map(|(i, _)| i)
+        };
+        let (index, value) = match found {
+            Some(i) => (i, entries[i].clone()),
+            None if delta > 0 && index < entries.len() => (entries.len(), prefix.clone()),
+            None => return None,
+        };
+        self.browse = Some(Browse { kind, index, prefix, shown: value.clone() });
+        Some(value)
+    }
+}
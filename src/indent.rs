@@ -0,0 +1,277 @@
+use iced::widget::text_editor;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::app::App;
+use crate::cursor_editor::TAB_WIDTH;
+
+// Tabs always render TAB_WIDTH columns wide; the editor widget has no tab width setting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Indent {
+    pub shiftwidth: usize,
+    pub expandtab: bool,
+    pub softtabstop: usize,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self { shiftwidth: 4, expandtab: true, softtabstop: 4 }
+    }
+}
+
+impl Indent {
+    pub fn width(&self, whitespace: &str) -> usize {
+        whitespace.chars().fold(0, |col, c| if c == '\t' { (col / TAB_WIDTH + 1) * TAB_WIDTH } else { col + 1 })
+    }
+
+    pub fn fill(&self, columns: usize) -> String {
+        if self.expandtab {
+            return " ".repeat(columns);
+        }
+        "\t".repeat(columns / TAB_WIDTH) + &" ".repeat(columns % TAB_WIDTH)
+    }
+
+    pub fn unit(&self) -> String {
+        self.fill(self.shiftwidth.max(1))
+    }
+
+    pub fn set_width(&mut self, width: usize) {
+        self.shiftwidth = width;
+        if self.softtabstop > 0 {
+            self.softtabstop = width;
+        }
+    }
+
+    fn tab_stop(&self, col: usize) -> usize {
+        let step = if self.softtabstop > 0 { self.softtabstop } else { TAB_WIDTH };
+        (col / step + 1) * step
+    }
+
+    pub fn tab(&self, col: usize) -> String {
+        let target = self.tab_stop(col);
+        if !self.expandtab && target.is_multiple_of(TAB_WIDTH) {
+            String::from("\t")
+        } else {
+            " ".repeat(target - col)
+        }
+    }
+
+    pub fn shift(&self, line: &str, levels: isize) -> Option<String> {
+        let rest = line.trim_start_matches([' ', '\t']);
+        if rest.is_empty() {
+            return None;
+        }
+        let width = self.width(&line[..line.len() - rest.len()]) as isize;
+        let step = self.shiftwidth.max(1) as isize;
+        Some(self.fill((width + levels * step).max(0) as usize))
+    }
+
+    // Applies one `:set` option such as `sw=2` or `noexpandtab`.
+    pub fn set_option(&mut self, option: &str) -> Result<(), String> {
+        match option {
+            "et" | "expandtab" => self.expandtab = true,
+            "noet" | "noexpandtab" => self.expandtab = false,
+            _ => {
+                let (name, value) = option.split_once('=').ok_or_else(|| format!("E518: Unknown option: {}", option))?;
+                let value: usize = value.parse().map_err(|_| format!("E521: Number required after =: {}", option))?;
+                match name {
+                    "ts" | "tabstop" | "sw" | "shiftwidth" if value == 0 => {
+                        return Err(format!("E487: Argument must be positive: {}", option));
+                    }
+                    "ts" | "tabstop" if value != TAB_WIDTH => {
+                        return Err(format!("E474: Tabs are always {} columns wide: {}", TAB_WIDTH, option));
+                    }
+                    "ts" | "tabstop" => {}
+                    "sw" | "shiftwidth" => self.shiftwidth = value,
+                    "sts" | "softtabstop" => self.softtabstop = value,
+                    _ => return Err(format!("E518: Unknown option: {}", name)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn label(&self) -> String {
+        if self.expandtab {
+            format!("Spaces: {}", self.shiftwidth)
+        } else {
+            format!("Tab Size: {}", TAB_WIDTH)
+        }
+    }
+}
+
+// Whether `text` indents with tabs, and otherwise its most common indent step.
+pub fn detect(text: &str) -> Option<(bool, usize)> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps = [0usize; 9];
+    let mut previous = 0;
+    for line in text.lines() {
+        let rest = line.trim_start_matches([' ', '\t']);
+        if rest.is_empty() || rest.starts_with('*') {
+            continue;
+        }
+        let lead = &line[..line.len() - rest.len()];
+        if lead.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        if lead.contains('\t') {
+            continue;
+        }
+        if !lead.is_empty() {
+            spaces += 1;
+        }
+        if lead.len() > previous && lead.len() - previous < steps.len() {
+            steps[lead.len() - previous] += 1;
+        }
+        previous = lead.len();
+    }
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs > spaces {
+        return Some((true, 0));
+    }
+    let width = (2..steps.len()).rev().max_by_key(|&w| steps[w]).filter(|&w| steps[w] > 0).unwrap_or(1);
+    Some((false, width))
+}
+
+impl App {
+    pub fn detect_indent(&mut self) {
+        self.indent = self.indent_defaults;
+        match detect(&self.content.text()) {
+            Some((true, _)) => {
+                self.indent.expandtab = false;
+                self.indent.shiftwidth = TAB_WIDTH;
+                self.indent.softtabstop = 0;
+            }
+            Some((false, width)) => {
+                self.indent.expandtab = true;
+                self.indent.shiftwidth = width;
+                self.indent.softtabstop = width;
+            }
+            None => {}
+        }
+    }
+
+    pub fn soft_backspace(&self) -> usize {
+        let cursor = self.content.cursor();
+        if cursor.selection.is_some() || self.indent.softtabstop == 0 {
+            return 1;
+        }
+        let Some(line) = self.content.line(cursor.position.line) else { return 1 };
+        let before = &line.text[..cursor.position.column.min(line.text.len())];
+        if before.is_empty() || !before.trim_start_matches([' ', '\t']).is_empty() {
+            return 1;
+        }
+        let col = self.indent.width(before);
+        let target = (col - 1) / self.indent.softtabstop * self.indent.softtabstop;
+        let spaces = before.len() - before.trim_end_matches(' ').len();
+        spaces.min(col - target).max(1)
+    }
+
+    pub fn shift_lines(&mut self, lines: Range<usize>, levels: isize) -> bool {
+        let mut changed = false;
+        for line in lines {
+            let Some(text) = self.content.line(line).map(|l| l.text.into_owned()) else { break };
+            let Some(indent) = self.indent.shift(&text, levels) else { continue };
+            let lead = text.len() - text.trim_start_matches([' ', '\t']).len();
+            if text[..lead] == indent {
+                continue;
+            }
+            self.content.move_to(text_editor::Cursor {
+                position: text_editor::Position { line, column: lead },
+                selection: Some(text_editor::Position { line, column: 0 }),
+            });
+            let edit = if indent.is_empty() { text_editor::Edit::Delete } else { text_editor::Edit::Paste(Arc::new(indent)) };
+            self.content.perform(text_editor::Action::Edit(edit));
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn shift_selection(&mut self, levels: isize) -> bool {
+        let cursor = self.content.cursor();
+        let (start, end) = match cursor.selection {
+            Some(selection) => (selection.line.min(cursor.position.line), selection.line.max(cursor.position.line)),
+            None => (cursor.position.line, cursor.position.line),
+        };
+        let line_len = |app: &Self, line: usize| app.content.line(line).map_or(0, |l| l.text.len());
+        let before = line_len(self, end);
+        let changed = self.shift_lines(start..end + 1, levels);
+        let after = line_len(self, end);
+        let position = match cursor.selection {
+            Some(_) => text_editor::Position { line: end, column: after },
+            None => {
+                let column = (cursor.position.column + after).saturating_sub(before);
+                text_editor::Position { line: end, column: column.min(after) }
+            }
+        };
+        self.content.move_to(text_editor::Cursor {
+            position,
+            selection: cursor.selection.map(|_| text_editor::Position { line: start, column: 0 }),
+        });
+        changed
+    }
+
+    pub fn tab_text(&self) -> String {
+        let cursor = self.content.cursor();
+        let col = self
+            .content
+            .line(cursor.position.line)
+            .map_or(0, |l| self.indent.width(&l.text[..cursor.position.column.min(l.text.len())]));
+        self.indent.tab(col)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_tabs_spaces_and_mixed_files() {
+        assert_eq!(detect("fn f() {\n\tif x {\n\t\ty();\n\t}\n}"), Some((true, 0)));
+        assert_eq!(detect("a:\n  b:\n    c: 1\n  d: 2"), Some((false, 2)));
+        assert_eq!(detect("fn f() {\n    if x {\n        y();\n    }\n}"), Some((false, 4)));
+        assert_eq!(detect("/*\n * doc\n */\nfn f() {\n    x();\n}"), Some((false, 4)));
+        assert_eq!(detect("a {\n\tb;\n\tc;\n    d;\n}"), Some((true, 0)));
+        assert_eq!(detect("a {\n  b {\n    c;\n  }\n\td;\n}"), Some((false, 2)));
+        assert_eq!(detect("a\nb\n\n"), None);
+    }
+
+    #[test]
+    fn tab_inserts_up_to_the_next_stop() {
+        let spaces = Indent { shiftwidth: 4, expandtab: true, softtabstop: 4 };
+        assert_eq!(spaces.tab(0), "    ");
+        assert_eq!(spaces.tab(6), "  ");
+        let tabs = Indent { shiftwidth: TAB_WIDTH, expandtab: false, softtabstop: 0 };
+        assert_eq!(tabs.tab(0), "\t");
+        assert_eq!(tabs.tab(3), "\t");
+        let mixed = Indent { shiftwidth: 4, expandtab: false, softtabstop: 4 };
+        assert_eq!(mixed.tab(0), "    ");
+        assert_eq!(mixed.tab(4), "\t");
+    }
+
+    #[test]
+    fn shift_rebuilds_the_indent() {
+        let spaces = Indent { shiftwidth: 4, expandtab: true, softtabstop: 4 };
+        assert_eq!(spaces.shift("  x", 1).as_deref(), Some("      "));
+        assert_eq!(spaces.shift("\tx", -1).as_deref(), Some("    "));
+        assert_eq!(spaces.shift("  x", -1).as_deref(), Some(""));
+        assert_eq!(spaces.shift("   ", 1), None);
+        let tabs = Indent { shiftwidth: TAB_WIDTH, expandtab: false, softtabstop: 0 };
+        assert_eq!(tabs.shift("x", 2).as_deref(), Some("\t\t"));
+        assert_eq!(tabs.shift("        \tx", -1).as_deref(), Some("\t"));
+        let mixed = Indent { shiftwidth: 4, expandtab: false, softtabstop: 4 };
+        assert_eq!(mixed.shift("\tx", 1).as_deref(), Some("\t    "));
+    }
+
+    #[test]
+    fn only_the_fixed_tabstop_is_accepted() {
+        let mut indent = Indent::default();
+        assert_eq!(indent.set_option("ts=8"), Ok(()));
+        assert!(indent.set_option("ts=4").is_err());
+        assert_eq!(indent.set_option("sw=2"), Ok(()));
+        assert_eq!(indent.shiftwidth, 2);
+    }
+}
@@ -0,0 +1,173 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+const CHUNK_LINES: usize = 256;
+
+#[derive(Debug)]
+pub struct LineMap<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Clone for LineMap<T> {
+    fn clone(&self) -> Self {
+        Self { chunks: self.chunks.clone(), starts: self.starts.clone(), len: self.len }
+    }
+}
+
+impl<T> Default for LineMap<T> {
+    fn default() -> Self {
+        Self { chunks: Vec::new(), starts: Vec::new(), len: 0 }
+    }
+}
+
+impl<T: Clone> LineMap<T> {
+    fn chunk_of(&self, line: usize) -> usize {
+        self.starts.partition_point(|&start| start <= line).saturating_sub(1)
+    }
+
+    pub fn get(&self, line: usize) -> Option<&T> {
+        let chunk = self.chunk_of(line);
+        self.chunks.get(chunk)?.get(line - self.starts[chunk])
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self.chunks.len() == other.chunks.len()
+            && self.chunks.iter().zip(&other.chunks).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+
+    pub fn get_mut(&mut self, line: usize) -> Option<&mut T> {
+        let chunk = self.chunk_of(line);
+        let start = *self.starts.get(chunk)?;
+        Arc::make_mut(&mut self.chunks[chunk]).get_mut(line - start)
+    }
+
+    pub fn first_difference(&self, other: &Self) -> Option<usize>
+    where
+        T: PartialEq,
+    {
+        self.first_difference_by(other, T::eq)
+    }
+
+    pub fn first_difference_by(&self, other: &Self, same: impl Fn(&T, &T) -> bool) -> Option<usize> {
+        let shared = self.chunks.iter().zip(&other.chunks).take_while(|(a, b)| Arc::ptr_eq(a, b)).count();
+        let start = self.starts.get(shared).copied().unwrap_or(self.len);
+        match self.iter_from(start).zip(other.iter_from(start)).position(|(a, b)| !same(a, b)) {
+            Some(offset) => Some(start + offset),
+            None => (self.len != other.len).then(|| self.len.min(other.len)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.iter_from(0)
+    }
+
+    pub fn iter_from(&self, line: usize) -> impl Iterator<Item = &T> {
+        let chunk = self.chunk_of(line);
+        let skip = line - self.starts.get(chunk).copied().unwrap_or(0);
+        self.chunks.get(chunk..).unwrap_or_default().iter().flat_map(|rows| rows.iter()).skip(skip)
+    }
+
+    pub fn splice(&mut self, lines: Range<usize>, rows: impl IntoIterator<Item = T>) {
+        let end = lines.end.min(self.len);
+        let start = lines.start.min(end);
+        let first = self.chunk_of(start);
+        let last = if end > start { self.chunk_of(end - 1) } else { first };
+        let mut merged = Vec::new();
+        if let Some(chunk) = self.chunks.get(first) {
+            merged.extend_from_slice(&chunk[..start - self.starts[first]]);
+        }
+        merged.extend(rows);
+        if let Some(chunk) = self.chunks.get(last) {
+            merged.extend_from_slice(&chunk[(end - self.starts[last]).min(chunk.len())..]);
+        }
+        let replaced = if self.chunks.is_empty() { 0..0 } else { first..last + 1 };
+        let mut merged = merged.into_iter().peekable();
+        let mut pieces = Vec::new();
+        while merged.peek().is_some() {
+            pieces.push(Arc::new(merged.by_ref().take(CHUNK_LINES).collect()));
+        }
+        self.chunks.splice(replaced, pieces);
+        self.starts.clear();
+        self.len = 0;
+        for chunk in &self.chunks {
+            self.starts.push(self.len);
+            self.len += chunk.len();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowEdit {
+    pub base: u64,
+    pub from: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl RowEdit {
+    pub fn new(base: u64, lines: Range<usize>, old_count: usize, new_count: usize) -> Self {
+        let from = lines.start.min(old_count.saturating_sub(1)).min(new_count.saturating_sub(1));
+        let tail = new_count.saturating_sub(lines.end).min(old_count - from).min(new_count - from);
+        Self { base, from, old_end: old_count - tail, new_end: new_count - tail }
+    }
+
+    pub fn applies_to(&self, tick: Option<u64>) -> bool {
+        tick == Some(self.base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(map: &LineMap<usize>) -> Vec<usize> {
+        map.iter().copied().collect()
+    }
+
+    #[test]
+    fn splice_across_chunks() {
+        let mut map = LineMap::default();
+        map.splice(0..0, 0..1000);
+        map.splice(250..260, [7, 8]);
+        let mut expected: Vec<usize> = (0..1000).collect();
+        expected.splice(250..260, [7, 8]);
+        assert_eq!(rows(&map), expected);
+        assert_eq!(map.iter_from(251).next(), Some(&8));
+        assert_eq!(map.iter_from(990).count(), 2);
+
+        map.splice(992..992, [1, 2, 3]);
+        map.splice(0..0, [9]);
+        expected.extend([1, 2, 3]);
+        expected.insert(0, 9);
+        assert_eq!(rows(&map), expected);
+    }
+
+    #[test]
+    fn first_difference_skips_shared_chunks() {
+        let mut map = LineMap::default();
+        map.splice(0..0, 0..1000);
+        let mut edited = map.clone();
+        assert!(edited.ptr_eq(&map));
+        edited.splice(600..601, [7, 8]);
+        assert!(!edited.ptr_eq(&map));
+        assert_eq!(map.first_difference(&edited), Some(600));
+        assert_eq!(edited.get(601), Some(&8));
+        assert_eq!(edited.get(1000), Some(&999));
+        assert_eq!(edited.get(1001), None);
+        edited.splice(600..602, [600]);
+        assert_eq!(map.first_difference(&edited), None);
+        edited.splice(1000..1000, [1]);
+        assert_eq!(map.first_difference(&edited), Some(1000));
+    }
+
+    #[test]
+    fn row_edit_keeps_unchanged_tail() {
+        assert_eq!(RowEdit::new(1, 4..6, 10, 11), RowEdit { base: 1, from: 4, old_end: 5, new_end: 6 });
+        assert_eq!(RowEdit::new(1, 4..5, 10, 9), RowEdit { base: 1, from: 4, old_end: 6, new_end: 5 });
+        assert_eq!(RowEdit::new(1, 3..5, 3, 5), RowEdit { base: 1, from: 2, old_end: 3, new_end: 5 });
+        assert_eq!(RowEdit::new(1, 0..usize::MAX, 3, 5), RowEdit { base: 1, from: 0, old_end: 3, new_end: 5 });
+    }
+}
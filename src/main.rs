@@ -1,13 +1,23 @@
 #![cfg_attr(target_os = "windows", windows_subsystem = "windows")]
 
 mod app;
+mod brackets;
 mod cursor_editor;
+mod ex;
 mod format;
 mod highlight;
+mod history;
+mod indent;
+mod line_map;
+mod markdown;
 mod menu;
 mod message;
 mod search;
 mod subscription;
+mod syntax;
+mod theme;
+#[cfg(feature = "tree-sitter")]
+mod treesitter;
 mod ui;
 mod undo_tree;
 mod undo_tree_widget;
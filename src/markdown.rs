@@ -0,0 +1,524 @@
+use iced::widget::operation::{self, RelativeOffset};
+use iced::widget::text::Span;
+use iced::widget::{column, container, image, rich_text, row, rule, scrollable, span, text};
+use iced::{Element, Fill, Font, Length, Task, Theme, font};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::app::App;
+use crate::message::Message;
+use crate::syntax::{self, Language, LineState};
+use crate::theme::EditorTheme;
+
+pub const PREVIEW_SCROLL_ID: iced::widget::Id = iced::widget::Id::new("markdown_preview");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Strong(String),
+    Emphasis(String),
+    Code(String),
+    Link { text: String, url: String },
+    Image { alt: String, url: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub depth: usize,
+    pub marker: Option<String>,
+    pub checked: Option<bool>,
+    pub content: Vec<Inline>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading(usize, Vec<Inline>),
+    Paragraph(Vec<Inline>),
+    List(Vec<ListItem>),
+    Code { language: String, code: String },
+    Quote(Vec<Inline>),
+    Table { header: Vec<Vec<Inline>>, rows: Vec<Vec<Vec<Inline>>> },
+    Rule,
+}
+
+// Blocks paired with the source line they start on, which the preview uses
+// to follow the editor.
+pub type Document = Vec<(usize, Block)>;
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3 && ["-", "*", "_"].iter().any(|c| compact.chars().all(|x| x.to_string() == *c))
+}
+
+fn fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f))
+}
+
+fn list_item(line: &str) -> Option<ListItem> {
+    let indent = line.len() - line.trim_start().len();
+    let trimmed = line.trim_start();
+    let (marker, rest) = if let Some(rest) = trimmed.strip_prefix(['-', '*', '+']) {
+        (None, rest)
+    } else {
+        let digits = trimmed.bytes().take_while(u8::is_ascii_digit).count();
+        let rest = trimmed[digits..].strip_prefix(['.', ')']).filter(|_| digits > 0)?;
+        (Some(format!("{}.", &trimmed[..digits])), rest)
+    };
+    let rest = rest.strip_prefix(' ')?;
+    let (checked, rest) = match rest.get(..4) {
+        Some("[ ] ") => (Some(false), &rest[4..]),
+        Some("[x] ") | Some("[X] ") => (Some(true), &rest[4..]),
+        _ => (None, rest),
+    };
+    Some(ListItem { depth: indent / 2, marker, checked, content: parse_inline(rest.trim()) })
+}
+
+fn table_cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(str::trim).collect()
+}
+
+fn is_table_delimiter(line: &str) -> bool {
+    line.contains('-')
+        && table_cells(line)
+            .iter()
+            .all(|cell| !cell.is_empty() && cell.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+}
+
+pub fn parse(source: &str) -> Document {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut blocks = Document::new();
+    let mut paragraph: Option<(usize, String)> = None;
+    let flush = |paragraph: &mut Option<(usize, String)>, blocks: &mut Document| {
+        if let Some((start, text)) = paragraph.take() {
+            blocks.push((start, Block::Paragraph(parse_inline(&text))));
+        }
+    };
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &mut blocks);
+            i += 1;
+            continue;
+        }
+        if let Some(marker) = fence(line) {
+            flush(&mut paragraph, &mut blocks);
+            let language = trimmed[marker.len()..].trim().to_string();
+            let start = i;
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() && fence(lines[i]) != Some(marker) {
+                code.push(lines[i]);
+                i += 1;
+            }
+            blocks.push((start, Block::Code { language, code: code.join("\n") }));
+            i += 1;
+            continue;
+        }
+        let setext = match trimmed.chars().next() {
+            Some('=') if trimmed.chars().all(|c| c == '=') => Some(1),
+            Some('-') if trimmed.chars().all(|c| c == '-') => Some(2),
+            _ => None,
+        };
+        if let Some(level) = setext
+            && let Some((start, text)) = paragraph.take()
+        {
+            blocks.push((start, Block::Heading(level, parse_inline(&text))));
+            i += 1;
+            continue;
+        }
+        if let Some((level, text)) = heading(trimmed) {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((i, Block::Heading(level, parse_inline(text))));
+        } else if is_rule(trimmed) {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((i, Block::Rule));
+        } else if trimmed.starts_with('>') {
+            flush(&mut paragraph, &mut blocks);
+            let start = i;
+            let mut quote = Vec::new();
+            while i < lines.len() && lines[i].trim_start().starts_with('>') {
+                quote.push(lines[i].trim_start()[1..].trim());
+                i += 1;
+            }
+            blocks.push((start, Block::Quote(parse_inline(&quote.join(" ")))));
+            continue;
+        } else if let Some(item) = list_item(line) {
+            flush(&mut paragraph, &mut blocks);
+            let start = i;
+            let mut items = vec![item];
+            i += 1;
+            while i < lines.len() && !lines[i].trim().is_empty() {
+                match list_item(lines[i]) {
+                    Some(item) => items.push(item),
+                    None => {
+                        let Some(last) = items.last_mut() else { break };
+                        last.content.push(Inline::Text(String::from(" ")));
+                        last.content.extend(parse_inline(lines[i].trim()));
+                    }
+                }
+                i += 1;
+            }
+            blocks.push((start, Block::List(items)));
+            continue;
+        } else if trimmed.contains('|') && lines.get(i + 1).is_some_and(|next| is_table_delimiter(next)) {
+            flush(&mut paragraph, &mut blocks);
+            let start = i;
+            let header = table_cells(line).into_iter().map(parse_inline).collect();
+            let mut rows = Vec::new();
+            i += 2;
+            while i < lines.len() && lines[i].contains('|') {
+                rows.push(table_cells(lines[i]).into_iter().map(parse_inline).collect());
+                i += 1;
+            }
+            blocks.push((start, Block::Table { header, rows }));
+            continue;
+        } else if paragraph.is_none() && line.starts_with("    ") {
+            let start = i;
+            let mut code = Vec::new();
+            while i < lines.len() && (lines[i].starts_with("    ") || lines[i].trim().is_empty()) {
+                code.push(lines[i].get(4..).unwrap_or(""));
+                i += 1;
+            }
+            while code.last().is_some_and(|l| l.is_empty()) {
+                code.pop();
+            }
+            blocks.push((start, Block::Code { language: String::new(), code: code.join("\n") }));
+            continue;
+        } else {
+            match &mut paragraph {
+                Some((_, text)) => {
+                    text.push(' ');
+                    text.push_str(trimmed);
+                }
+                None => paragraph = Some((i, trimmed.to_string())),
+            }
+        }
+        i += 1;
+    }
+    flush(&mut paragraph, &mut blocks);
+    blocks
+}
+
+// `[text](url)` starting at the `[`; returns the text, url and bytes consumed.
+fn bracketed(rest: &str) -> Option<(&str, &str, usize)> {
+    let close = rest.find("](")?;
+    let end = rest[close + 2..].find(')')? + close + 2;
+    let url = rest[close + 2..end].split_whitespace().next().unwrap_or("");
+    Some((&rest[1..close], url, end + 1))
+}
+
+pub fn parse_inline(source: &str) -> Vec<Inline> {
+    let mut result = Vec::new();
+    let mut plain = String::new();
+    let mut rest = source;
+    let push_plain = |plain: &mut String, result: &mut Vec<Inline>| {
+        if !plain.is_empty() {
+            result.push(Inline::Text(std::mem::take(plain)));
+        }
+    };
+    while let Some(c) = rest.chars().next() {
+        let parsed = match c {
+            '\\' if rest.len() > 1 => {
+                let escaped = rest[1..].chars().next().unwrap_or('\\');
+                plain.push(escaped);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+            '`' => rest[1..].find('`').map(|end| (Inline::Code(rest[1..end + 1].to_string()), end + 2)),
+            '!' if rest[1..].starts_with('[') => bracketed(&rest[1..]).map(|(alt, url, len)| {
+                (Inline::Image { alt: alt.to_string(), url: url.to_string() }, len + 1)
+            }),
+            '[' => bracketed(rest).map(|(text, url, len)| {
+                (Inline::Link { text: text.to_string(), url: url.to_string() }, len)
+            }),
+            '<' => rest.find('>').map(|end| &rest[1..end]).filter(|url| url.contains("://")).map(|url| {
+                (Inline::Link { text: url.to_string(), url: url.to_string() }, url.len() + 2)
+            }),
+            '*' | '_' => {
+                let strong = if c == '*' { "**" } else { "__" };
+                if let Some(inner) = rest.strip_prefix(strong) {
+                    inner
+                        .find(strong)
+                        .filter(|&end| end > 0)
+                        .map(|end| (Inline::Strong(inner[..end].to_string()), end + 4))
+                } else {
+                    let inner = &rest[1..];
+                    let opens = inner.starts_with(|n: char| !n.is_whitespace())
+                        && (c == '*' || !plain.ends_with(|p: char| p.is_alphanumeric()));
+                    inner
+                        .find(c)
+                        .filter(|&end| opens && end > 0)
+                        .map(|end| (Inline::Emphasis(inner[..end].to_string()), end + 2))
+                }
+            }
+            _ => None,
+        };
+        match parsed {
+            Some((inline, len)) => {
+                push_plain(&mut plain, &mut result);
+                result.push(inline);
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    push_plain(&mut plain, &mut result);
+    result
+}
+
+fn resolve(base: Option<&Path>, url: &str) -> PathBuf {
+    let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+    match base {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn is_remote(url: &str) -> bool {
+    url.contains("://") && !url.starts_with("file://")
+}
+
+// Links are handed straight to the system opener, never to a shell, and only
+// for web, mail and local file targets. Local paths are made absolute so they
+// cannot be read as options.
+fn link_target(base: Option<&Path>, url: &str) -> Option<String> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .filter(|scheme| scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)));
+    match scheme.as_deref() {
+        Some("http" | "https" | "mailto") => Some(url.to_string()),
+        Some("file") | None => std::path::absolute(resolve(base, url)).ok().map(|path| path.to_string_lossy().into_owned()),
+        Some(_) => None,
+    }
+}
+
+fn spans<'a>(inlines: &'a [Inline], size: f32, bold: bool, theme: &EditorTheme) -> Vec<Span<'a, String>> {
+    let weight = if bold { font::Weight::Bold } else { font::Weight::Normal };
+    let base = Font { weight, ..Font::DEFAULT };
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => span(text.as_str()).font(base).size(size),
+            Inline::Strong(text) => span(text.as_str()).font(Font { weight: font::Weight::Bold, ..base }).size(size),
+            Inline::Emphasis(text) => span(text.as_str()).font(Font { style: font::Style::Italic, ..base }).size(size),
+            Inline::Code(code) => span(code.as_str())
+                .font(Font::MONOSPACE)
+                .size(size * 0.9)
+                .background(theme.current_line)
+                .color(theme.string),
+            Inline::Link { text, url } => span(text.as_str())
+                .font(base)
+                .size(size)
+                .color(theme.palette.primary)
+                .underline(true)
+                .link(url.clone()),
+            Inline::Image { alt, url } => span(format!("[{alt}]"))
+                .font(base)
+                .size(size)
+                .color(theme.palette.primary)
+                .link(url.clone()),
+        })
+        .collect()
+}
+
+fn paragraph<'a>(inlines: &'a [Inline], size: f32, bold: bool, theme: &EditorTheme) -> Element<'a, Message> {
+    rich_text(spans(inlines, size, bold, theme)).on_link_click(Message::PreviewLinkClicked).into()
+}
+
+fn code_block<'a>(language: &str, code: &'a str, theme: &EditorTheme) -> Element<'a, Message> {
+    let language = Language::from_name(language);
+    let mut state = LineState::default();
+    let mut lines = column![];
+    for line in code.split('\n') {
+        let mut spans: Vec<Span<'a, String>> = Vec::new();
+        let mut last = 0;
+        if let Some(language) = language {
+            let (tokens, next) = syntax::highlight_line(language, line, state);
+            state = next;
+            for (range, token) in tokens {
+                if range.start < last || range.end > line.len() {
+                    continue;
+                }
+                spans.push(span(&line[last..range.start]));
+                spans.push(span(&line[range.clone()]).color(theme.token(token)));
+                last = range.end;
+            }
+        }
+        spans.push(span(&line[last..]));
+        lines = lines.push(rich_text(spans).font(Font::MONOSPACE).size(13).on_link_click(Message::PreviewLinkClicked));
+    }
+    let background = theme.current_line;
+    container(lines)
+        .padding(8)
+        .width(Fill)
+        .style(move |_: &Theme| container::Style { background: Some(background.into()), ..Default::default() })
+        .into()
+}
+
+fn block<'a>(block: &'a Block, base: Option<&Path>, theme: &EditorTheme) -> Element<'a, Message> {
+    match block {
+        Block::Heading(level, inlines) => {
+            let size = [28.0, 24.0, 20.0, 18.0, 16.0, 14.0][level.clamp(&1, &6) - 1];
+            paragraph(inlines, size, true, theme)
+        }
+        Block::Paragraph(inlines) => match inlines.as_slice() {
+            [Inline::Image { url, .. }] if !is_remote(url) => image(resolve(base, url)).into(),
+            _ => paragraph(inlines, 15.0, false, theme),
+        },
+        Block::List(items) => column(items.iter().map(|item| {
+            let bullet = match (&item.checked, &item.marker) {
+                (Some(true), _) => String::from("☑"),
+                (Some(false), _) => String::from("☐"),
+                (None, Some(marker)) => marker.clone(),
+                (None, None) => String::from("•"),
+            };
+            row![
+                text(bullet).size(15).width(Length::Fixed(24.0)),
+                paragraph(&item.content, 15.0, false, theme),
+            ]
+            .padding(iced::Padding { left: item.depth as f32 * 20.0, ..Default::default() })
+            .into()
+        }))
+        .spacing(2)
+        .into(),
+        Block::Code { language, code } => code_block(language, code, theme),
+        Block::Quote(inlines) => {
+            let bar = theme.muted;
+            row![
+                container(text(""))
+                    .width(3)
+                    .height(Length::Fill)
+                    .style(move |_: &Theme| container::Style { background: Some(bar.into()), ..Default::default() }),
+                paragraph(inlines, 15.0, false, theme),
+            ]
+            .spacing(8)
+            .height(Length::Shrink)
+            .into()
+        }
+        Block::Table { header, rows } => {
+            let border = theme.gutter;
+            let cell = move |content: Element<'a, Message>| -> Element<'a, Message> {
+                container(content)
+                    .padding([4, 8])
+                    .width(Fill)
+                    .style(move |_: &Theme| container::Style {
+                        border: iced::Border { color: border, width: 1.0, radius: 0.0.into() },
+                        ..Default::default()
+                    })
+                    .into()
+            };
+            let header = row(header.iter().map(|c| cell(paragraph(c, 14.0, true, theme))));
+            let body = rows.iter().map(|r| row(r.iter().map(|c| cell(paragraph(c, 14.0, false, theme)))).into());
+            column![header].extend(body).into()
+        }
+        Block::Rule => rule::horizontal(1).into(),
+    }
+}
+
+impl App {
+    pub fn refresh_preview(&mut self) {
+        if !self.show_preview || self.preview_tick == Some(self.changedtick) {
+            return;
+        }
+        self.preview_tick = Some(self.changedtick);
+        self.preview = Arc::new(parse(&self.content.text()));
+    }
+
+    // The preview follows the block under an estimate of the editor's top
+    // line when scrolling, and the block under the cursor otherwise.
+    pub fn sync_preview(&mut self, scrolled: Option<i32>) -> Task<Message> {
+        if !self.show_preview {
+            return Task::none();
+        }
+        let line_count = self.content.line_count();
+        self.preview_line = match scrolled {
+            Some(lines) => self.preview_line.saturating_add_signed(lines as isize).min(line_count.saturating_sub(1)),
+            None => self.content.cursor().position.line,
+        };
+        let blocks = &self.preview;
+        let index = blocks.partition_point(|(start, _)| *start <= self.preview_line).saturating_sub(1);
+        let y = match (blocks.get(index), blocks.len().checked_sub(1).filter(|&last| last > 0)) {
+            (Some(&(start, _)), Some(last)) => {
+                let end = blocks.get(index + 1).map_or(line_count, |(next, _)| *next);
+                let within = self.preview_line.saturating_sub(start) as f32 / end.saturating_sub(start).max(1) as f32;
+                ((index as f32 + within) / last as f32).min(1.0)
+            }
+            _ => 0.0,
+        };
+        operation::snap_to(PREVIEW_SCROLL_ID, RelativeOffset { x: 0.0, y })
+    }
+
+    pub fn preview_link(&mut self, url: &str) {
+        let base = self.current_file.as_deref().and_then(Path::parent);
+        let Some(target) = link_target(base, url) else {
+            self.status_message = format!("Not opening {}: only web, mail and file links are followed", url);
+            return;
+        };
+        #[cfg(target_os = "windows")]
+        let opened = {
+            use std::os::windows::process::CommandExt;
+            std::process::Command::new("rundll32").arg("url.dll,FileProtocolHandler").raw_arg(&target).spawn()
+        };
+        #[cfg(target_os = "macos")]
+        let opened = std::process::Command::new("open").arg(&target).spawn();
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let opened = std::process::Command::new("xdg-open").arg(&target).spawn();
+        if let Err(e) = opened {
+            self.status_message = format!("Cannot open {}: {}", target, e);
+        }
+    }
+
+    pub fn markdown_preview(&self) -> Element<'_, Message> {
+        let theme = self.editor_theme();
+        let base = self.current_file.as_deref().and_then(Path::parent);
+        let blocks = column(self.preview.iter().map(|(_, b)| block(b, base, &theme))).spacing(12).padding(16);
+        container(scrollable(blocks).id(PREVIEW_SCROLL_ID).width(Fill).height(Fill))
+            .width(Fill)
+            .height(Fill)
+            .style(|theme: &Theme| container::Style {
+                border: iced::Border {
+                    width: 1.0,
+                    color: theme.extended_palette().background.strong.color,
+                    radius: 0.0.into(),
+                },
+                ..Default::default()
+            })
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_targets() {
+        let base = Path::new("/docs");
+        assert_eq!(link_target(Some(base), "https://a.test/?x=1&y=2").as_deref(), Some("https://a.test/?x=1&y=2"));
+        assert_eq!(link_target(Some(base), "mailto:me@a.test").as_deref(), Some("mailto:me@a.test"));
+        assert_eq!(link_target(Some(base), "notes/a.md").as_deref(), Some("/docs/notes/a.md"));
+        assert_eq!(link_target(Some(base), "file:///tmp/a.md").as_deref(), Some("/tmp/a.md"));
+        assert!(link_target(None, "-n").is_some_and(|target| Path::new(&target).is_absolute()));
+        assert_eq!(link_target(Some(base), "javascript:alert(1)"), None);
+        assert_eq!(link_target(Some(base), "ms-settings:privacy"), None);
+    }
+}
@@ -4,6 +4,7 @@ use iced_aw::menu::{Item, Menu, MenuBar};
 
 use crate::app::App;
 use crate::message::{LineNumbers, Message};
+use crate::undo_tree::UndoGranularity;
 
 impl App {
     pub fn menu_bar(&self) -> Element<'_, Message> {
@@ -17,9 +18,21 @@ impl App {
         ])
         .max_width(220.0);
 
+        let granularity_label = match self.undo_granularity {
+            UndoGranularity::Pause => "Undo Steps: Pause",
+            UndoGranularity::Word => "Undo Steps: Word",
+            UndoGranularity::Edit => "Undo Steps: Each Edit",
+        };
+        let insert_session_label = if self.undo_insert_session {
+            "Insert Session = One Step ✓"
+        } else {
+            "Insert Session = One Step"
+        };
         let edit_menu = Menu::new(vec![
             Item::new(menu_item("Undo", "u", Message::Undo)),
             Item::new(menu_item("Redo", "Ctrl+R", Message::Redo)),
+            Item::new(menu_item(granularity_label, "", Message::CycleUndoGranularity)),
+            Item::new(menu_item(insert_session_label, "", Message::ToggleUndoInsertSession)),
             Item::new(separator()),
             Item::new(menu_item("Cut", "Ctrl+X", Message::Cut)),
             Item::new(menu_item("Copy", "Ctrl+C", Message::Copy)),
@@ -31,10 +44,15 @@ impl App {
             Item::new(menu_item("Go To Line", "Ctrl+G", Message::TogglePanel)),
             Item::new(separator()),
             Item::new(menu_item("Select All", "Ctrl+A", Message::SelectAll)),
+            Item::new(menu_item("Add Next Occurrence", "Ctrl+D", Message::AddNextOccurrence)),
+            Item::new(menu_item("Cursors From Matches", "Ctrl+Shift+L", Message::CursorsFromMatches)),
+            #[cfg(feature = "tree-sitter")]
+            Item::new(menu_item("Expand Selection", "Ctrl+W", Message::ExpandSelection)),
             Item::new(separator()),
             Item::new(menu_item("Format Document", "F5", Message::FormatDocument)),
+            Item::new(menu_item("Strip Trailing Whitespace", "", Message::StripTrailingWhitespace)),
         ])
-        .max_width(220.0);
+        .max_width(250.0);
 
         let wrap_label = if self.word_wrap { "Word Wrap ✓" } else { "Word Wrap" };
         let format_menu = Menu::new(vec![
@@ -51,14 +69,52 @@ impl App {
             LineNumbers::Absolute => "Line Numbers: Absolute ✓",
             LineNumbers::Relative => "Line Numbers: Relative ✓",
         };
+        let rainbow_label = if self.rainbow_brackets { "Rainbow Brackets ✓" } else { "Rainbow Brackets" };
+        let current_line_label = if self.highlight_current_line { "Current Line ✓" } else { "Current Line" };
+        let rulers_label = if self.show_rulers { "Rulers ✓" } else { "Rulers" };
+        let guides_label = if self.indent_guides { "Indent Guides ✓" } else { "Indent Guides" };
+        let whitespace_label = if self.show_whitespace { "Show Whitespace ✓" } else { "Show Whitespace" };
+        let word_label = if self.highlight_word { "Highlight Word Under Cursor ✓" } else { "Highlight Word Under Cursor" };
+        let follow_label = if self.follow_system_theme { "Follow System ✓" } else { "Follow System" };
+        let current_theme = self.editor_theme();
+        let mut theme_items: Vec<_> = self
+            .themes
+            .iter()
+            .map(|theme| {
+                let label = if theme.name == current_theme.name { format!("{} ✓", theme.name) } else { theme.name.clone() };
+                Item::new(menu_item(label, "", Message::SelectTheme(theme.name.clone())))
+            })
+            .collect();
+        theme_items.push(Item::new(separator()));
+        theme_items.push(Item::new(menu_item(follow_label, "", Message::ToggleFollowSystemTheme)));
+        let theme_menu = Menu::new(theme_items).max_width(220.0);
+        let spaces_label = if self.indent.expandtab { "Indent Using Spaces ✓" } else { "Indent Using Spaces" };
+        let mut indent_items = vec![Item::new(menu_item(spaces_label, "", Message::ToggleExpandTab)), Item::new(separator())];
+        indent_items.extend([2, 4, 8].map(|width| {
+            let label = if self.indent.shiftwidth == width { format!("Width: {} ✓", width) } else { format!("Width: {}", width) };
+            Item::new(menu_item(label, "", Message::SetIndentWidth(width)))
+        }));
+        indent_items.push(Item::new(separator()));
+        indent_items.push(Item::new(menu_item("Detect From Content", "", Message::DetectIndent)));
+        let indent_menu = Menu::new(indent_items).max_width(220.0);
         let undo_panel_label = if self.show_undo_panel { "Undo Tree ✓" } else { "Undo Tree" };
+        let preview_label = if self.show_preview { "Markdown Preview ✓" } else { "Markdown Preview" };
         let view_menu = Menu::new(vec![
             Item::new(menu_item_disabled("Status Bar")),
             Item::new(separator()),
             Item::new(menu_item(vim_label, "F6", Message::ToggleVim)),
             Item::new(separator()),
             Item::new(menu_item(ln_label, "", Message::ToggleLineNumbers)),
+            Item::new(menu_item(rainbow_label, "", Message::ToggleRainbowBrackets)),
+            Item::new(menu_item(current_line_label, "", Message::ToggleCurrentLine)),
+            Item::new(menu_item(rulers_label, "", Message::ToggleRulers)),
+            Item::new(menu_item(guides_label, "", Message::ToggleIndentGuides)),
+            Item::new(menu_item(whitespace_label, "", Message::ToggleWhitespace)),
+            Item::new(menu_item(word_label, "", Message::ToggleWordHighlight)),
+            Item::with_menu(submenu_item("Theme"), theme_menu),
+            Item::with_menu(submenu_item("Indentation"), indent_menu),
             Item::new(separator()),
+            Item::new(menu_item(preview_label, "Ctrl+Shift+M", Message::TogglePreview)),
             Item::new(menu_item(undo_panel_label, "Ctrl+T", Message::ToggleUndoPanel)),
         ])
         .max_width(250.0);
@@ -102,13 +158,11 @@ pub fn menu_root(label: &str) -> Element<'_, Message> {
         .into()
 }
 
-pub fn menu_item<'a>(label: &'a str, shortcut: &'a str, msg: Message) -> Element<'a, Message> {
+pub fn menu_item<'a>(label: impl text::IntoFragment<'a>, shortcut: &'a str, msg: Message) -> Element<'a, Message> {
     button(
         row![
             text(label).size(14).width(Length::Fill),
-            text(shortcut)
-                .size(12)
-                .color(iced::Color::from_rgb(0.5, 0.5, 0.5)),
+            text(shortcut).size(12).style(muted_text),
         ]
         .width(Length::Fill),
     )
@@ -135,17 +189,32 @@ pub fn menu_item<'a>(label: &'a str, shortcut: &'a str, msg: Message) -> Element
 }
 
 pub fn menu_item_disabled(label: &str) -> Element<'_, Message> {
-    button(text(label).size(14).color(iced::Color::from_rgb(0.4, 0.4, 0.4)))
+    button(text(label).size(14).style(muted_text))
         .padding([4, 12])
         .width(Length::Fill)
         .style(|_theme: &Theme, _status| button::Style {
-            text_color: iced::Color::from_rgb(0.4, 0.4, 0.4),
             background: None,
             ..Default::default()
         })
         .into()
 }
 
+pub fn submenu_item(label: &str) -> Element<'_, Message> {
+    button(row![text(label).size(14).width(Length::Fill), text("▸").size(12)].width(Length::Fill))
+        .padding([4, 12])
+        .width(Length::Fill)
+        .style(|theme: &Theme, _status| button::Style {
+            text_color: theme.extended_palette().background.base.text,
+            background: None,
+            ..Default::default()
+        })
+        .into()
+}
+
+fn muted_text(theme: &Theme) -> text::Style {
+    text::Style { color: Some(theme.extended_palette().background.strong.color) }
+}
+
 pub fn separator<'a>() -> Element<'a, Message> {
     iced::widget::container(iced::widget::Space::new())
         .width(Length::Fill)
@@ -1,7 +1,13 @@
-use iced::widget::text_editor;
+use iced::keyboard;
+use iced::widget::{scrollable, text_editor};
 use serde::{Deserialize, Serialize};
+use std::ops::Range;
 use std::path::PathBuf;
 
+use crate::format::FormatOutcome;
+use crate::history::HistoryKind;
+use crate::search::FindResults;
+
 #[derive(Debug, Clone)]
 pub enum PendingAction {
     New,
@@ -17,6 +23,7 @@ pub enum VimMode {
     Search,
     Visual,
     VisualLine,
+    Confirm,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -43,7 +50,7 @@ pub enum Message {
     FileOpened(Option<(PathBuf, String)>),
     Save,
     SaveAs,
-    FileSaved(Option<PathBuf>),
+    FileSaved(u64, usize, Option<PathBuf>),
     Exit,
     Undo,
     Cut,
@@ -52,12 +59,30 @@ pub enum Message {
     Delete,
     SelectAll,
     FormatDocument,
+    DocumentFormatted(u64, Option<Range<usize>>, FormatOutcome),
+    StripTrailingWhitespace,
+    Indent,
+    Unindent,
+    ToggleExpandTab,
+    SetIndentWidth(usize),
+    DetectIndent,
     TogglePanel,
     ClosePanel,
     FindQueryChanged(String),
     ReplaceTextChanged(String),
     GoToLineChanged(String),
     ToggleCaseSensitive(bool),
+    ToggleRegex(bool),
+    ToggleWholeWord(bool),
+    ToggleInSelection(bool),
+    ToggleFindResults(bool),
+    FindResultSelected(usize),
+    FindResultsFocusToggle,
+    FindResultsMove(i32),
+    FindResultsConfirm,
+    FindResultsReady(u64, Option<FindResults>),
+    HistoryKey(i32),
+    HistoryStep(HistoryKind, i32),
     FindNext,
     FindPrevious,
     ReplaceOne,
@@ -68,6 +93,12 @@ pub enum Message {
     ZoomOut,
     CtrlPressed,
     CtrlReleased,
+    ModifiersChanged(keyboard::Modifiers),
+    AddNextOccurrence,
+    CursorsFromMatches,
+    ClearCursors,
+    #[cfg(feature = "tree-sitter")]
+    ExpandSelection,
     ShowAbout,
     CloseAbout,
     WindowCloseRequested,
@@ -87,17 +118,44 @@ pub enum Message {
     VimEnterCommand,
     VimCommandChanged(String),
     VimCommandSubmit,
+    VimConfirm(char),
     ToggleVim,
     ToggleLineNumbers,
+    ToggleRainbowBrackets,
+    ToggleCurrentLine,
+    ToggleRulers,
+    ToggleIndentGuides,
+    ToggleWhitespace,
+    ToggleWordHighlight,
+    SelectTheme(String),
+    ToggleFollowSystemTheme,
+    SystemThemeChanged(iced::theme::Mode),
     VimEnterSearch(bool),
     VimSearchChanged(String),
     VimSearchSubmit,
+    VimSearchResults(u64, Option<FindResults>),
+    WordMatchesReady(u64, Option<FindResults>),
     ToggleUndoPanel,
+    TogglePreview,
+    PreviewLinkClicked(String),
     UndoTreeSelect(usize),
     UndoTreeJump(usize),
     UndoPanelFocusToggle,
     UndoPanelMoveSelection(i32),
+    UndoPanelMoveSibling(i32),
+    UndoPanelToggleCollapse,
+    UndoPanelCollapseInactive,
+    UndoPanelZoom(i32),
+    UndoPanelFocusFilter,
+    UndoPanelScrolled(scrollable::Viewport),
+    UndoMinimapJump(f32),
     UndoPanelConfirm,
+    UndoLabelChanged(String),
+    UndoLabelSubmit,
+    UndoSearchChanged(String),
+    UndoSearchStep(i32),
     Redo,
+    CycleUndoGranularity,
+    ToggleUndoInsertSession,
     Tick,
 }
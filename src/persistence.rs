@@ -1,19 +1,69 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
+use crate::history::History;
 use crate::message::LineNumbers;
-use crate::undo_tree::UndoTree;
+use crate::undo_tree::{UndoGranularity, UndoTree};
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub vim_enabled: bool,
     pub line_numbers: LineNumbers,
     pub word_wrap: bool,
     pub scale: f32,
+    pub undo_granularity: UndoGranularity,
+    pub undo_pause_ms: u64,
+    pub undo_insert_session: bool,
+    pub find_case_sensitive: bool,
+    pub find_regex: bool,
+    pub find_whole_word: bool,
+    pub find_in_selection: bool,
+    pub find_show_results: bool,
+    pub rainbow_brackets: bool,
+    pub highlight_current_line: bool,
+    pub show_rulers: bool,
+    pub rulers: Vec<usize>,
+    pub indent_guides: bool,
+    pub show_whitespace: bool,
+    pub highlight_word: bool,
+    pub theme: String,
+    pub follow_system_theme: bool,
+    pub formatters: BTreeMap<String, String>,
+    pub shiftwidth: usize,
+    pub expandtab: bool,
+    pub softtabstop: usize,
 }
 
 impl Default for Settings {
     fn default() -> Self {
-        Self { vim_enabled: false, line_numbers: LineNumbers::None, word_wrap: true, scale: 1.0 }
+        Self {
+            vim_enabled: false,
+            line_numbers: LineNumbers::None,
+            word_wrap: true,
+            scale: 1.0,
+            undo_granularity: UndoGranularity::Pause,
+            undo_pause_ms: 1000,
+            undo_insert_session: true,
+            find_case_sensitive: false,
+            find_regex: false,
+            find_whole_word: false,
+            find_in_selection: false,
+            find_show_results: false,
+            rainbow_brackets: true,
+            highlight_current_line: true,
+            show_rulers: false,
+            rulers: vec![80, 100],
+            indent_guides: false,
+            show_whitespace: false,
+            highlight_word: true,
+            theme: String::from("Dark"),
+            follow_system_theme: false,
+            formatters: crate::format::default_formatters(),
+            shiftwidth: 4,
+            expandtab: true,
+            softtabstop: 4,
+        }
     }
 }
 
@@ -25,10 +75,18 @@ fn settings_path() -> Option<PathBuf> {
     config_dir().map(|d| d.join("settings.json"))
 }
 
+fn history_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("history.json"))
+}
+
 fn undo_path(file_path: &Path) -> Option<PathBuf> {
     config_dir().map(|d| d.join("undo").join(format!("{:016x}.json", path_hash(file_path))))
 }
 
+fn themes_dir() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("themes"))
+}
+
 fn path_hash(path: &Path) -> u64 {
     let s = path.to_string_lossy();
     let mut h: u64 = 14695981039346656037;
@@ -51,6 +109,28 @@ pub fn save_settings(settings: &Settings) {
     if let Ok(json) = serde_json::to_vec_pretty(settings) { let _ = std::fs::write(&path, json); }
 }
 
+pub fn load_theme_files() -> Vec<(PathBuf, String)> {
+    let Some(entries) = themes_dir().and_then(|d| std::fs::read_dir(d).ok()) else { return Vec::new() };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+    paths.into_iter().filter_map(|p| std::fs::read_to_string(&p).ok().map(|source| (p, source))).collect()
+}
+
+pub fn load_history() -> History {
+    let path = match history_path() { Some(p) => p, None => return History::default() };
+    let bytes = match std::fs::read(&path) { Ok(b) => b, Err(_) => return History::default() };
+    serde_json::from_slice(&bytes).unwrap_or_default()
+}
+
+pub fn save_history(history: &History) {
+    let path = match history_path() { Some(p) => p, None => return };
+    if let Some(parent) = path.parent() { let _ = std::fs::create_dir_all(parent); }
+    if let Ok(json) = serde_json::to_vec_pretty(history) { let _ = std::fs::write(&path, json); }
+}
+
 pub fn load_undo_tree(file_path: &Path) -> Option<UndoTree> {
     let path = undo_path(file_path)?;
     let bytes = std::fs::read(&path).ok()?;
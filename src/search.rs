@@ -1,65 +1,739 @@
-use iced::widget::{checkbox, column, container, row, text, text_editor, text_input};
-use iced::{Element, Length, Theme};
+use iced::futures::channel::oneshot;
+use iced::widget::text_editor::LineEnding;
+use iced::widget::operation::{self, AbsoluteOffset};
+use iced::widget::text::{Span, Wrapping};
+use iced::widget::{button, checkbox, column, container, rich_text, row, scrollable, span, text, text_editor, text_input};
+use iced::{Element, Font, Length, Task, Theme};
+use regex::{Regex, RegexBuilder};
+use regex_syntax::ParserBuilder;
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::app::App;
-use crate::message::Message;
+use crate::line_map::{LineMap, RowEdit};
+use crate::message::{Message, VimMode};
 use crate::ui::dialog_button;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FindMatch {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+pub const WORD_HIGHLIGHT_DELAY: Duration = Duration::from_millis(300);
+
+pub const FIND_INPUT_ID: iced::widget::Id = iced::widget::Id::new("find_input");
+pub const REPLACE_INPUT_ID: iced::widget::Id = iced::widget::Id::new("replace_input");
+pub const FIND_RESULTS_SCROLL_ID: iced::widget::Id = iced::widget::Id::new("find_results_scroll");
+const MAX_RESULT_ROWS: usize = 500;
+const RESULT_ROW_HEIGHT: f32 = 54.0;
+const RESULTS_HEIGHT: f32 = 220.0;
+
+const MAX_FIND_MATCHES: usize = 100_000;
+const BACKGROUND_SEARCH_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EditSpan {
+    pub start: (usize, usize),
+    pub old_end: (usize, usize),
+    pub new_end: (usize, usize),
+}
+
+impl EditSpan {
+    pub fn map(&self, position: (usize, usize)) -> (usize, usize) {
+        if position <= self.start {
+            position
+        } else if position < self.old_end {
+            self.new_end
+        } else if position.0 == self.old_end.0 {
+            (self.new_end.0, self.new_end.1 + position.1 - self.old_end.1)
+        } else {
+            (position.0 - self.old_end.0 + self.new_end.0, position.1)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FindResults {
+    pub matches: Arc<Vec<FindMatch>>,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(
+            text.bytes()
+                .enumerate()
+                .filter(|&(_, b)| b == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+        Self { starts }
+    }
+
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_of(offset);
+        (line, offset - self.starts[line])
+    }
+
+    pub fn line_start(&self, line: usize) -> usize {
+        self.starts.get(line).copied().unwrap_or(usize::MAX)
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn splice(&mut self, range: Range<usize>, text: &str) {
+        let first = self.starts.partition_point(|&start| start <= range.start);
+        let last = self.starts.partition_point(|&start| start <= range.end);
+        let shift = |start: usize| start - range.end + range.start + text.len();
+        let tail: Vec<usize> = self.starts[last..].iter().map(|&start| shift(start)).collect();
+        self.starts.truncate(first);
+        self.starts.extend(text.bytes().enumerate().filter(|&(_, b)| b == b'\n').map(|(i, _)| range.start + i + 1));
+        self.starts.extend(tail);
+    }
+
+    pub fn offset(&self, (line, col): (usize, usize)) -> usize {
+        self.line_start(line).saturating_add(col)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TextLines {
+    lines: LineMap<String>,
+    index: Arc<LineIndex>,
+    bytes: usize,
+}
+
+impl TextLines {
+    pub fn new(rows: Vec<String>) -> Self {
+        let mut starts = Vec::with_capacity(rows.len());
+        let mut bytes = 0;
+        for row in &rows {
+            starts.push(bytes);
+            bytes += row.len();
+        }
+        let mut lines = LineMap::default();
+        lines.splice(0..0, rows);
+        Self { lines, index: Arc::new(LineIndex { starts }), bytes }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn index(&self) -> &LineIndex {
+        &self.index
+    }
+
+    pub fn splice(&mut self, lines: Range<usize>, rows: Vec<String>) {
+        let start = self.index.line_start(lines.start).min(self.bytes);
+        let end = self.index.line_start(lines.end).min(self.bytes);
+        let text = rows.concat();
+        Arc::make_mut(&mut self.index).splice(start..end, &text);
+        self.bytes = self.bytes - (end - start) + text.len();
+        self.lines.splice(lines, rows);
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.bytes);
+        self.lines.iter().for_each(|line| text.push_str(line));
+        text
+    }
+
+    pub fn search(&self, regex: &Regex, scope: Option<Range<(usize, usize)>>, cancel: &AtomicBool) -> Option<FindResults> {
+        if matches_across_lines(regex) {
+            let text = self.text();
+            let scope = scope.map(|scope| self.index.offset(scope.start)..self.index.offset(scope.end));
+            return search_text(regex, &text, &self.index, scope, cancel);
+        }
+        let mut matches = Vec::new();
+        let mut truncated = false;
+        'lines: for (line, text) in self.lines.iter().enumerate() {
+            if line % 1024 == 0 && cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            for m in regex.find_iter(text) {
+                if m.is_empty() || scope.as_ref().is_some_and(|r| (line, m.start()) < r.start || (line, m.end()) > r.end) {
+                    continue;
+                }
+                if matches.len() == MAX_FIND_MATCHES {
+                    truncated = true;
+                    break 'lines;
+                }
+                matches.push(FindMatch { line, col: m.start(), end_line: line, end_col: m.end() });
+            }
+        }
+        Some(FindResults { matches: Arc::new(matches), truncated })
+    }
+}
+
+// Whether `regex` can match a line break or anchor on the whole text, so it
+// cannot be run one line at a time.
+fn matches_across_lines(regex: &Regex) -> bool {
+    fn spans(hir: &Hir) -> bool {
+        match hir.kind() {
+            HirKind::Empty => false,
+            HirKind::Literal(literal) => literal.0.contains(&b'\n'),
+            HirKind::Class(Class::Unicode(class)) => class.ranges().iter().any(|r| r.start() <= '\n' && '\n' <= r.end()),
+            HirKind::Class(Class::Bytes(class)) => class.ranges().iter().any(|r| r.start() <= b'\n' && b'\n' <= r.end()),
+            HirKind::Look(look) => matches!(look, Look::Start | Look::End),
+            HirKind::Repetition(repetition) => spans(&repetition.sub),
+            HirKind::Capture(capture) => spans(&capture.sub),
+            HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(spans),
+        }
+    }
+    ParserBuilder::new().multi_line(true).build().parse(regex.as_str()).map_or(true, |hir| spans(&hir))
+}
+
+pub async fn search_in_background(
+    regex: Regex,
+    lines: TextLines,
+    scope: Option<Range<(usize, usize)>>,
+    cancel: Arc<AtomicBool>,
+) -> Option<FindResults> {
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(lines.search(&regex, scope, &cancel));
+    });
+    rx.await.ok().flatten()
+}
+
+pub fn search_text(
+    regex: &Regex,
+    text: &str,
+    index: &LineIndex,
+    scope: Option<Range<usize>>,
+    cancel: &AtomicBool,
+) -> Option<FindResults> {
+    let mut matches = Vec::new();
+    let mut truncated = false;
+    for m in regex.find_iter(text) {
+        if matches.len() % 1024 == 0 && cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        if m.is_empty() || scope.as_ref().is_some_and(|r| m.start() < r.start || m.end() > r.end) {
+            continue;
+        }
+        if matches.len() == MAX_FIND_MATCHES {
+            truncated = true;
+            break;
+        }
+        let (line, col) = index.position(m.start());
+        let (end_line, end_col) = index.position(m.end());
+        matches.push(FindMatch { line, col, end_line, end_col });
+    }
+    Some(FindResults { matches: Arc::new(matches), truncated })
+}
+
+pub fn char_boundary(line: &str, byte_col: usize) -> usize {
+    let mut col = byte_col.min(line.len());
+    while !line.is_char_boundary(col) {
+        col -= 1;
+    }
+    col
+}
+
+fn scroll_to_result_row(row: usize) -> Task<Message> {
+    let y = row as f32 * RESULT_ROW_HEIGHT - (RESULTS_HEIGHT - RESULT_ROW_HEIGHT) / 2.0;
+    operation::scroll_to(FIND_RESULTS_SCROLL_ID, AbsoluteOffset { x: None, y: Some(y.max(0.0)) })
+}
+
+fn result_groups(matches: &[FindMatch]) -> Vec<(usize, Range<usize>)> {
+    let mut groups: Vec<(usize, Range<usize>)> = Vec::new();
+    for (i, m) in matches.iter().enumerate() {
+        match groups.last_mut() {
+            Some((line, range)) if *line == m.line => range.end = i + 1,
+            _ => groups.push((m.line, i..i + 1)),
+        }
+    }
+    groups
+}
+
+pub fn grapheme_column(line: &str, byte_col: usize) -> usize {
+    line[..char_boundary(line, byte_col)].graphemes(true).count()
+}
+
+pub fn build_search_regex(query: &str, case_sensitive: bool, use_regex: bool, whole_word: bool) -> Result<Regex, regex::Error> {
+    let pattern = if use_regex { query.to_string() } else { regex::escape(query) };
+    let pattern = if whole_word { format!(r"\b(?:{})\b", pattern) } else { pattern };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .multi_line(true)
+        .build()
+}
+
+pub fn match_byte_range(text: &str, m: &FindMatch) -> Range<usize> {
+    let mut offset = 0;
+    let mut start = None;
+    for (i, line) in text.split('\n').enumerate() {
+        if i == m.line {
+            start = Some(offset + m.col.min(line.len()));
+        }
+        if i == m.end_line {
+            let end = offset + m.end_col.min(line.len());
+            return start.unwrap_or(end)..end;
+        }
+        offset += line.len() + 1;
+    }
+    text.len()..text.len()
+}
+
+pub fn expand_replacement(regex: &Regex, text: &str, start: usize, template: &str, use_regex: bool) -> String {
+    if !use_regex {
+        return template.to_string();
+    }
+    let mut result = String::new();
+    if let Some(caps) = regex.captures_at(text, start) {
+        caps.expand(template, &mut result);
+    }
+    result
+}
+
+pub fn replace_all_matches(
+    regex: &Regex,
+    text: &str,
+    template: &str,
+    use_regex: bool,
+    scope: Option<Range<usize>>,
+) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in regex.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        if m.is_empty() || scope.as_ref().is_some_and(|r| m.start() < r.start || m.end() > r.end) {
+            continue;
+        }
+        result.push_str(&text[last..m.start()]);
+        if use_regex {
+            caps.expand(template, &mut result);
+        } else {
+            result.push_str(template);
+        }
+        last = m.end();
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
 impl App {
-    pub fn find_all_matches(&mut self) {
-        self.find_matches.clear();
+    pub fn search_regex(&self) -> Result<Regex, regex::Error> {
+        build_search_regex(&self.find_query, self.case_sensitive, self.use_regex, self.whole_word)
+    }
+
+    pub fn active_find_scope(&self, index: &LineIndex) -> Option<Range<usize>> {
+        let scope = self.find_scope.as_ref().filter(|_| self.find_in_selection)?;
+        Some(index.offset(scope.start)..index.offset(scope.end))
+    }
+
+    fn find_scope_positions(&self) -> Option<Range<(usize, usize)>> {
+        self.find_scope.clone().filter(|_| self.find_in_selection)
+    }
+
+    pub fn cancel_find(&mut self) {
+        self.find_cancel.store(true, Ordering::Relaxed);
+        self.find_generation += 1;
+        self.find_pending = false;
+    }
+
+    pub fn clear_find(&mut self) {
+        self.cancel_find();
+        self.find_tick = None;
+        self.find_matches = Arc::default();
+        self.find_truncated = false;
         self.current_match = None;
+        self.find_results_focused = false;
+        self.selected_result = None;
+    }
+
+    fn prepare_find(&mut self) -> Option<Regex> {
+        self.cancel_find();
+        self.find_error = None;
+        self.find_tick = Some(self.changedtick);
 
         if self.find_query.is_empty() {
+            self.clear_find();
+            return None;
+        }
+
+        match self.search_regex() {
+            Ok(regex) => Some(regex),
+            Err(err) => {
+                self.clear_find();
+                self.find_error = Some(err.to_string());
+                None
+            }
+        }
+    }
+
+    pub fn line_with_ending(&self, line: usize, line_count: usize) -> String {
+        let Some(l) = self.content.line(line) else { return String::new() };
+        let ending = match l.ending {
+            _ if line + 1 == line_count => LineEnding::None,
+            LineEnding::None => LineEnding::default(),
+            ending => ending,
+        };
+        format!("{}{}", l.text, ending.as_str())
+    }
+
+    pub fn refresh_text_lines(&mut self, edit: Option<RowEdit>) {
+        if self.text_lines_tick.is_none_or(|tick| tick == self.changedtick) {
             return;
         }
+        let Some(edit) = edit.filter(|edit| edit.applies_to(self.text_lines_tick)) else {
+            self.rebuild_text_lines();
+            return;
+        };
+        let line_count = self.content.line_count();
+        let rows = (edit.from..edit.new_end).map(|i| self.line_with_ending(i, line_count)).collect();
+        self.text_lines.splice(edit.from..edit.old_end, rows);
+        self.text_lines_tick = Some(self.changedtick);
+    }
 
-        let content_text = self.content.text();
-        let (haystack, needle) = if self.case_sensitive {
-            (content_text.clone(), self.find_query.clone())
-        } else {
-            (content_text.to_lowercase(), self.find_query.to_lowercase())
+    fn rebuild_text_lines(&mut self) {
+        let line_count = self.content.line_count();
+        let rows = (0..line_count).map(|i| self.line_with_ending(i, line_count)).collect();
+        self.text_lines = TextLines::new(rows);
+        self.text_lines_tick = Some(self.changedtick);
+    }
+
+    pub fn text_snapshot(&mut self) -> TextLines {
+        if self.text_lines_tick != Some(self.changedtick) {
+            self.rebuild_text_lines();
+        }
+        self.text_lines.clone()
+    }
+
+    pub fn apply_find_results(&mut self, results: FindResults) {
+        self.find_pending = false;
+        self.find_matches = results.matches;
+        self.find_truncated = results.truncated;
+        self.current_match = None;
+    }
+
+    pub fn find_all_matches_now(&mut self) {
+        let Some(regex) = self.prepare_find() else { return };
+        let scope = self.find_scope_positions();
+        if let Some(results) = self.text_snapshot().search(&regex, scope, &AtomicBool::new(false)) {
+            self.apply_find_results(results);
+        }
+    }
+
+    pub fn find_all_matches(&mut self) -> Task<Message> {
+        let Some(regex) = self.prepare_find() else { return Task::none() };
+        let lines = self.text_snapshot();
+        let scope = self.find_scope_positions();
+        if lines.bytes() < BACKGROUND_SEARCH_BYTES {
+            if let Some(results) = lines.search(&regex, scope, &AtomicBool::new(false)) {
+                self.apply_find_results(results);
+            }
+            return Task::none();
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.find_cancel = cancel.clone();
+        self.find_pending = true;
+        let generation = self.find_generation;
+        Task::perform(
+            search_in_background(regex, lines, scope, cancel),
+            move |results| Message::FindResultsReady(generation, results),
+        )
+    }
+
+    pub fn cancel_vim_search(&mut self) {
+        self.vim_search_cancel.store(true, Ordering::Relaxed);
+        self.vim_search_generation += 1;
+        self.vim_search_pending = false;
+    }
+
+    pub fn start_vim_search(&mut self, pattern: &str) -> Task<Message> {
+        self.cancel_vim_search();
+        self.vim_search_tick = Some(self.changedtick);
+        let Ok(regex) = build_search_regex(pattern, false, true, false) else {
+            self.vim_search_matches = Arc::default();
+            return Task::none();
         };
+        let lines = self.text_snapshot();
+        if lines.bytes() < BACKGROUND_SEARCH_BYTES {
+            let results = lines.search(&regex, None, &AtomicBool::new(false));
+            self.vim_search_matches = results.map(|r| r.matches).unwrap_or_default();
+            return Task::none();
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.vim_search_cancel = cancel.clone();
+        self.vim_search_pending = true;
+        let generation = self.vim_search_generation;
+        Task::perform(
+            search_in_background(regex, lines, None, cancel),
+            move |results| Message::VimSearchResults(generation, results),
+        )
+    }
 
-        let mut start = 0;
-        while let Some(pos) = haystack[start..].find(&needle) {
-            let abs_pos = start + pos;
-            let line = content_text[..abs_pos].matches('\n').count();
-            let line_start = if line == 0 {
-                0
-            } else {
-                content_text[..abs_pos].rfind('\n').unwrap() + 1
-            };
-            let col = abs_pos - line_start;
-            self.find_matches.push((line, col));
-            start = abs_pos + 1;
+    pub fn refresh_vim_search(&mut self) -> Task<Message> {
+        if !self.vim_hlsearch
+            || self.vim_search_pattern.is_empty()
+            || self.vim_mode == VimMode::Search
+            || self.vim_search_tick == Some(self.changedtick)
+        {
+            return Task::none();
+        }
+        self.start_vim_search(&self.vim_search_pattern.clone())
+    }
+
+    pub fn ensure_vim_search_matches(&mut self) {
+        if self.vim_search_tick == Some(self.changedtick) && !self.vim_search_pending {
+            return;
+        }
+        self.cancel_vim_search();
+        let regex = build_search_regex(&self.vim_search_pattern, false, true, false);
+        let results = regex.ok().and_then(|regex| self.text_snapshot().search(&regex, None, &AtomicBool::new(false)));
+        self.vim_search_matches = results.map(|r| r.matches).unwrap_or_default();
+        self.vim_search_tick = Some(self.changedtick);
+    }
+
+    // Occurrences of the word under the cursor are looked up once the cursor
+    // has rested for WORD_HIGHLIGHT_DELAY; edits drop them straight away since
+    // their positions go stale, plain moves keep them until the lookup reruns
+    // and the lookup is skipped while the cursor stays on the same word.
+    pub fn track_word_under_cursor(&mut self) {
+        if !self.highlight_word {
+            self.clear_word_matches();
+            self.word_key = None;
+            self.word_due = None;
+            return;
+        }
+        let position = self.content.cursor().position;
+        let key = (self.changedtick, position.line, position.column);
+        if self.word_key == Some(key) {
+            return;
+        }
+        if self.word_key.is_some_and(|(tick, _, _)| tick != self.changedtick) {
+            self.clear_word_matches();
+        }
+        self.word_key = Some(key);
+        self.word_due = Some(Instant::now() + WORD_HIGHLIGHT_DELAY);
+    }
+
+    fn clear_word_matches(&mut self) {
+        self.cancel_word_search();
+        self.word_searched = None;
+        if !self.word_matches.is_empty() {
+            self.word_matches = Arc::default();
+        }
+    }
+
+    fn cancel_word_search(&mut self) {
+        self.word_cancel.store(true, Ordering::Relaxed);
+        self.word_generation += 1;
+    }
+
+    pub fn refresh_word_highlight(&mut self) -> Task<Message> {
+        if self.word_due.is_none_or(|due| Instant::now() < due) {
+            return Task::none();
+        }
+        self.word_due = None;
+        let word = self.vim_word_under_cursor();
+        let is_identifier = !word.is_empty() && !word.starts_with(|c: char| c.is_ascii_digit());
+        let regex = build_search_regex(&word, true, false, true);
+        let Some(regex) = regex.ok().filter(|_| is_identifier && self.content.cursor().selection.is_none()) else {
+            self.clear_word_matches();
+            return Task::none();
+        };
+        let searched = Some((self.changedtick, word));
+        if self.word_searched == searched {
+            return Task::none();
+        }
+        self.cancel_word_search();
+        self.word_searched = searched;
+        let lines = self.text_snapshot();
+        if lines.bytes() < BACKGROUND_SEARCH_BYTES {
+            let results = lines.search(&regex, None, &AtomicBool::new(false));
+            self.word_matches = results.map(|r| r.matches).unwrap_or_default();
+            return Task::none();
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.word_cancel = cancel.clone();
+        let generation = self.word_generation;
+        Task::perform(
+            search_in_background(regex, lines, None, cancel),
+            move |results| Message::WordMatchesReady(generation, results),
+        )
+    }
+
+    pub fn vim_match_from(&self, from: (usize, usize), forward: bool) -> Option<(usize, bool)> {
+        let matches = &self.vim_search_matches;
+        if matches.is_empty() {
+            return None;
+        }
+        if forward {
+            let i = matches.partition_point(|m| (m.line, m.col) <= from);
+            Some(if i < matches.len() { (i, false) } else { (0, true) })
+        } else {
+            let i = matches.partition_point(|m| (m.line, m.col) < from);
+            Some(if i > 0 { (i - 1, false) } else { (matches.len() - 1, true) })
         }
     }
 
     pub fn navigate_to_match(&mut self, index: usize) {
-        if let Some(&(line, col)) = self.find_matches.get(index) {
+        if let Some(m) = self.find_matches.get(index).copied() {
             self.current_match = Some(index);
-            let end_col = col + self.find_query.len();
+            let column = |line: usize, col: usize| {
+                self.content.line(line).map_or(0, |l| char_boundary(&l.text, col))
+            };
             self.content.move_to(text_editor::Cursor {
-                position: text_editor::Position { line, column: col },
+                position: text_editor::Position { line: m.line, column: column(m.line, m.col) },
                 selection: Some(text_editor::Position {
-                    line,
-                    column: end_col,
+                    line: m.end_line,
+                    column: column(m.end_line, m.end_col),
                 }),
             });
         }
     }
 
+    pub fn scroll_find_results(&self) -> Task<Message> {
+        let Some(current) = self.current_match.filter(|_| self.show_find_results) else {
+            return Task::none();
+        };
+        let row = result_groups(&self.find_matches).partition_point(|(_, range)| range.end <= current);
+        scroll_to_result_row(row)
+    }
+
+    pub fn focus_find_results(&mut self) -> Task<Message> {
+        if !self.show_panel || !self.show_find_results || self.find_matches.is_empty() {
+            return Task::none();
+        }
+        let current = self.current_match.unwrap_or(0);
+        let row = result_groups(&self.find_matches).partition_point(|(_, range)| range.end <= current);
+        self.find_results_focused = true;
+        self.selected_result = Some(row.min(MAX_RESULT_ROWS - 1));
+        Task::batch([operation::focus(FIND_RESULTS_SCROLL_ID), self.move_result_selection(0)])
+    }
+
+    pub fn move_result_selection(&mut self, delta: i32) -> Task<Message> {
+        let rows = result_groups(&self.find_matches).len().min(MAX_RESULT_ROWS);
+        if rows == 0 {
+            self.selected_result = None;
+            return Task::none();
+        }
+        let row = self.selected_result.map_or(0, |row| row.saturating_add_signed(delta as isize).min(rows - 1));
+        self.selected_result = Some(row);
+        scroll_to_result_row(row)
+    }
+
+    pub fn selected_result_match(&self) -> Option<usize> {
+        let row = self.selected_result.filter(|&row| row < MAX_RESULT_ROWS)?;
+        result_groups(&self.find_matches).get(row).map(|(_, range)| range.start)
+    }
+
+    pub fn search_results(&self) -> Element<'_, Message> {
+        let groups = result_groups(&self.find_matches);
+        let line_text = |line: usize| self.content.line(line).map(|l| l.text.into_owned());
+        let palette = self.editor_theme();
+        let dim = palette.muted;
+        let selected = self.selected_result.filter(|_| self.find_results_focused);
+        let rows = groups.iter().take(MAX_RESULT_ROWS).enumerate().map(|(row, &(line, ref range))| {
+            let current = self.current_match.is_some_and(|i| range.contains(&i));
+            let selected = selected == Some(row);
+            let content = line_text(line).unwrap_or_default();
+            let mut spans: Vec<Span<'_>> = Vec::new();
+            let mut last = 0;
+            for m in &self.find_matches[range.clone()] {
+                let start = char_boundary(&content, m.col).max(last);
+                let end = if m.end_line == line { char_boundary(&content, m.end_col) } else { content.len() };
+                if end <= start {
+                    continue;
+                }
+                spans.push(span(content[last..start].to_string()));
+                spans.push(span(content[start..end].to_string()).background(palette.find_match.scale_alpha(0.45)));
+                last = end;
+            }
+            spans.push(span(content[last..].to_string()));
+            let context = |line: Option<usize>| {
+                text(line.and_then(line_text).unwrap_or_default())
+                    .size(12)
+                    .font(Font::MONOSPACE)
+                    .wrapping(Wrapping::None)
+                    .color(dim)
+            };
+            let body = column![
+                context(line.checked_sub(1)),
+                rich_text(spans).size(12).font(Font::MONOSPACE).wrapping(Wrapping::None),
+                context(Some(line + 1)),
+            ];
+            let entry = row![
+                text(format!("{:>5}", line + 1)).size(12).font(Font::MONOSPACE).color(dim),
+                container(body).clip(true).width(Length::Fill),
+            ]
+            .spacing(8);
+            button(entry)
+                .on_press(Message::FindResultSelected(range.start))
+                .padding([2, 6])
+                .width(Length::Fill)
+                .height(RESULT_ROW_HEIGHT)
+                .style(move |theme: &Theme, status| {
+                    let palette = theme.extended_palette();
+                    let background = match status {
+                        _ if selected => Some(palette.primary.base.color.scale_alpha(0.55).into()),
+                        _ if current => Some(palette.primary.weak.color.scale_alpha(0.4).into()),
+                        button::Status::Hovered => Some(palette.background.strong.color.into()),
+                        _ => None,
+                    };
+                    button::Style {
+                        background,
+                        text_color: palette.background.base.text,
+                        ..Default::default()
+                    }
+                })
+                .into()
+        });
+        let mut list = column(rows);
+        if groups.len() > MAX_RESULT_ROWS {
+            list = list.push(
+                text(format!("{} more lines not shown", groups.len() - MAX_RESULT_ROWS)).size(12).color(dim),
+            );
+        }
+        container(scrollable(list).id(FIND_RESULTS_SCROLL_ID).height(RESULTS_HEIGHT))
+            .padding([0, 8])
+            .style(|theme: &Theme| container::Style {
+                background: Some(theme.extended_palette().background.base.color.into()),
+                ..Default::default()
+            })
+            .into()
+    }
+
     pub fn search_panel(&self) -> Element<'_, Message> {
         let match_info = if self.find_query.is_empty() {
             String::new()
+        } else if self.find_error.is_some() {
+            String::from("Invalid pattern")
+        } else if self.find_pending {
+            String::from("Searching...")
         } else if self.find_matches.is_empty() {
             String::from("No matches")
         } else {
             let idx = self.current_match.map(|i| i + 1).unwrap_or(0);
-            format!("{}/{}", idx, self.find_matches.len())
+            let more = if self.find_truncated { "+" } else { "" };
+            format!("{}/{}{}", idx, self.find_matches.len(), more)
         };
 
+        let has_error = self.find_error.is_some();
         let line_count = self.content.line_count();
 
         container(
@@ -67,12 +741,27 @@ impl App {
                 row![
                     text("Find:").size(14).width(60),
                     text_input("Search...", &self.find_query)
+                        .id(FIND_INPUT_ID)
                         .size(14)
                         .on_input(Message::FindQueryChanged)
                         .on_submit(Message::FindNext)
-                        .width(Length::Fill),
-                    text(match_info).size(12).width(80),
+                        .width(Length::Fill)
+                        .style(move |theme: &Theme, status| {
+                            let mut style = text_input::default(theme, status);
+                            if has_error {
+                                style.border.color = theme.extended_palette().danger.base.color;
+                                style.border.width = 1.0;
+                            }
+                            style
+                        }),
+                    text(match_info).size(12).width(80).style(move |theme: &Theme| text::Style {
+                        color: has_error.then(|| theme.extended_palette().danger.base.color),
+                    }),
                     checkbox(self.case_sensitive).label("Aa").on_toggle(Message::ToggleCaseSensitive).size(14),
+                    checkbox(self.use_regex).label(".*").on_toggle(Message::ToggleRegex).size(14),
+                    checkbox(self.whole_word).label("Word").on_toggle(Message::ToggleWholeWord).size(14),
+                    checkbox(self.find_in_selection).label("In Sel").on_toggle(Message::ToggleInSelection).size(14),
+                    checkbox(self.show_find_results).label("List").on_toggle(Message::ToggleFindResults).size(14),
                     dialog_button("Find Next", Message::FindNext),
                     dialog_button("Find Prev", Message::FindPrevious),
                 ]
@@ -81,6 +770,7 @@ impl App {
                 row![
                     text("Replace:").size(14).width(60),
                     text_input("Replace with...", &self.replace_text)
+                        .id(REPLACE_INPUT_ID)
                         .size(14)
                         .on_input(Message::ReplaceTextChanged)
                         .width(Length::Fill),
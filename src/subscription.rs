@@ -17,6 +17,7 @@ struct AppSubscription {
     vim_operator: Option<char>,
     vim_awaits_char: bool,
     undo_panel_focused: bool,
+    find_results_focused: bool,
 }
 
 impl Recipe for AppSubscription {
@@ -28,6 +29,7 @@ impl Recipe for AppSubscription {
         self.vim_operator.hash(state);
         self.vim_awaits_char.hash(state);
         self.undo_panel_focused.hash(state);
+        self.find_results_focused.hash(state);
     }
 
     fn stream(self: Box<Self>, input: EventStream) -> iced::futures::stream::BoxStream<'static, Message> {
@@ -36,16 +38,17 @@ impl Recipe for AppSubscription {
         let vim_operator = self.vim_operator;
         let vim_awaits_char = self.vim_awaits_char;
         let undo_panel_focused = self.undo_panel_focused;
+        let find_results_focused = self.find_results_focused;
         input
             .filter_map(move |raw_event| {
-                let msg = handle_event(raw_event, vim_enabled, vim_mode.clone(), vim_operator, vim_awaits_char, undo_panel_focused);
+                let msg = handle_event(raw_event, vim_enabled, vim_mode.clone(), vim_operator, vim_awaits_char, undo_panel_focused, find_results_focused);
                 std::future::ready(msg)
             })
             .boxed()
     }
 }
 
-fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: VimMode, vim_operator: Option<char>, vim_awaits_char: bool, undo_panel_focused: bool) -> Option<Message> {
+fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: VimMode, vim_operator: Option<char>, vim_awaits_char: bool, undo_panel_focused: bool, find_results_focused: bool) -> Option<Message> {
     let subscription::Event::Interaction { event, status, .. } = raw_event else {
         return None;
     };
@@ -68,9 +71,7 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
             return Some(Message::CtrlReleased);
         }
         Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
-            if !modifiers.control() {
-                return Some(Message::CtrlReleased);
-            }
+            return Some(Message::ModifiersChanged(*modifiers));
         }
         _ => {}
     }
@@ -99,6 +100,35 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
             if let keyboard::Key::Named(keyboard::key::Named::F6) = key.as_ref() {
                 return Some(Message::ToggleVim);
             }
+            if let keyboard::Key::Named(keyboard::key::Named::F4) = key.as_ref() {
+                return Some(Message::FindResultsFocusToggle);
+            }
+        }
+
+        if find_results_focused && modifiers.is_empty() {
+            match key.as_ref() {
+                keyboard::Key::Named(keyboard::key::Named::ArrowUp) => return Some(Message::FindResultsMove(-1)),
+                keyboard::Key::Named(keyboard::key::Named::ArrowDown) => return Some(Message::FindResultsMove(1)),
+                keyboard::Key::Named(keyboard::key::Named::Enter) => return Some(Message::FindResultsConfirm),
+                keyboard::Key::Named(keyboard::key::Named::Escape) => return Some(Message::FindResultsFocusToggle),
+                _ => {}
+            }
+        }
+
+        if modifiers.is_empty() && !matches!(status, event::Status::Captured) {
+            match key.as_ref() {
+                keyboard::Key::Named(keyboard::key::Named::ArrowUp) => return Some(Message::HistoryKey(-1)),
+                keyboard::Key::Named(keyboard::key::Named::ArrowDown) => return Some(Message::HistoryKey(1)),
+                _ => {}
+            }
+        }
+
+        if vim_enabled && vim_mode == VimMode::Confirm {
+            return match modified_key.as_ref() {
+                keyboard::Key::Named(keyboard::key::Named::Escape) => Some(Message::VimConfirm('q')),
+                keyboard::Key::Character(ch) if !modifiers.control() => ch.chars().next().map(Message::VimConfirm),
+                _ => None,
+            };
         }
 
         if vim_enabled && vim_mode == VimMode::Normal {
@@ -109,21 +139,34 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
                     keyboard::Key::Character("r") => return Some(Message::VimKey('\x12')),
                     keyboard::Key::Character("t") => return Some(Message::ToggleUndoPanel),
                     keyboard::Key::Character("w") => return Some(Message::UndoPanelFocusToggle),
+                    keyboard::Key::Character("M") => return Some(Message::TogglePreview),
                     _ => {}
                 }
                 return None;
             }
-            if modifiers.is_empty() {
-                if undo_panel_focused {
-                    match key.as_ref() {
-                        keyboard::Key::Character("j") => return Some(Message::UndoPanelMoveSelection(1)),
-                        keyboard::Key::Character("k") => return Some(Message::UndoPanelMoveSelection(-1)),
-                        keyboard::Key::Named(keyboard::key::Named::Enter) => return Some(Message::UndoPanelConfirm),
-                        keyboard::Key::Named(keyboard::key::Named::Escape) => return Some(Message::UndoPanelFocusToggle),
-                        _ => {}
-                    }
+            if undo_panel_focused && !modifiers.alt() {
+                if matches!(status, event::Status::Captured) {
                     return None;
                 }
+                match modified_key.as_ref() {
+                    keyboard::Key::Character("j") => return Some(Message::UndoPanelMoveSelection(1)),
+                    keyboard::Key::Character("k") => return Some(Message::UndoPanelMoveSelection(-1)),
+                    keyboard::Key::Character("h") => return Some(Message::UndoPanelMoveSibling(-1)),
+                    keyboard::Key::Character("l") => return Some(Message::UndoPanelMoveSibling(1)),
+                    keyboard::Key::Character("c") => return Some(Message::UndoPanelToggleCollapse),
+                    keyboard::Key::Character("C") => return Some(Message::UndoPanelCollapseInactive),
+                    keyboard::Key::Character("+") | keyboard::Key::Character("=") => return Some(Message::UndoPanelZoom(1)),
+                    keyboard::Key::Character("-") => return Some(Message::UndoPanelZoom(-1)),
+                    keyboard::Key::Character("/") => return Some(Message::UndoPanelFocusFilter),
+                    keyboard::Key::Character("n") => return Some(Message::UndoSearchStep(1)),
+                    keyboard::Key::Character("N") => return Some(Message::UndoSearchStep(-1)),
+                    keyboard::Key::Named(keyboard::key::Named::Enter) => return Some(Message::UndoPanelConfirm),
+                    keyboard::Key::Named(keyboard::key::Named::Escape) => return Some(Message::UndoPanelFocusToggle),
+                    _ => {}
+                }
+                return None;
+            }
+            if modifiers.is_empty() {
                 match key.as_ref() {
                     keyboard::Key::Named(keyboard::key::Named::Escape) => {
                         return Some(Message::VimEnterNormal);
@@ -160,6 +203,8 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
                                 'J' => Some(Message::VimKey('J')),
                                 'D' => Some(Message::VimKey('D')),
                                 'C' => Some(Message::VimKey('C')),
+                                '>' => Some(Message::VimKey('>')),
+                                '<' => Some(Message::VimKey('<')),
                                 'V' => Some(Message::VimEnterVisualLine),
                                 ':' => Some(Message::VimEnterCommand),
                                 '?' => Some(Message::VimEnterSearch(false)),
@@ -229,6 +274,13 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
             }
         }
 
+        if !vim_enabled
+            && modifiers.is_empty()
+            && let keyboard::Key::Named(keyboard::key::Named::Escape) = key.as_ref()
+        {
+            return Some(Message::ClearCursors);
+        }
+
         if matches!(status, event::Status::Captured) {
             return None;
         }
@@ -236,6 +288,8 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
         if modifiers.control() && modifiers.shift() {
             match key.as_ref() {
                 keyboard::Key::Character("S") => return Some(Message::SaveAs),
+                keyboard::Key::Character("L") => return Some(Message::CursorsFromMatches),
+                keyboard::Key::Character("M") => return Some(Message::TogglePreview),
                 _ => {}
             }
         }
@@ -249,6 +303,9 @@ fn handle_event(raw_event: subscription::Event, vim_enabled: bool, vim_mode: Vim
                 keyboard::Key::Character("h") => return Some(Message::TogglePanel),
                 keyboard::Key::Character("g") => return Some(Message::TogglePanel),
                 keyboard::Key::Character("u") => return Some(Message::ToggleUndoPanel),
+                keyboard::Key::Character("d") => return Some(Message::AddNextOccurrence),
+                #[cfg(feature = "tree-sitter")]
+                keyboard::Key::Character("w") => return Some(Message::ExpandSelection),
                 _ => {}
             }
         }
@@ -287,14 +344,18 @@ impl App {
             vim_operator: self.vim_operator,
             vim_awaits_char,
             undo_panel_focused: self.undo_panel_focused,
+            find_results_focused: self.find_results_focused,
         });
+        let mut subs = vec![event_sub];
         if self.show_undo_panel {
-            Subscription::batch([
-                event_sub,
-                time::every(std::time::Duration::from_secs(1)).map(|_| Message::Tick),
-            ])
-        } else {
-            event_sub
+            subs.push(time::every(std::time::Duration::from_secs(1)).map(|_| Message::Tick));
+        }
+        if self.word_due.is_some() {
+            subs.push(time::every(std::time::Duration::from_millis(100)).map(|_| Message::Tick));
+        }
+        if self.follow_system_theme {
+            subs.push(iced::system::theme_changes().map(Message::SystemThemeChanged));
         }
+        Subscription::batch(subs)
     }
 }
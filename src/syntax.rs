@@ -0,0 +1,841 @@
+use iced::advanced::text::highlighter::Highlighter;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::line_map::LineMap;
+use crate::search::char_boundary;
+
+pub type LineSpans = LineMap<Vec<(Range<usize>, Token)>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Json,
+    Toml,
+    Yaml,
+    Markdown,
+    Shell,
+    Html,
+    Css,
+}
+
+impl Language {
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Rust => "Rust",
+            Language::Python => "Python",
+            Language::JavaScript => "JavaScript",
+            Language::TypeScript => "TypeScript",
+            Language::Json => "JSON",
+            Language::Toml => "TOML",
+            Language::Yaml => "YAML",
+            Language::Markdown => "Markdown",
+            Language::Shell => "Shell",
+            Language::Html => "HTML",
+            Language::Css => "CSS",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => Language::Rust,
+            "python" | "python3" | "py" => Language::Python,
+            "javascript" | "js" | "mjs" | "cjs" | "jsx" | "node" => Language::JavaScript,
+            "typescript" | "ts" | "tsx" | "mts" | "cts" => Language::TypeScript,
+            "json" | "jsonc" => Language::Json,
+            "toml" => Language::Toml,
+            "yaml" | "yml" => Language::Yaml,
+            "markdown" | "md" | "mkd" => Language::Markdown,
+            "sh" | "bash" | "zsh" | "ksh" | "dash" | "shell" => Language::Shell,
+            "html" | "htm" | "xhtml" | "xml" | "svg" => Language::Html,
+            "css" => Language::Css,
+            _ => return None,
+        })
+    }
+
+    pub fn detect(path: Option<&Path>, text: &str) -> Option<Self> {
+        let lines: Vec<&str> = text.lines().collect();
+        let edges = lines.iter().take(5).chain(lines.iter().skip(5).rev().take(5));
+        if let Some(language) = edges.filter_map(|l| modeline(l)).next() {
+            return Some(language);
+        }
+        if let Some(language) = lines.first().and_then(|l| shebang(l)) {
+            return Some(language);
+        }
+        let path = path?;
+        let file_name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        match file_name.as_str() {
+            "cargo.lock" | "pipfile" => return Some(Language::Toml),
+            ".bashrc" | ".zshrc" | ".profile" | ".bash_profile" | "pkgbuild" => return Some(Language::Shell),
+            _ => {}
+        }
+        Language::from_name(&path.extension()?.to_string_lossy())
+    }
+}
+
+fn modeline(line: &str) -> Option<Language> {
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let body = &rest[..rest.find("-*-")?];
+        let mode = body
+            .split(';')
+            .find_map(|part| part.trim().strip_prefix("mode:"))
+            .unwrap_or(body);
+        return Language::from_name(mode.trim());
+    }
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|tag| line.find(tag).map(|i| i + tag.len()))?;
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|opt| {
+            let (key, value) = opt.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax" | "syn").then(|| Language::from_name(value)).flatten()
+        })
+}
+
+fn shebang(line: &str) -> Option<Language> {
+    let command = line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    let program = if program == "env" {
+        words.find(|w| !w.starts_with('-'))?
+    } else {
+        program
+    };
+    Language::from_name(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+        .or_else(|| Language::from_name(program))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    Keyword,
+    Type,
+    Function,
+    String,
+    Number,
+    Comment,
+    Constant,
+    Attribute,
+    Tag,
+    Key,
+    Heading,
+    Emphasis,
+    Code,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Mode {
+    #[default]
+    Normal,
+    BlockComment,
+    String(u8, bool),
+    Fence,
+    InTag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LineState {
+    mode: Mode,
+    depth: u16,
+}
+
+struct Spec {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [u8],
+    multiline_quotes: &'static [u8],
+    triple_quotes: bool,
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    capitalized_types: bool,
+}
+
+const RUST: Spec = Spec {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: b"\"",
+    multiline_quotes: b"\"",
+    triple_quotes: false,
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+        "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+        "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use", "where",
+        "while", "yield",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+        "i128", "isize", "f32", "f64",
+    ],
+    constants: &["true", "false", "None", "Some", "Ok", "Err"],
+    capitalized_types: true,
+};
+
+const PYTHON: Spec = Spec {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: b"\"'",
+    multiline_quotes: &[],
+    triple_quotes: true,
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+        "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+        "match", "case", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+        "yield", "self",
+    ],
+    types: &["int", "float", "str", "bytes", "bool", "list", "dict", "set", "tuple", "object"],
+    constants: &["True", "False", "None"],
+    capitalized_types: true,
+};
+
+const JAVASCRIPT: Spec = Spec {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: b"\"'`",
+    multiline_quotes: b"`",
+    triple_quotes: false,
+    keywords: &[
+        "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+        "delete", "do", "else", "export", "extends", "finally", "for", "from", "function", "get", "if",
+        "import", "in", "instanceof", "let", "new", "of", "return", "set", "static", "super", "switch",
+        "this", "throw", "try", "typeof", "var", "void", "while", "with", "yield",
+    ],
+    types: &[],
+    constants: &["true", "false", "null", "undefined", "NaN", "Infinity"],
+    capitalized_types: true,
+};
+
+const TYPESCRIPT: Spec = Spec {
+    keywords: &[
+        "abstract", "as", "async", "await", "break", "case", "catch", "class", "const", "continue",
+        "declare", "default", "delete", "do", "else", "enum", "export", "extends", "finally", "for",
+        "from", "function", "get", "if", "implements", "import", "in", "instanceof", "interface", "is",
+        "keyof", "let", "namespace", "new", "of", "private", "protected", "public", "readonly",
+        "return", "satisfies", "set", "static", "super", "switch", "this", "throw", "try", "type",
+        "typeof", "var", "void", "while", "yield",
+    ],
+    types: &["any", "boolean", "never", "number", "object", "string", "symbol", "unknown", "bigint"],
+    ..JAVASCRIPT
+};
+
+const JSON: Spec = Spec {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: b"\"",
+    multiline_quotes: &[],
+    triple_quotes: false,
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "null"],
+    capitalized_types: false,
+};
+
+const TOML: Spec = Spec {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: b"\"'",
+    multiline_quotes: &[],
+    triple_quotes: true,
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "inf", "nan"],
+    capitalized_types: false,
+};
+
+const YAML: Spec = Spec {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: b"\"'",
+    multiline_quotes: &[],
+    triple_quotes: false,
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "null", "yes", "no", "on", "off", "~"],
+    capitalized_types: false,
+};
+
+const SHELL: Spec = Spec {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: b"\"'",
+    multiline_quotes: b"\"'",
+    triple_quotes: false,
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in",
+        "function", "return", "local", "export", "readonly", "declare", "unset", "shift", "source",
+        "exit", "break", "continue", "select", "time",
+    ],
+    types: &[],
+    constants: &["true", "false"],
+    capitalized_types: false,
+};
+
+const CSS: Spec = Spec {
+    line_comments: &[],
+    block_comment: Some(("/*", "*/")),
+    quotes: b"\"'",
+    multiline_quotes: &[],
+    triple_quotes: false,
+    keywords: &["important", "inherit", "initial", "unset", "auto", "none"],
+    types: &[],
+    constants: &[],
+    capitalized_types: false,
+};
+
+fn spec(language: Language) -> &'static Spec {
+    match language {
+        Language::Rust => &RUST,
+        Language::Python => &PYTHON,
+        Language::JavaScript => &JAVASCRIPT,
+        Language::TypeScript => &TYPESCRIPT,
+        Language::Json => &JSON,
+        Language::Toml => &TOML,
+        Language::Yaml => &YAML,
+        Language::Shell => &SHELL,
+        Language::Css => &CSS,
+        Language::Markdown | Language::Html => &JSON,
+    }
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+}
+
+fn skip_spaces(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && (bytes[i] == b' ' || bytes[i] == b'\t') {
+        i += 1;
+    }
+    i
+}
+
+// Scans a string body starting at `i` (just past the opening delimiter) and
+// returns the end offset and whether the closing delimiter was found.
+fn scan_string(bytes: &[u8], mut i: usize, quote: u8, triple: bool, escapes: bool) -> (usize, bool) {
+    while i < bytes.len() {
+        if escapes && bytes[i] == b'\\' {
+            i += 2;
+            continue;
+        }
+        if bytes[i] == quote {
+            if !triple {
+                return (i + 1, true);
+            }
+            if bytes[i..].starts_with(&[quote; 3]) {
+                return (i + 3, true);
+            }
+        }
+        i += 1;
+    }
+    (bytes.len(), false)
+}
+
+pub fn highlight_line(language: Language, line: &str, state: LineState) -> (Vec<(Range<usize>, Token)>, LineState) {
+    match language {
+        Language::Markdown => markdown_line(line, state),
+        Language::Html => html_line(line, state),
+        _ => code_line(language, line, state),
+    }
+}
+
+fn code_line(language: Language, line: &str, mut state: LineState) -> (Vec<(Range<usize>, Token)>, LineState) {
+    let spec = spec(language);
+    let bytes = line.as_bytes();
+    let escapes = |quote: u8| !(quote == b'\'' && matches!(language, Language::Shell | Language::Toml));
+    let mut spans = Vec::new();
+    let mut i = 0;
+
+    match state.mode {
+        Mode::BlockComment => {
+            let (_, close) = spec.block_comment.unwrap_or(("/*", "*/"));
+            match line.find(close) {
+                Some(end) => {
+                    spans.push((0..end + close.len(), Token::Comment));
+                    i = end + close.len();
+                    state.mode = Mode::Normal;
+                }
+                None => return (vec![(0..line.len(), Token::Comment)], state),
+            }
+        }
+        Mode::String(quote, triple) => {
+            let (end, closed) = scan_string(bytes, 0, quote, triple, escapes(quote));
+            spans.push((0..end, Token::String));
+            if !closed {
+                return (spans, state);
+            }
+            i = end;
+            state.mode = Mode::Normal;
+        }
+        _ => {}
+    }
+
+    if language == Language::Toml {
+        let start = skip_spaces(bytes, i);
+        if bytes.get(start) == Some(&b'[') {
+            let end = line[start..].find(']').map_or(line.len(), |e| {
+                let e = start + e + 1;
+                if bytes.get(e) == Some(&b']') { e + 1 } else { e }
+            });
+            spans.push((start..end, Token::Heading));
+            i = end;
+        }
+    }
+    if language == Language::Yaml {
+        let mut start = skip_spaces(bytes, i);
+        if line[start..].starts_with("- ") {
+            spans.push((start..start + 1, Token::Keyword));
+            start = skip_spaces(bytes, start + 1);
+        }
+        if line[start..].starts_with("---") || line[start..].starts_with("...") {
+            spans.push((start..line.len(), Token::Keyword));
+            return (spans, state);
+        }
+        let key_end = line[start..].find(": ").or_else(|| line[start..].strip_suffix(':').map(str::len));
+        if let Some(len) = key_end
+            && !line[start..start + len].contains(['#', '"', '\'', '{', '['])
+            && len > 0
+        {
+            spans.push((start..start + len, Token::Key));
+            i = start + len;
+        }
+    }
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        let rest = &line[i..];
+
+        if spec.line_comments.iter().any(|c| rest.starts_with(c))
+            && (language != Language::Shell || i == 0 || bytes[i - 1].is_ascii_whitespace())
+        {
+            spans.push((i..line.len(), Token::Comment));
+            break;
+        }
+
+        if let Some((open, close)) = spec.block_comment
+            && rest.starts_with(open)
+        {
+            match rest[open.len()..].find(close) {
+                Some(end) => {
+                    let end = i + open.len() + end + close.len();
+                    spans.push((i..end, Token::Comment));
+                    i = end;
+                    continue;
+                }
+                None => {
+                    spans.push((i..line.len(), Token::Comment));
+                    state.mode = Mode::BlockComment;
+                    break;
+                }
+            }
+        }
+
+        if language == Language::Rust && b == b'#' && matches!(bytes.get(i + 1), Some(b'[') | Some(b'!')) {
+            let end = rest.find(']').map_or(line.len(), |e| i + e + 1);
+            spans.push((i..end, Token::Attribute));
+            i = end;
+            continue;
+        }
+
+        if language == Language::Rust && b == b'\'' {
+            let is_char = bytes.get(i + 1) == Some(&b'\\') || rest.chars().nth(2) == Some('\'');
+            if !is_char {
+                let mut end = i + 1;
+                while end < bytes.len() && is_ident(bytes[end]) {
+                    end += 1;
+                }
+                spans.push((i..end, Token::Type));
+                i = end;
+                continue;
+            }
+            let (end, _) = scan_string(bytes, i + 1, b'\'', false, true);
+            spans.push((i..end, Token::String));
+            i = end;
+            continue;
+        }
+
+        if spec.quotes.contains(&b) {
+            let triple = spec.triple_quotes && rest.as_bytes().starts_with(&[b; 3]);
+            let body = if triple { i + 3 } else { i + 1 };
+            let (end, closed) = scan_string(bytes, body, b, triple, escapes(b));
+            let token = if matches!(language, Language::Json | Language::Toml)
+                && line[end..].trim_start().starts_with([':', '='])
+            {
+                Token::Key
+            } else {
+                Token::String
+            };
+            spans.push((i..end, token));
+            if !closed && (triple || spec.multiline_quotes.contains(&b)) {
+                state.mode = Mode::String(b, triple);
+                break;
+            }
+            i = end;
+            continue;
+        }
+
+        if language == Language::Shell && b == b'$' {
+            let end = if bytes.get(i + 1) == Some(&b'{') {
+                rest.find('}').map_or(line.len(), |e| i + e + 1)
+            } else {
+                let mut end = i + 1;
+                while end < bytes.len() && (is_ident(bytes[end]) || (end == i + 1 && b"@#?$!*-".contains(&bytes[end]))) {
+                    end += 1;
+                }
+                end
+            };
+            spans.push((i..end, Token::Constant));
+            i = end;
+            continue;
+        }
+
+        if language == Language::Css {
+            match b {
+                b'{' => state.depth = state.depth.saturating_add(1),
+                b'}' => state.depth = state.depth.saturating_sub(1),
+                b'@' => {
+                    let mut end = i + 1;
+                    while end < bytes.len() && (is_ident(bytes[end]) || bytes[end] == b'-') {
+                        end += 1;
+                    }
+                    spans.push((i..end, Token::Keyword));
+                    i = end;
+                    continue;
+                }
+                b'#' | b'.' if state.depth == 0 => {
+                    let mut end = i + 1;
+                    while end < bytes.len() && (is_ident(bytes[end]) || bytes[end] == b'-') {
+                        end += 1;
+                    }
+                    spans.push((i..end, Token::Tag));
+                    i = end;
+                    continue;
+                }
+                b'#' => {
+                    let mut end = i + 1;
+                    while end < bytes.len() && bytes[end].is_ascii_hexdigit() {
+                        end += 1;
+                    }
+                    spans.push((i..end, Token::Number));
+                    i = end;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        if language == Language::Python && b == b'@' && line[..i].trim().is_empty() {
+            let end = rest.find(|c: char| c == '(' || c.is_whitespace()).map_or(line.len(), |e| i + e);
+            spans.push((i..end, Token::Attribute));
+            i = end;
+            continue;
+        }
+
+        if b.is_ascii_digit() || (b == b'-' && language == Language::Json && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) {
+            let mut end = i + 1;
+            while end < bytes.len() && (is_ident(bytes[end]) || bytes[end] == b'.') {
+                end += 1;
+            }
+            let token = if language == Language::Css { Token::Constant } else { Token::Number };
+            spans.push((i..end, token));
+            i = end;
+            continue;
+        }
+
+        if is_ident(b) || (language == Language::Css && b == b'-') {
+            let mut end = i + 1;
+            while end < bytes.len() && (is_ident(bytes[end]) || (language == Language::Css && bytes[end] == b'-')) {
+                end += 1;
+            }
+            let word = &line[i..end];
+            let next = bytes.get(skip_spaces(bytes, end)).copied();
+            let token = if spec.keywords.contains(&word) {
+                Some(Token::Keyword)
+            } else if spec.types.contains(&word) {
+                Some(Token::Type)
+            } else if spec.constants.contains(&word) {
+                Some(Token::Constant)
+            } else if language == Language::Css {
+                match next {
+                    Some(b':') if state.depth > 0 => Some(Token::Attribute),
+                    _ if state.depth == 0 => Some(Token::Tag),
+                    _ => None,
+                }
+            } else if language == Language::Toml && next == Some(b'=') {
+                Some(Token::Key)
+            } else if next == Some(b'(') || (language == Language::Rust && next == Some(b'!')) {
+                Some(Token::Function)
+            } else if spec.capitalized_types && word.starts_with(|c: char| c.is_ascii_uppercase()) {
+                if word.len() > 1 && word.bytes().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_') {
+                    Some(Token::Constant)
+                } else {
+                    Some(Token::Type)
+                }
+            } else {
+                None
+            };
+            if let Some(token) = token {
+                let end = if token == Token::Function && next == Some(b'!') { end + 1 } else { end };
+                spans.push((i..end, token));
+                i = end;
+            } else {
+                i = end;
+            }
+            continue;
+        }
+
+        i += line[i..].chars().next().map_or(1, char::len_utf8);
+    }
+
+    (spans, state)
+}
+
+fn markdown_line(line: &str, mut state: LineState) -> (Vec<(Range<usize>, Token)>, LineState) {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+        state.mode = if state.mode == Mode::Fence { Mode::Normal } else { Mode::Fence };
+        return (vec![(0..line.len(), Token::Code)], state);
+    }
+    if state.mode == Mode::Fence {
+        return (vec![(0..line.len(), Token::Code)], state);
+    }
+    let indent = line.len() - trimmed.len();
+    if trimmed.starts_with('#') {
+        return (vec![(0..line.len(), Token::Heading)], state);
+    }
+    if trimmed.starts_with('>') {
+        return (vec![(0..line.len(), Token::Comment)], state);
+    }
+    if indent >= 4 || line.starts_with('\t') {
+        return (vec![(0..line.len(), Token::Code)], state);
+    }
+
+    let bytes = line.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = indent;
+    let marker = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+    if trimmed.starts_with(['-', '*', '+']) && trimmed[1..].starts_with(' ') {
+        spans.push((indent..indent + 1, Token::Keyword));
+        i += 1;
+    } else if marker > 0 && trimmed[marker..].starts_with(". ") {
+        spans.push((indent..indent + marker + 1, Token::Keyword));
+        i += marker + 1;
+    }
+
+    while i < bytes.len() {
+        let rest = &line[i..];
+        match bytes[i] {
+            b'`' => {
+                let end = rest[1..].find('`').map_or(line.len(), |e| i + e + 2);
+                spans.push((i..end, Token::Code));
+                i = end;
+            }
+            b'*' | b'_' => {
+                let delimiter = if rest.starts_with("**") || rest.starts_with("__") { &rest[..2] } else { &rest[..1] };
+                match rest[delimiter.len()..].find(delimiter) {
+                    Some(e) if e > 0 => {
+                        let end = i + delimiter.len() * 2 + e;
+                        spans.push((i..end, Token::Emphasis));
+                        i = end;
+                    }
+                    _ => i += delimiter.len(),
+                }
+            }
+            b'[' => {
+                let Some(close) = rest.find("](") else {
+                    i += 1;
+                    continue;
+                };
+                let end = rest[close..].find(')').map_or(line.len(), |e| i + close + e + 1);
+                spans.push((i..i + close + 1, Token::Function));
+                spans.push((i + close + 1..end, Token::String));
+                i = end;
+            }
+            _ => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    (spans, state)
+}
+
+fn html_line(line: &str, mut state: LineState) -> (Vec<(Range<usize>, Token)>, LineState) {
+    let bytes = line.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &line[i..];
+        match state.mode {
+            Mode::BlockComment => match rest.find("-->") {
+                Some(e) => {
+                    spans.push((i..i + e + 3, Token::Comment));
+                    i += e + 3;
+                    state.mode = Mode::Normal;
+                }
+                None => {
+                    spans.push((i..line.len(), Token::Comment));
+                    break;
+                }
+            },
+            Mode::String(quote, _) => {
+                let (end, closed) = scan_string(bytes, i, quote, false, false);
+                spans.push((i..end, Token::String));
+                i = end;
+                if closed {
+                    state.mode = Mode::InTag;
+                }
+            }
+            Mode::InTag => match bytes[i] {
+                b'>' => {
+                    spans.push((i..i + 1, Token::Tag));
+                    i += 1;
+                    state.mode = Mode::Normal;
+                }
+                b'/' if rest.starts_with("/>") => {
+                    spans.push((i..i + 2, Token::Tag));
+                    i += 2;
+                    state.mode = Mode::Normal;
+                }
+                quote @ (b'"' | b'\'') => {
+                    let (end, closed) = scan_string(bytes, i + 1, quote, false, false);
+                    spans.push((i..end, Token::String));
+                    i = end;
+                    if !closed {
+                        state.mode = Mode::String(quote, false);
+                    }
+                }
+                b if is_ident(b) => {
+                    let end = rest
+                        .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+                        .map_or(line.len(), |e| i + e);
+                    spans.push((i..end, Token::Attribute));
+                    i = end;
+                }
+                _ => i += rest.chars().next().map_or(1, char::len_utf8),
+            },
+            _ => {
+                if rest.starts_with("<!--") {
+                    state.mode = Mode::BlockComment;
+                    spans.push((i..i + 4, Token::Comment));
+                    i += 4;
+                } else if bytes[i] == b'<' && bytes.get(i + 1).is_some_and(|&b| b == b'/' || b == b'!' || b == b'?' || b.is_ascii_alphabetic()) {
+                    let end = rest[1..]
+                        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                        .map_or(line.len(), |e| i + e + 1);
+                    let end = if end == i + 1 { end + 1 } else { end };
+                    spans.push((i..end, Token::Tag));
+                    i = end;
+                    state.mode = Mode::InTag;
+                } else if bytes[i] == b'&' {
+                    let end = rest.find(';').filter(|&e| e < 10).map_or(i + 1, |e| i + e + 1);
+                    spans.push((i..end, Token::Constant));
+                    i = end;
+                } else {
+                    i += rest.chars().next().map_or(1, char::len_utf8);
+                }
+            }
+        }
+    }
+    (spans, state)
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxSettings {
+    pub language: Option<Language>,
+    pub spans: Option<LineSpans>,
+}
+
+impl PartialEq for SyntaxSettings {
+    fn eq(&self, other: &Self) -> bool {
+        let same_spans = match (&self.spans, &other.spans) {
+            (Some(a), Some(b)) => a.ptr_eq(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.language == other.language && same_spans
+    }
+}
+
+pub struct SyntaxHighlighter {
+    language: Option<Language>,
+    spans: Option<LineSpans>,
+    states: Vec<LineState>,
+    current_line: usize,
+}
+
+impl SyntaxHighlighter {
+    pub fn rewind(&mut self, line: usize) {
+        self.current_line = self.current_line.min(line).min(self.states.len() - 1);
+    }
+}
+
+impl Highlighter for SyntaxHighlighter {
+    type Settings = SyntaxSettings;
+    type Highlight = Token;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Token)>;
+
+    fn new(settings: &Self::Settings) -> Self {
+        Self {
+            language: settings.language,
+            spans: settings.spans.clone(),
+            states: vec![LineState::default()],
+            current_line: 0,
+        }
+    }
+
+    fn update(&mut self, new_settings: &Self::Settings) {
+        if self.language != new_settings.language {
+            self.language = new_settings.language;
+            self.states.truncate(1);
+            self.current_line = 0;
+        }
+        match (&self.spans, &new_settings.spans) {
+            (Some(a), Some(b)) if a.ptr_eq(b) => {}
+            (None, None) => {}
+            (Some(a), Some(b)) => {
+                if let Some(line) = a.first_difference(b) {
+                    self.rewind(line);
+                }
+                self.spans = new_settings.spans.clone();
+            }
+            _ => {
+                self.spans = new_settings.spans.clone();
+                self.current_line = 0;
+            }
+        }
+    }
+
+    fn change_line(&mut self, line: usize) {
+        self.states.truncate(line + 1);
+        self.current_line = self.current_line.min(self.states.len() - 1);
+    }
+
+    fn highlight_line(&mut self, text: &str) -> Self::Iterator<'_> {
+        let line = self.current_line;
+        self.current_line += 1;
+        let Some(language) = self.language else {
+            return Vec::new().into_iter();
+        };
+        let state = self.states.get(line).copied().unwrap_or_default();
+        let (spans, next) = highlight_line(language, text, state);
+        self.states.resize(line + 1, LineState::default());
+        self.states.push(next);
+        match &self.spans {
+            Some(tree) => tree
+                .get(line)
+                .map(|spans| {
+                    spans
+                        .iter()
+                        .map(|(range, token)| {
+                            (char_boundary(text, range.start)..char_boundary(text, range.end), *token)
+                        })
+                        .filter(|(range, _)| !range.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+                .into_iter(),
+            None => spans.into_iter(),
+        }
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
@@ -0,0 +1,396 @@
+use iced::theme::{Mode, Palette};
+use iced::{Color, Theme};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::app::App;
+use crate::syntax::Token;
+
+#[derive(Debug, Clone)]
+pub struct EditorTheme {
+    pub name: String,
+    pub palette: Palette,
+    pub iced: Theme,
+    pub cursor: Color,
+    pub selection: Color,
+    pub current_line: Color,
+    pub indent_guide: Color,
+    pub ruler: Color,
+    pub whitespace: Color,
+    pub trailing_whitespace: Color,
+    pub bracket_match: Color,
+    pub unmatched: Color,
+    pub find_match: Color,
+    pub find_current: Color,
+    pub word_highlight: Color,
+    pub gutter: Color,
+    pub gutter_current: Color,
+    pub muted: Color,
+    pub accent: Color,
+    pub rainbow: [Color; 4],
+    pub keyword: Color,
+    pub type_name: Color,
+    pub function: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub tag: Color,
+}
+
+impl EditorTheme {
+    pub fn dark() -> Self {
+        Self {
+            name: String::from("Dark"),
+            palette: Palette::DARK,
+            iced: Theme::Dark,
+            cursor: Color::from_rgba(0.7, 0.7, 0.7, 0.5),
+            selection: Color::from_rgba(0.3, 0.5, 0.8, 0.35),
+            current_line: Color::from_rgba(1.0, 1.0, 1.0, 0.05),
+            indent_guide: Color::from_rgba(0.6, 0.6, 0.6, 0.2),
+            ruler: Color::from_rgba(0.6, 0.6, 0.6, 0.3),
+            whitespace: Color::from_rgba(0.6, 0.6, 0.6, 0.5),
+            trailing_whitespace: Color::from_rgba(1.0, 0.4, 0.2, 0.3),
+            bracket_match: Color::from_rgba(0.8, 0.8, 0.8, 0.8),
+            unmatched: Color::from_rgb(1.0, 0.3, 0.3),
+            find_match: Color::from_rgb(1.0, 0.9, 0.2),
+            find_current: Color::from_rgb(1.0, 0.6, 0.0),
+            word_highlight: Color::from_rgb(0.95, 0.95, 0.75),
+            gutter: Color::from_rgb(0.4, 0.4, 0.4),
+            gutter_current: Color::from_rgb(0.7, 0.7, 0.7),
+            muted: Color::from_rgb(0.5, 0.5, 0.5),
+            accent: Color::from_rgb(1.0, 0.75, 0.0),
+            rainbow: [
+                Color::from_rgb(1.0, 0.84, 0.0),
+                Color::from_rgb(0.85, 0.44, 0.84),
+                Color::from_rgb(0.09, 0.62, 1.0),
+                Color::from_rgb(0.4, 0.8, 0.5),
+            ],
+            keyword: Color::from_rgb(0.78, 0.47, 0.87),
+            type_name: Color::from_rgb(0.90, 0.75, 0.48),
+            function: Color::from_rgb(0.38, 0.69, 0.94),
+            string: Color::from_rgb(0.60, 0.76, 0.47),
+            number: Color::from_rgb(0.82, 0.60, 0.40),
+            comment: Color::from_rgb(0.50, 0.54, 0.58),
+            tag: Color::from_rgb(0.88, 0.42, 0.46),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            name: String::from("Light"),
+            palette: Palette::LIGHT,
+            iced: Theme::Light,
+            cursor: Color::from_rgba(0.2, 0.2, 0.2, 0.4),
+            selection: Color::from_rgba(0.3, 0.5, 0.9, 0.3),
+            current_line: Color::from_rgba(0.0, 0.0, 0.0, 0.05),
+            indent_guide: Color::from_rgba(0.4, 0.4, 0.4, 0.2),
+            ruler: Color::from_rgba(0.4, 0.4, 0.4, 0.3),
+            whitespace: Color::from_rgba(0.4, 0.4, 0.4, 0.5),
+            trailing_whitespace: Color::from_rgba(1.0, 0.4, 0.2, 0.3),
+            bracket_match: Color::from_rgba(0.3, 0.3, 0.3, 0.8),
+            unmatched: Color::from_rgb(0.85, 0.1, 0.1),
+            find_match: Color::from_rgb(0.75, 0.5, 0.0),
+            find_current: Color::from_rgb(0.9, 0.3, 0.0),
+            word_highlight: Color::from_rgb(0.1, 0.1, 0.35),
+            gutter: Color::from_rgb(0.6, 0.6, 0.6),
+            gutter_current: Color::from_rgb(0.2, 0.2, 0.2),
+            muted: Color::from_rgb(0.45, 0.45, 0.45),
+            accent: Color::from_rgb(0.85, 0.5, 0.0),
+            rainbow: [
+                Color::from_rgb(0.8, 0.6, 0.0),
+                Color::from_rgb(0.6, 0.2, 0.6),
+                Color::from_rgb(0.0, 0.4, 0.8),
+                Color::from_rgb(0.2, 0.6, 0.3),
+            ],
+            keyword: Color::from_rgb(0.65, 0.15, 0.64),
+            type_name: Color::from_rgb(0.76, 0.52, 0.0),
+            function: Color::from_rgb(0.25, 0.47, 0.95),
+            string: Color::from_rgb(0.31, 0.63, 0.31),
+            number: Color::from_rgb(0.6, 0.41, 0.0),
+            comment: Color::from_rgb(0.63, 0.63, 0.65),
+            tag: Color::from_rgb(0.89, 0.34, 0.29),
+        }
+    }
+
+    pub fn is_dark(&self) -> bool {
+        let Color { r, g, b, .. } = self.palette.background;
+        0.299 * r + 0.587 * g + 0.114 * b < 0.5
+    }
+
+    pub fn token(&self, token: Token) -> Color {
+        match token {
+            Token::Keyword => self.keyword,
+            Token::Type | Token::Attribute | Token::Emphasis => self.type_name,
+            Token::Function | Token::Heading => self.function,
+            Token::String | Token::Code => self.string,
+            Token::Number | Token::Constant => self.number,
+            Token::Comment => self.comment,
+            Token::Tag | Token::Key => self.tag,
+        }
+    }
+
+    fn set(&mut self, key: &str, color: Color) {
+        let slot = match key {
+            "background" => &mut self.palette.background,
+            "text" => &mut self.palette.text,
+            "primary" => &mut self.palette.primary,
+            "success" => &mut self.palette.success,
+            "warning" => &mut self.palette.warning,
+            "danger" => &mut self.palette.danger,
+            "cursor" => &mut self.cursor,
+            "selection" => &mut self.selection,
+            "current_line" => &mut self.current_line,
+            "indent_guide" => &mut self.indent_guide,
+            "ruler" => &mut self.ruler,
+            "whitespace" => &mut self.whitespace,
+            "trailing_whitespace" => &mut self.trailing_whitespace,
+            "bracket_match" => &mut self.bracket_match,
+            "unmatched" => &mut self.unmatched,
+            "find_match" => &mut self.find_match,
+            "find_current" => &mut self.find_current,
+            "word_highlight" => &mut self.word_highlight,
+            "gutter" => &mut self.gutter,
+            "gutter_current" => &mut self.gutter_current,
+            "muted" => &mut self.muted,
+            "accent" => &mut self.accent,
+            "rainbow1" => &mut self.rainbow[0],
+            "rainbow2" => &mut self.rainbow[1],
+            "rainbow3" => &mut self.rainbow[2],
+            "rainbow4" => &mut self.rainbow[3],
+            "keyword" => &mut self.keyword,
+            "type" => &mut self.type_name,
+            "function" => &mut self.function,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "comment" => &mut self.comment,
+            "tag" => &mut self.tag,
+            _ => return,
+        };
+        *slot = color;
+    }
+
+    fn finish(mut self, name: String) -> Self {
+        self.iced = Theme::custom(name.clone(), self.palette);
+        self.name = name;
+        self
+    }
+}
+
+pub fn parse_color(hex: &str) -> Option<Color> {
+    let hex = hex.strip_prefix('#')?;
+    let digits: Vec<u8> = match hex.len() {
+        3 | 4 => hex.chars().map(|c| c.to_digit(16).map(|d| d as u8 * 17)).collect::<Option<_>>()?,
+        6 | 8 => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    let alpha = digits.get(3).map_or(1.0, |&a| a as f32 / 255.0);
+    Some(Color::from_rgba8(digits[0], digits[1], digits[2], alpha))
+}
+
+// Our own format names a base theme and overrides colours by key:
+// {"name": "Dusk", "base": "dark", "colors": {"background": "#1e1e2e", "keyword": "#cba6f7"}}
+fn parse_native(json: &Value) -> Option<EditorTheme> {
+    let name = json.get("name")?.as_str()?.to_string();
+    let mut theme = match json.get("base").and_then(Value::as_str) {
+        Some("light") => EditorTheme::light(),
+        _ => EditorTheme::dark(),
+    };
+    for (key, value) in json.get("colors")?.as_object()? {
+        if let Some(color) = value.as_str().and_then(parse_color) {
+            theme.set(key, color);
+        }
+    }
+    Some(theme.finish(name))
+}
+
+const VSCODE_COLORS: &[(&str, &str)] = &[
+    ("editor.background", "background"),
+    ("editor.foreground", "text"),
+    ("focusBorder", "primary"),
+    ("button.background", "primary"),
+    ("editorError.foreground", "danger"),
+    ("editorError.foreground", "unmatched"),
+    ("editorWarning.foreground", "warning"),
+    ("editorCursor.foreground", "cursor"),
+    ("editor.selectionBackground", "selection"),
+    ("editor.lineHighlightBackground", "current_line"),
+    ("editorIndentGuide.background", "indent_guide"),
+    ("editorIndentGuide.background1", "indent_guide"),
+    ("editorRuler.foreground", "ruler"),
+    ("editorWhitespace.foreground", "whitespace"),
+    ("editorBracketMatch.border", "bracket_match"),
+    ("editor.wordHighlightBorder", "word_highlight"),
+    ("editorLineNumber.foreground", "gutter"),
+    ("editorLineNumber.activeForeground", "gutter_current"),
+    ("editorBracketHighlight.foreground1", "rainbow1"),
+    ("editorBracketHighlight.foreground2", "rainbow2"),
+    ("editorBracketHighlight.foreground3", "rainbow3"),
+    ("editorBracketHighlight.foreground4", "rainbow4"),
+];
+
+const VSCODE_SCOPES: &[(&str, &str)] = &[
+    ("comment", "comment"),
+    ("string", "string"),
+    ("constant", "number"),
+    ("keyword", "keyword"),
+    ("storage", "keyword"),
+    ("entity.name.type", "type"),
+    ("entity.name.class", "type"),
+    ("support.type", "type"),
+    ("support.class", "type"),
+    ("entity.name.function", "function"),
+    ("support.function", "function"),
+    ("entity.name.tag", "tag"),
+];
+
+// VS Code colour themes: workbench colours under "colors", TextMate scopes
+// under "tokenColors". Only the keys we have a use for are read.
+fn parse_vscode(json: &Value) -> Option<EditorTheme> {
+    let name = json.get("name")?.as_str()?.to_string();
+    let mut theme = match json.get("type").and_then(Value::as_str) {
+        Some("light") | Some("hcLight") => EditorTheme::light(),
+        _ => EditorTheme::dark(),
+    };
+    if let Some(colors) = json.get("colors").and_then(Value::as_object) {
+        for (from, to) in VSCODE_COLORS {
+            if let Some(color) = colors.get(*from).and_then(Value::as_str).and_then(parse_color) {
+                theme.set(to, color);
+            }
+        }
+    }
+    for rule in json.get("tokenColors").and_then(Value::as_array).into_iter().flatten() {
+        let Some(color) = rule.pointer("/settings/foreground").and_then(Value::as_str).and_then(parse_color) else {
+            continue;
+        };
+        let scopes: Vec<&str> = match rule.get("scope") {
+            Some(Value::String(scope)) => scope.split(',').map(str::trim).collect(),
+            Some(Value::Array(scopes)) => scopes.iter().filter_map(Value::as_str).collect(),
+            _ => continue,
+        };
+        for scope in scopes {
+            let key = VSCODE_SCOPES
+                .iter()
+                .find(|(prefix, _)| scope == *prefix || scope.starts_with(&format!("{prefix}.")));
+            if let Some((_, key)) = key {
+                theme.set(key, color);
+            }
+        }
+    }
+    Some(theme.finish(name))
+}
+
+fn string_end(source: &str) -> usize {
+    let mut escaped = false;
+    for (i, b) in source.bytes().enumerate().skip(1) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => return i + 1,
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+fn skip_comment(source: &str) -> Option<&str> {
+    if let Some(comment) = source.strip_prefix("//") {
+        return Some(comment.find('\n').map_or("", |end| &comment[end..]));
+    }
+    let comment = source.strip_prefix("/*")?;
+    Some(comment.find("*/").map_or("", |end| &comment[end + 2..]))
+}
+
+// VS Code writes its themes as JSONC: comments and trailing commas allowed.
+fn strip_jsonc(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(i) = rest.find(['"', '/', ',']) {
+        let (before, after) = rest.split_at(i);
+        out.push_str(before);
+        if after.starts_with('"') {
+            let end = string_end(after);
+            out.push_str(&after[..end]);
+            rest = &after[end..];
+        } else if let Some(next) = skip_comment(after) {
+            rest = next;
+        } else {
+            let mut next = after[1..].trim_start();
+            while let Some(skipped) = skip_comment(next) {
+                next = skipped.trim_start();
+            }
+            if !(after.starts_with(',') && next.starts_with(['}', ']'])) {
+                out.push_str(&after[..1]);
+            }
+            rest = &after[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn parse_theme(source: &str) -> Option<EditorTheme> {
+    let json: Value = serde_json::from_str(&strip_jsonc(source)).ok()?;
+    if json.get("tokenColors").is_some() || json.get("type").is_some() {
+        parse_vscode(&json)
+    } else {
+        parse_native(&json)
+    }
+}
+
+pub fn load_themes() -> (Vec<Arc<EditorTheme>>, Vec<String>) {
+    let mut themes = vec![Arc::new(EditorTheme::dark()), Arc::new(EditorTheme::light())];
+    let mut broken = Vec::new();
+    for (path, source) in crate::persistence::load_theme_files() {
+        let Some(theme) = parse_theme(&source) else {
+            broken.push(path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned()));
+            continue;
+        };
+        themes.retain(|t| t.name != theme.name);
+        themes.push(Arc::new(theme));
+    }
+    (themes, broken)
+}
+
+impl App {
+    // When following the system, the chosen theme is kept as long as its tone
+    // matches; otherwise the built-in theme of the system's tone stands in.
+    pub fn editor_theme(&self) -> Arc<EditorTheme> {
+        let find = |name: &str| self.themes.iter().find(|t| t.name == name);
+        let selected = find(&self.theme_name).unwrap_or(&self.themes[0]);
+        let fallback = match self.system_theme {
+            Mode::Light if self.follow_system_theme && selected.is_dark() => find("Light"),
+            Mode::Dark if self.follow_system_theme && !selected.is_dark() => find("Dark"),
+            _ => None,
+        };
+        fallback.unwrap_or(selected).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vscode_themes_may_use_jsonc() {
+        let source = r##"{
+            // Exported from VS Code
+            "name": "Commented // not a comment",
+            "type": "dark",
+            /* block
+               comment */
+            "colors": {
+                "editor.background": "#101010", // trailing comment
+            },
+            "tokenColors": [
+                { "scope": "comment", "settings": { "foreground": "#00ff00", }, },
+            ],
+        }"##;
+        let theme = parse_theme(source).unwrap();
+        assert_eq!(theme.name, "Commented // not a comment");
+        assert_eq!(theme.token(Token::Comment), Color::from_rgb8(0, 255, 0));
+        assert_eq!(strip_jsonc(r#"["a,]", "\"/*", 1,]"#), r#"["a,]", "\"/*", 1]"#);
+    }
+}
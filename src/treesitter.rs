@@ -0,0 +1,345 @@
+use std::ops::Range;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+use crate::app::App;
+use crate::line_map::{LineMap, RowEdit};
+use crate::search::LineIndex;
+use crate::syntax::{Language, LineSpans, Token};
+
+const FUNCTION_KINDS: &[&str] = &[
+    "function_item",
+    "function_signature_item",
+    "closure_expression",
+    "function_definition",
+    "lambda",
+    "function_declaration",
+    "function_expression",
+    "generator_function_declaration",
+    "arrow_function",
+    "method_definition",
+];
+
+const CLASS_KINDS: &[&str] = &[
+    "struct_item",
+    "enum_item",
+    "union_item",
+    "trait_item",
+    "impl_item",
+    "class_definition",
+    "class_declaration",
+    "class",
+    "abstract_class_declaration",
+    "interface_declaration",
+    "enum_declaration",
+];
+
+const ARGUMENT_LISTS: &[&str] = &[
+    "arguments",
+    "parameters",
+    "formal_parameters",
+    "argument_list",
+    "type_arguments",
+    "type_parameters",
+    "closure_parameters",
+    "lambda_parameters",
+];
+
+pub struct SyntaxTree {
+    pub language: Language,
+    parser: Parser,
+    tree: Tree,
+    query: Option<Query>,
+    source: String,
+    index: LineIndex,
+    pub highlights: Option<LineSpans>,
+}
+
+fn grammar(language: Language) -> Option<(tree_sitter::Language, String)> {
+    Some(match language {
+        Language::Rust => (tree_sitter_rust::LANGUAGE.into(), tree_sitter_rust::HIGHLIGHTS_QUERY.into()),
+        Language::Python => (tree_sitter_python::LANGUAGE.into(), tree_sitter_python::HIGHLIGHTS_QUERY.into()),
+        Language::JavaScript => (
+            tree_sitter_javascript::LANGUAGE.into(),
+            tree_sitter_javascript::HIGHLIGHT_QUERY.into(),
+        ),
+        Language::TypeScript => (
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            format!("{}\n{}", tree_sitter_javascript::HIGHLIGHT_QUERY, tree_sitter_typescript::HIGHLIGHTS_QUERY),
+        ),
+        Language::Json => (tree_sitter_json::LANGUAGE.into(), tree_sitter_json::HIGHLIGHTS_QUERY.into()),
+        Language::Toml => (tree_sitter_toml_ng::LANGUAGE.into(), tree_sitter_toml_ng::HIGHLIGHTS_QUERY.into()),
+        Language::Yaml => (tree_sitter_yaml::LANGUAGE.into(), tree_sitter_yaml::HIGHLIGHTS_QUERY.into()),
+        Language::Shell => (tree_sitter_bash::LANGUAGE.into(), tree_sitter_bash::HIGHLIGHT_QUERY.into()),
+        Language::Html => (tree_sitter_html::LANGUAGE.into(), tree_sitter_html::HIGHLIGHTS_QUERY.into()),
+        Language::Css => (tree_sitter_css::LANGUAGE.into(), tree_sitter_css::HIGHLIGHTS_QUERY.into()),
+        Language::Markdown => return None,
+    })
+}
+
+fn capture_token(name: &str) -> Option<Token> {
+    if name == "variable.builtin" {
+        return Some(Token::Keyword);
+    }
+    Some(match name.split('.').next()? {
+        "keyword" => Token::Keyword,
+        "type" | "constructor" | "label" => Token::Type,
+        "function" => Token::Function,
+        "string" | "escape" => Token::String,
+        "number" | "boolean" => Token::Number,
+        "constant" => Token::Constant,
+        "comment" => Token::Comment,
+        "attribute" => Token::Attribute,
+        "property" => Token::Key,
+        "tag" => Token::Tag,
+        _ => return None,
+    })
+}
+
+fn point((row, column): (usize, usize)) -> Point {
+    Point { row, column }
+}
+
+fn end_point(start: Point, text: &str) -> Point {
+    match text.rfind('\n') {
+        Some(last) => Point {
+            row: start.row + text.bytes().filter(|&b| b == b'\n').count(),
+            column: text.len() - last - 1,
+        },
+        None => Point { row: start.row, column: start.column + text.len() },
+    }
+}
+
+impl SyntaxTree {
+    pub fn new(language: Language, text: String) -> Option<Self> {
+        let (grammar, query) = grammar(language)?;
+        let mut parser = Parser::new();
+        parser.set_language(&grammar).ok()?;
+        let tree = parser.parse(&text, None)?;
+        let query = Query::new(&grammar, &query).ok();
+        let mut syntax = Self {
+            language,
+            parser,
+            tree,
+            query,
+            index: LineIndex::new(&text),
+            source: text,
+            highlights: None,
+        };
+        if syntax.query.is_some() {
+            let mut highlights = LineMap::default();
+            highlights.splice(0..0, syntax.highlight_rows(0..syntax.index.line_count()));
+            syntax.highlights = Some(highlights);
+        }
+        Some(syntax)
+    }
+
+    pub fn apply_rows(&mut self, edit: RowEdit, text: &str) {
+        let start = self.index.line_start(edit.from).min(self.source.len());
+        let old_end = self.index.line_start(edit.old_end).min(self.source.len());
+        self.apply(start..old_end, text);
+    }
+
+    fn apply(&mut self, range: Range<usize>, text: &str) {
+        let start_position = point(self.index.position(range.start));
+        let edit = InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte: range.start + text.len(),
+            start_position,
+            old_end_position: point(self.index.position(range.end)),
+            new_end_position: end_point(start_position, text),
+        };
+        self.source.replace_range(range.clone(), text);
+        self.index.splice(range, text);
+        self.tree.edit(&edit);
+        let Some(tree) = self.parser.parse(&self.source, Some(&self.tree)) else {
+            return;
+        };
+        let changed: Vec<_> = self.tree.changed_ranges(&tree).collect();
+        self.tree = tree;
+        let Some(mut highlights) = self.highlights.take() else {
+            return;
+        };
+        let rows = edit.start_position.row..edit.new_end_position.row + 1;
+        highlights.splice(edit.start_position.row..edit.old_end_position.row + 1, self.highlight_rows(rows.clone()));
+        let line_count = self.index.line_count();
+        for range in changed {
+            let changed = range.start_point.row.min(line_count)..(range.end_point.row + 1).min(line_count);
+            if changed.start < rows.start || changed.end > rows.end {
+                highlights.splice(changed.clone(), self.highlight_rows(changed));
+            }
+        }
+        self.highlights = Some(highlights);
+    }
+
+    fn highlight_rows(&self, rows: Range<usize>) -> Vec<Vec<(Range<usize>, Token)>> {
+        let mut lines = vec![Vec::new(); rows.len()];
+        let Some(query) = &self.query else {
+            return lines;
+        };
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(point((rows.start, 0))..point((rows.end, 0)));
+        let mut captures = cursor.captures(query, self.tree.root_node(), self.source.as_bytes());
+        while let Some((m, index)) = captures.next() {
+            let capture = m.captures[*index];
+            let Some(token) = capture_token(query.capture_names()[capture.index as usize]) else {
+                continue;
+            };
+            let (start, end) = (capture.node.start_position(), capture.node.end_position());
+            for row in start.row.max(rows.start)..=end.row.min(rows.end - 1) {
+                let line_len = self.index.line_start(row + 1).min(self.source.len()) - self.index.line_start(row);
+                let from = if row == start.row { start.column } else { 0 };
+                let to = if row == end.row { end.column } else { line_len };
+                if from >= to {
+                    continue;
+                }
+                let spans = &mut lines[row - rows.start];
+                match spans.last_mut() {
+                    Some((range, last)) if *range == (from..to) => *last = token,
+                    _ => spans.push((from..to, token)),
+                }
+            }
+        }
+        lines
+    }
+
+    fn offset(&self, line: usize, column: usize) -> usize {
+        self.index.line_start(line).saturating_add(column).min(self.source.len())
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        self.index.position(offset)
+    }
+
+    fn smallest_node(&self, range: Range<usize>) -> Option<Node<'_>> {
+        self.tree.root_node().named_descendant_for_byte_range(range.start, range.end)
+    }
+
+    fn body_range(&self, node: Node<'_>, inner: bool) -> Range<usize> {
+        if !inner {
+            return node.byte_range();
+        }
+        let Some(body) = node.child_by_field_name("body") else {
+            return node.byte_range();
+        };
+        let range = body.byte_range();
+        let text = &self.source[range.clone()];
+        let delimited = text.starts_with(['{', '(', '[']) && range.len() >= 2;
+        let range = if delimited { range.start + 1..range.end - 1 } else { range };
+        let text = &self.source[range.clone()];
+        let start = range.start + (text.len() - text.trim_start().len());
+        let end = range.end - (text.len() - text.trim_end().len());
+        if start < end { start..end } else { range }
+    }
+
+    fn argument_range(&self, node: Node<'_>, inner: bool) -> Range<usize> {
+        let range = node.byte_range();
+        if inner {
+            return range;
+        }
+        let rest = &self.source[range.end..];
+        let trailing = rest.trim_start();
+        if let Some(after) = trailing.strip_prefix(',') {
+            let skipped = rest.len() - after.trim_start().len();
+            return range.start..range.end + skipped;
+        }
+        let before = &self.source[..range.start];
+        match before.trim_end().strip_suffix(',') {
+            Some(lead) => lead.len()..range.end,
+            None => range,
+        }
+    }
+
+    pub fn text_object(&self, line: usize, column: usize, object: char, inner: bool) -> Option<((usize, usize), (usize, usize))> {
+        let offset = self.offset(line, column);
+        let mut node = self.smallest_node(offset..offset)?;
+        let range = loop {
+            let parent = node.parent();
+            match object {
+                'f' if FUNCTION_KINDS.contains(&node.kind()) => break self.body_range(node, inner),
+                'c' if CLASS_KINDS.contains(&node.kind()) => break self.body_range(node, inner),
+                'a' if node.is_named() && parent.is_some_and(|p| ARGUMENT_LISTS.contains(&p.kind())) => {
+                    break self.argument_range(node, inner);
+                }
+                _ => {}
+            }
+            node = parent?;
+        };
+        Some((self.position(range.start), self.position(range.end)))
+    }
+
+    pub fn enclosing(&self, start: (usize, usize), end: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let range = self.offset(start.0, start.1)..self.offset(end.0, end.1);
+        let mut node = self.smallest_node(range.clone())?;
+        while node.byte_range() == range || node.byte_range().len() < range.len() {
+            node = node.parent()?;
+        }
+        let range = node.byte_range();
+        Some((self.position(range.start), self.position(range.end)))
+    }
+}
+
+impl App {
+    pub fn refresh_syntax_tree(&mut self, edit: Option<RowEdit>) {
+        let current = self.syntax_tree.as_ref().map(|t| t.language);
+        let language = match self.language {
+            Some(language) if self.syntax_tree_tick != Some(self.changedtick) || current != Some(language) => language,
+            Some(_) => return,
+            None => {
+                self.syntax_tree = None;
+                self.syntax_spans = None;
+                return;
+            }
+        };
+        let previous = self.syntax_tree_tick.replace(self.changedtick);
+        match edit.filter(|edit| edit.applies_to(previous) && current == Some(language)) {
+            Some(edit) => {
+                let line_count = self.content.line_count();
+                let text: String = (edit.from..edit.new_end).map(|i| self.line_with_ending(i, line_count)).collect();
+                if let Some(tree) = &mut self.syntax_tree {
+                    tree.apply_rows(edit, &text);
+                }
+            }
+            None => self.syntax_tree = SyntaxTree::new(language, self.content.text()),
+        }
+        self.syntax_spans = self.syntax_tree.as_ref().and_then(|t| t.highlights.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "struct Point {\n    x: i32,\n}\n\nfn add(a: i32, b: i32) -> i32 {\n    let sum = a + b;\n    sum\n}\n";
+
+    #[test]
+    fn text_objects() {
+        let tree = SyntaxTree::new(Language::Rust, SOURCE.to_string()).unwrap();
+        let cases = [
+            ((5, 8), 'f', false, ((4, 0), (7, 1))),
+            ((5, 8), 'f', true, ((5, 4), (6, 7))),
+            ((1, 4), 'c', false, ((0, 0), (2, 1))),
+            ((1, 4), 'c', true, ((1, 4), (1, 11))),
+            ((4, 8), 'a', false, ((4, 7), (4, 15))),
+            ((4, 8), 'a', true, ((4, 7), (4, 13))),
+            ((4, 16), 'a', false, ((4, 13), (4, 21))),
+        ];
+        for ((line, column), object, inner, expected) in cases {
+            assert_eq!(tree.text_object(line, column, object, inner), Some(expected), "{object} at {line}:{column}");
+        }
+        assert_eq!(tree.text_object(1, 4, 'f', false), None);
+    }
+
+    #[test]
+    fn selection_expands_to_enclosing_nodes() {
+        let tree = SyntaxTree::new(Language::Rust, SOURCE.to_string()).unwrap();
+        let mut selection = ((6, 5), (6, 5));
+        let mut steps = Vec::new();
+        while let Some(next) = tree.enclosing(selection.0, selection.1) {
+            steps.push(next);
+            selection = next;
+        }
+        assert_eq!(steps[..3], [((6, 4), (6, 7)), ((4, 30), (7, 1)), ((4, 0), (7, 1))]);
+        assert_eq!(steps.last(), Some(&((0, 0), (8, 0))));
+    }
+}
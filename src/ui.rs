@@ -3,8 +3,12 @@ use iced::{Element, Fill, Length, Theme};
 
 use crate::app::App;
 use crate::message::{Message, VimMode};
+use crate::search::grapheme_column;
 use crate::subscription::{COMMAND_INPUT_ID, SEARCH_INPUT_ID};
 
+pub const UNDO_TREE_SCROLL_ID: iced::widget::Id = iced::widget::Id::new("undo_tree_scroll");
+pub const UNDO_FILTER_INPUT_ID: iced::widget::Id = iced::widget::Id::new("undo_filter_input");
+
 impl App {
     pub fn command_bar(&self) -> Element<'_, Message> {
         let input = text_input("", &self.vim_command)
@@ -66,7 +70,16 @@ impl App {
     pub fn status_bar(&self) -> Element<'_, Message> {
         let cursor = self.content.cursor();
         let line = cursor.position.line + 1;
-        let col = cursor.position.column + 1;
+        let col = self
+            .content
+            .line(cursor.position.line)
+            .map_or(0, |l| grapheme_column(&l.text, cursor.position.column))
+            + 1;
+        let position = if self.extra_cursors.is_empty() {
+            format!("Ln {}, Col {}", line, col)
+        } else {
+            format!("Ln {}, Col {} ({} cursors)", line, col, self.extra_cursors.len() + 1)
+        };
         let lines = self.content.line_count();
         let zoom = (self.scale * 100.0).round() as u32;
         let mode_row: Element<'_, Message> = if self.vim_enabled {
@@ -77,6 +90,7 @@ impl App {
                 VimMode::Search => "SEARCH",
                 VimMode::Visual => "VISUAL",
                 VimMode::VisualLine => "VISUAL LINE",
+                VimMode::Confirm => "CONFIRM",
             };
             let mut keys = String::new();
             if !self.vim_count.is_empty() {
@@ -104,20 +118,26 @@ impl App {
             row![
                 text(mode_label).size(12),
                 iced::widget::Space::new().width(20),
-                text(format!("Ln {}, Col {}", line, col)).size(12),
+                text(position).size(12),
                 iced::widget::Space::new().width(20),
                 text(keys).size(12).font(iced::Font::MONOSPACE),
+                iced::widget::Space::new().width(20),
+                text(&self.status_message).size(12),
             ]
             .align_y(iced::Alignment::Center)
             .into()
         } else {
-            text(format!("Ln {}, Col {}", line, col)).size(12).into()
+            text(position).size(12).into()
         };
 
         container(
             row![
                 mode_row,
                 iced::widget::Space::new().width(Length::Fill),
+                text(self.indent.label()).size(12),
+                iced::widget::Space::new().width(20),
+                text(self.language.map_or("Plain Text", |l| l.name())).size(12),
+                iced::widget::Space::new().width(20),
                 text(format!("{} lines", lines)).size(12),
                 iced::widget::Space::new().width(20),
                 text(format!("{}%", zoom)).size(12),
@@ -207,21 +227,29 @@ impl App {
 
     pub fn undo_tree_panel(&self) -> Element<'_, Message> {
         use crate::undo_tree_widget::{
-            format_elapsed, node_positions, UndoTreeWidget,
+            format_elapsed, node_positions, UndoTreeMinimap, UndoTreeWidget,
             NODE_R, ROW_HEIGHT, START_Y,
         };
         use iced::widget::{scrollable, stack};
 
-        let positions = node_positions(&self.undo_tree.nodes);
+        let zoom = self.undo_zoom;
+        let positions = node_positions(&self.undo_tree.nodes, &self.undo_collapsed, zoom);
+        let filtering = !self.undo_search_query.is_empty();
+        let search_hits = &self.undo_search_hits;
+        let palette = self.editor_theme();
 
         let tree = UndoTreeWidget::new(
             &self.undo_tree.nodes,
             self.undo_tree.current,
             self.selected_undo_node,
+            &self.undo_collapsed,
+            filtering.then_some(search_hits.as_slice()),
+            palette.clone(),
             Message::UndoTreeSelect,
-        );
+        )
+        .zoom(zoom);
 
-        let top_offset = START_Y - ROW_HEIGHT / 2.0;
+        let top_offset = (START_Y - ROW_HEIGHT / 2.0) * zoom;
         let mut labels_col = column![
             iced::widget::Space::new().height(top_offset.max(0.0))
         ].spacing(0);
@@ -229,25 +257,36 @@ impl App {
             let node = &self.undo_tree.nodes[id];
             let is_current = id == self.undo_tree.current;
             let is_selected = self.selected_undo_node == Some(id);
-            let elapsed = format_elapsed(node.timestamp);
+            let mut elapsed = match node.annotation() {
+                Some(annotation) => format!("{} {}", format_elapsed(node.timestamp), annotation),
+                None => format_elapsed(node.timestamp),
+            };
+            if self.undo_collapsed.contains(&id) && !node.children.is_empty() {
+                elapsed = format!("{} (+{})", elapsed, self.undo_tree.descendant_count(id));
+            }
             let label = if is_current { format!("● {}", elapsed) } else { elapsed };
             let label_color = if is_current {
-                iced::Color::from_rgb(1.0, 0.85, 0.3)
+                palette.accent
             } else if is_selected {
-                iced::Color::from_rgb(0.6, 0.8, 1.0)
+                palette.palette.primary
+            } else if filtering && search_hits.contains(&id) {
+                palette.palette.success
+            } else if filtering {
+                palette.muted.scale_alpha(0.5)
             } else {
-                iced::Color::from_rgb(0.5, 0.5, 0.5)
+                palette.muted
             };
-            let left_pad = x + NODE_R + 6.0;
+            let left_pad = x + (NODE_R + 6.0) * zoom;
             labels_col = labels_col.push(
                 container(
                     text(label)
-                        .size(10)
+                        .size(10.0 * zoom)
                         .font(iced::Font::MONOSPACE)
+                        .wrapping(iced::widget::text::Wrapping::None)
                         .style(move |_: &Theme| text::Style { color: Some(label_color) })
                 )
                 .padding(iced::Padding { top: 0.0, bottom: 0.0, left: left_pad, right: 0.0 })
-                .height(ROW_HEIGHT)
+                .height(ROW_HEIGHT * zoom)
                 .align_y(iced::Alignment::Center)
             );
         }
@@ -255,7 +294,24 @@ impl App {
         let tree_with_labels = scrollable(stack![
             tree,
             labels_col,
-        ]);
+        ])
+        .id(UNDO_TREE_SCROLL_ID)
+        .direction(scrollable::Direction::Both {
+            vertical: scrollable::Scrollbar::new(),
+            horizontal: scrollable::Scrollbar::new(),
+        })
+        .on_scroll(Message::UndoPanelScrolled)
+        .width(Fill)
+        .height(Fill);
+
+        let minimap = UndoTreeMinimap::new(
+            &self.undo_tree.nodes,
+            self.undo_tree.current,
+            &self.undo_collapsed,
+            self.undo_viewport,
+            palette.clone(),
+            Message::UndoMinimapJump,
+        );
 
         let preview_label = if self.undo_preview_text.is_empty() {
             "select a node to preview"
@@ -286,10 +342,22 @@ impl App {
             ..Default::default()
         });
 
+        let label_input = text_input("Label selected node...", &self.undo_label_input)
+            .size(11)
+            .on_input(Message::UndoLabelChanged)
+            .on_submit(Message::UndoLabelSubmit);
+        let search_input = text_input("Filter nodes...", &self.undo_search_query)
+            .id(UNDO_FILTER_INPUT_ID)
+            .size(11)
+            .on_input(Message::UndoSearchChanged)
+            .on_submit(Message::UndoSearchStep(1));
+
         let focused = self.undo_panel_focused;
+        let accent = palette.accent;
         container(
             column![
-                container(tree_with_labels)
+                column![label_input, search_input].spacing(2).padding(4),
+                container(row![tree_with_labels, minimap])
                     .width(Fill)
                     .height(Length::FillPortion(2)),
                 preview,
@@ -302,7 +370,7 @@ impl App {
             border: iced::Border {
                 width: 1.0,
                 color: if focused {
-                    iced::Color::from_rgb(1.0, 0.75, 0.0)
+                    accent
                 } else {
                     theme.extended_palette().background.strong.color
                 },
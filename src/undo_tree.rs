@@ -1,7 +1,86 @@
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 
 pub type NodeId = usize;
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UndoGranularity {
+    Pause,
+    Word,
+    Edit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Insert,
+    Whitespace,
+    Newline,
+    Delete,
+    DeleteWhitespace,
+    Other,
+}
+
+impl EditKind {
+    pub fn of(edit: &iced::widget::text_editor::Edit, removed: Option<char>) -> Self {
+        use iced::widget::text_editor::Edit;
+        match edit {
+            Edit::Insert(c) if c.is_whitespace() => EditKind::Whitespace,
+            Edit::Insert(_) => EditKind::Insert,
+            Edit::Enter => EditKind::Newline,
+            Edit::Backspace | Edit::Delete if removed.is_some_and(char::is_whitespace) => EditKind::DeleteWhitespace,
+            Edit::Backspace | Edit::Delete => EditKind::Delete,
+            Edit::Paste(_) | Edit::Indent | Edit::Unindent => EditKind::Other,
+        }
+    }
+
+    fn is_delete(self) -> bool {
+        matches!(self, EditKind::Delete | EditKind::DeleteWhitespace)
+    }
+}
+
+#[derive(Default)]
+pub struct Coalescer {
+    last_kind: Option<EditKind>,
+    last_at: Option<Instant>,
+    interrupted: bool,
+}
+
+impl Coalescer {
+    pub fn breaks_before(&mut self, kind: EditKind, now: Instant, granularity: UndoGranularity, pause: Duration) -> bool {
+        let last_kind = self.last_kind.replace(kind);
+        let last_at = self.last_at.replace(now);
+        let interrupted = std::mem::take(&mut self.interrupted);
+        let Some(last_kind) = last_kind else { return false };
+        if interrupted || kind == EditKind::Other {
+            return true;
+        }
+        if last_kind.is_delete() != kind.is_delete() {
+            return true;
+        }
+        match granularity {
+            UndoGranularity::Pause => last_at.is_some_and(|t| now.duration_since(t) >= pause),
+            UndoGranularity::Word => match kind {
+                EditKind::Insert => matches!(last_kind, EditKind::Whitespace | EditKind::Newline),
+                EditKind::Delete => last_kind == EditKind::DeleteWhitespace,
+                _ => false,
+            },
+            UndoGranularity::Edit => true,
+        }
+    }
+
+    pub fn breaks_after(kind: EditKind, granularity: UndoGranularity) -> bool {
+        granularity == UndoGranularity::Edit || kind == EditKind::Other
+    }
+
+    pub fn interrupt(&mut self) {
+        self.interrupted = true;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Snapshot {
     pub text: String,
@@ -16,6 +95,29 @@ pub struct UndoNode {
     pub parent: Option<NodeId>,
     pub children: Vec<NodeId>,
     pub timestamp: u64,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub saved: bool,
+}
+
+impl UndoNode {
+    pub fn annotation(&self) -> Option<String> {
+        match (&self.label, self.saved) {
+            (Some(label), true) => Some(format!("{} [saved]", label)),
+            (Some(label), false) => Some(label.clone()),
+            (None, true) => Some(String::from("[saved]")),
+            (None, false) => None,
+        }
+    }
+
+    pub fn matches_label(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        if self.saved && query.len() >= 3 && "saved".starts_with(&query) {
+            return true;
+        }
+        self.label.as_ref().is_some_and(|l| l.to_lowercase().contains(&query))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,6 +126,8 @@ pub struct UndoTree {
     pub current: NodeId,
 }
 
+const SEARCH_TEXT_BUDGET: usize = 8 << 20;
+
 fn now_secs() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -39,6 +143,8 @@ impl UndoTree {
             parent: None,
             children: Vec::new(),
             timestamp: now_secs(),
+            label: None,
+            saved: false,
         };
         Self { nodes: vec![root], current: 0 }
     }
@@ -52,6 +158,8 @@ impl UndoTree {
             parent: Some(parent),
             children: Vec::new(),
             timestamp: now_secs(),
+            label: None,
+            saved: false,
         });
         self.nodes[parent].children.push(id);
         self.current = id;
@@ -78,6 +186,68 @@ impl UndoTree {
         Some(self.nodes[id].snapshot.clone())
     }
 
+    pub fn set_label(&mut self, id: NodeId, label: &str) {
+        if let Some(node) = self.nodes.get_mut(id) {
+            let label = label.trim();
+            node.label = if label.is_empty() { None } else { Some(label.to_string()) };
+        }
+    }
+
+    pub fn mark_saved(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes.get_mut(id) {
+            node.saved = true;
+        }
+    }
+
+    // Snapshot text is searched newest first until `SEARCH_TEXT_BUDGET` bytes
+    // have been scanned; labels are always searched.
+    pub fn search(&self, query: &str) -> Vec<NodeId> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let query = query.to_lowercase();
+        let mut budget = SEARCH_TEXT_BUDGET;
+        let mut hits: Vec<NodeId> = self
+            .nodes
+            .iter()
+            .rev()
+            .filter(|n| {
+                if n.matches_label(&query) {
+                    return true;
+                }
+                let text = &n.snapshot.text;
+                if text.len() > budget {
+                    return false;
+                }
+                budget -= text.len();
+                text.to_lowercase().contains(&query)
+            })
+            .map(|n| n.id)
+            .collect();
+        hits.reverse();
+        hits
+    }
+
+    pub fn descendant_count(&self, id: NodeId) -> usize {
+        let mut count = 0;
+        let mut stack = self.nodes[id].children.clone();
+        while let Some(child) = stack.pop() {
+            count += 1;
+            stack.extend_from_slice(&self.nodes[child].children);
+        }
+        count
+    }
+
+    pub fn active_path(&self) -> Vec<NodeId> {
+        let mut path = vec![self.current];
+        let mut id = self.current;
+        while let Some(parent) = self.nodes[id].parent {
+            path.push(parent);
+            id = parent;
+        }
+        path
+    }
+
     pub fn reset(&mut self, snapshot: Snapshot) {
         self.nodes.clear();
         let root = UndoNode {
@@ -86,8 +256,130 @@ impl UndoTree {
             parent: None,
             children: Vec::new(),
             timestamp: now_secs(),
+            label: None,
+            saved: false,
         };
         self.nodes.push(root);
         self.current = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAUSE: Duration = Duration::from_millis(1000);
+
+    fn breaks(granularity: UndoGranularity, edits: &[(EditKind, u64)]) -> Vec<bool> {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::default();
+        edits
+            .iter()
+            .map(|&(kind, ms)| {
+                let now = start + Duration::from_millis(ms);
+                coalescer.breaks_before(kind, now, granularity, PAUSE)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edit_granularity_breaks_around_every_edit() {
+        let edits = [(EditKind::Insert, 0), (EditKind::Insert, 10), (EditKind::Delete, 20)];
+        assert_eq!(breaks(UndoGranularity::Edit, &edits), [false, true, true]);
+        assert!(Coalescer::breaks_after(EditKind::Insert, UndoGranularity::Edit));
+        assert!(Coalescer::breaks_after(EditKind::Delete, UndoGranularity::Edit));
+    }
+
+    #[test]
+    fn pause_granularity_breaks_after_a_pause() {
+        let edits = [
+            (EditKind::Insert, 0),
+            (EditKind::Whitespace, 100),
+            (EditKind::Insert, 200),
+            (EditKind::Insert, 1500),
+            (EditKind::Newline, 1600),
+        ];
+        assert_eq!(breaks(UndoGranularity::Pause, &edits), [false, false, false, true, false]);
+    }
+
+    #[test]
+    fn word_granularity_breaks_before_each_typed_word() {
+        let edits = [
+            (EditKind::Insert, 0),
+            (EditKind::Insert, 10),
+            (EditKind::Whitespace, 20),
+            (EditKind::Insert, 30),
+            (EditKind::Newline, 40),
+            (EditKind::Insert, 5000),
+        ];
+        assert_eq!(breaks(UndoGranularity::Word, &edits), [false, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn word_granularity_breaks_before_each_deleted_word() {
+        let edits = [
+            (EditKind::Delete, 0),
+            (EditKind::Delete, 10),
+            (EditKind::DeleteWhitespace, 20),
+            (EditKind::DeleteWhitespace, 30),
+            (EditKind::Delete, 40),
+            (EditKind::Delete, 50),
+        ];
+        assert_eq!(breaks(UndoGranularity::Word, &edits), [false, false, false, false, true, false]);
+    }
+
+    #[test]
+    fn switching_between_typing_and_deleting_breaks() {
+        let edits = [(EditKind::Insert, 0), (EditKind::Delete, 10), (EditKind::DeleteWhitespace, 20), (EditKind::Insert, 30)];
+        for granularity in [UndoGranularity::Pause, UndoGranularity::Word] {
+            assert_eq!(breaks(granularity, &edits), [false, true, false, true]);
+        }
+    }
+
+    #[test]
+    fn other_edits_stand_alone() {
+        let edits = [(EditKind::Insert, 0), (EditKind::Other, 10)];
+        for granularity in [UndoGranularity::Pause, UndoGranularity::Word] {
+            assert_eq!(breaks(granularity, &edits), [false, true]);
+            assert!(Coalescer::breaks_after(EditKind::Other, granularity));
+            assert!(!Coalescer::breaks_after(EditKind::Insert, granularity));
+        }
+    }
+
+    #[test]
+    fn saved_nodes_match_only_the_saved_keyword() {
+        let mut tree = UndoTree::new(Snapshot { text: String::new(), cursor_line: 0, cursor_col: 0 });
+        tree.mark_saved(0);
+        let node = &tree.nodes[0];
+        for query in ["saved", "Sav"] {
+            assert!(node.matches_label(query), "{query}");
+        }
+        for query in ["", "a", "s", "ved", "saved!"] {
+            assert!(!node.matches_label(query), "{query}");
+        }
+    }
+
+    #[test]
+    fn search_ignores_case_in_labels_and_text() {
+        let snapshot = |text: &str| Snapshot { text: text.to_string(), cursor_line: 0, cursor_col: 0 };
+        let mut tree = UndoTree::new(snapshot(""));
+        tree.push(snapshot("fn Main() {}"));
+        tree.push(snapshot("other"));
+        tree.set_label(2, "Refactor");
+        assert_eq!(tree.search("MAIN"), [1]);
+        assert_eq!(tree.search("refactor"), [2]);
+    }
+
+    #[test]
+    fn interruption_breaks_the_next_edit() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::default();
+        for granularity in [UndoGranularity::Pause, UndoGranularity::Word] {
+            coalescer.reset();
+            assert!(!coalescer.breaks_before(EditKind::Insert, start, granularity, PAUSE));
+            coalescer.interrupt();
+            assert!(coalescer.breaks_before(EditKind::Insert, start, granularity, PAUSE));
+            assert!(!coalescer.breaks_before(EditKind::Insert, start, granularity, PAUSE));
+        }
+    }
+}
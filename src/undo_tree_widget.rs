@@ -4,6 +4,10 @@ use iced::advanced::widget::{self, Widget};
 use iced::advanced::{Clipboard, Shell};
 use iced::mouse;
 use iced::{Color, Element, Event, Length, Rectangle, Size, Theme};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::theme::EditorTheme;
 
 use crate::undo_tree::{NodeId, UndoNode};
 
@@ -32,32 +36,80 @@ pub fn format_elapsed(ts: u64) -> String {
     }
 }
 
-pub fn node_positions(nodes: &[UndoNode]) -> Vec<(NodeId, f32, f32)> {
+pub fn node_positions(nodes: &[UndoNode], collapsed: &HashSet<NodeId>, zoom: f32) -> Vec<(NodeId, f32, f32)> {
     if nodes.is_empty() {
         return Vec::new();
     }
     let mut result = Vec::new();
-    let mut row = 0usize;
-    fn dfs(id: NodeId, x: f32, row: &mut usize, nodes: &[UndoNode], result: &mut Vec<(NodeId, f32, f32)>) {
-        let y = START_Y + *row as f32 * ROW_HEIGHT;
+    let mut stack = vec![(0, START_X * zoom)];
+    while let Some((id, x)) = stack.pop() {
+        let y = (START_Y + result.len() as f32 * ROW_HEIGHT) * zoom;
         result.push((id, x, y));
-        *row += 1;
-        let children = &nodes[id].children;
-        for (i, &child_id) in children.iter().enumerate().skip(1) {
-            dfs(child_id, x + i as f32 * BRANCH_X, row, nodes, result);
+        if collapsed.contains(&id) {
+            continue;
         }
+        let children = &nodes[id].children;
         if let Some(&main_child) = children.first() {
-            dfs(main_child, x, row, nodes, result);
+            stack.push((main_child, x));
+        }
+        for (i, &child_id) in children.iter().enumerate().skip(1).rev() {
+            stack.push((child_id, x + i as f32 * BRANCH_X * zoom));
         }
     }
-    dfs(0, START_X, &mut row, nodes, &mut result);
     result
 }
 
+fn draw_edges(
+    renderer: &mut Renderer,
+    nodes: &[UndoNode],
+    positions: &[(NodeId, f32, f32)],
+    origin: iced::Point,
+    node_r: f32,
+    row_height: f32,
+    color: Color,
+) {
+    let pos_map: HashMap<NodeId, (f32, f32)> =
+        positions.iter().map(|&(id, x, y)| (id, (x, y))).collect();
+
+    for &(id, x, y) in positions {
+        let ax = origin.x + x;
+        let ay = origin.y + y;
+        let Some(parent_id) = nodes[id].parent else { continue };
+        let Some(&(px, py)) = pos_map.get(&parent_id) else { continue };
+        let bpx = origin.x + px;
+        let bpy = origin.y + py;
+        if (ax - bpx).abs() < 1.0 {
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle { x: ax - 0.5, y: bpy + node_r, width: 1.0, height: ay - bpy - node_r * 2.0 },
+                ..Default::default()
+            }, color);
+        } else {
+            let mid_y = bpy + row_height * 0.5;
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle { x: bpx - 0.5, y: bpy + node_r, width: 1.0, height: mid_y - bpy - node_r },
+                ..Default::default()
+            }, color);
+            let (lx, rx) = if ax > bpx { (bpx, ax) } else { (ax, bpx) };
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle { x: lx, y: mid_y - 0.5, width: rx - lx + 1.0, height: 1.0 },
+                ..Default::default()
+            }, color);
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle { x: ax - 0.5, y: mid_y, width: 1.0, height: ay - mid_y - node_r },
+                ..Default::default()
+            }, color);
+        }
+    }
+}
+
 pub struct UndoTreeWidget<'a, Message> {
     nodes: &'a [UndoNode],
     current: NodeId,
     selected: Option<NodeId>,
+    collapsed: &'a HashSet<NodeId>,
+    filter_hits: Option<&'a [NodeId]>,
+    zoom: f32,
+    theme: Arc<EditorTheme>,
     on_select: Box<dyn Fn(NodeId) -> Message + 'a>,
 }
 
@@ -66,9 +118,17 @@ impl<'a, Message> UndoTreeWidget<'a, Message> {
         nodes: &'a [UndoNode],
         current: NodeId,
         selected: Option<NodeId>,
+        collapsed: &'a HashSet<NodeId>,
+        filter_hits: Option<&'a [NodeId]>,
+        theme: Arc<EditorTheme>,
         on_select: impl Fn(NodeId) -> Message + 'a,
     ) -> Self {
-        Self { nodes, current, selected, on_select: Box::new(on_select) }
+        Self { nodes, current, selected, collapsed, filter_hits, zoom: 1.0, theme, on_select: Box::new(on_select) }
+    }
+
+    pub fn zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
     }
 }
 
@@ -79,13 +139,17 @@ impl<Message: Clone> Widget<Message, Theme, Renderer> for UndoTreeWidget<'_, Mes
     fn diff(&self, _tree: &mut widget::Tree) {}
 
     fn size(&self) -> Size<Length> {
-        Size { width: Length::Fixed(PANEL_WIDTH), height: Length::Shrink }
+        Size { width: Length::Shrink, height: Length::Shrink }
     }
 
     fn layout(&mut self, _tree: &mut widget::Tree, _renderer: &Renderer, _limits: &layout::Limits) -> layout::Node {
-        let n = self.nodes.len();
-        let height = START_Y + n as f32 * ROW_HEIGHT + 8.0;
-        layout::Node::new(Size::new(PANEL_WIDTH, height))
+        let positions = node_positions(self.nodes, self.collapsed, self.zoom);
+        let height = (START_Y + positions.len() as f32 * ROW_HEIGHT + 8.0) * self.zoom;
+        let width = positions
+            .iter()
+            .map(|&(_, x, _)| x + PANEL_WIDTH * 0.6)
+            .fold(PANEL_WIDTH, f32::max);
+        layout::Node::new(Size::new(width, height))
     }
 
     fn draw(
@@ -94,58 +158,40 @@ impl<Message: Clone> Widget<Message, Theme, Renderer> for UndoTreeWidget<'_, Mes
         layout: Layout<'_>, _cursor: mouse::Cursor, _viewport: &Rectangle,
     ) {
         let bounds = layout.bounds();
+        let node_r = NODE_R * self.zoom;
 
-        let positions = node_positions(self.nodes);
-        let pos_map: std::collections::HashMap<NodeId, (f32, f32)> =
-            positions.iter().map(|&(id, x, y)| (id, (x, y))).collect();
+        let positions = node_positions(self.nodes, self.collapsed, self.zoom);
+        draw_edges(renderer, self.nodes, &positions, bounds.position(), node_r, ROW_HEIGHT * self.zoom, self.theme.gutter);
 
         for &(id, x, y) in &positions {
             let ax = bounds.x + x;
             let ay = bounds.y + y;
 
-            if let Some(parent_id) = self.nodes[id].parent {
-                if let Some(&(px, py)) = pos_map.get(&parent_id) {
-                    let bpx = bounds.x + px;
-                    let bpy = bounds.y + py;
-                    if (ax - bpx).abs() < 1.0 {
-                        renderer.fill_quad(renderer::Quad {
-                            bounds: Rectangle { x: ax - 0.5, y: bpy + NODE_R, width: 1.0, height: ay - bpy - NODE_R * 2.0 },
-                            ..Default::default()
-                        }, Color::from_rgb(0.35, 0.35, 0.35));
-                    } else {
-                        let mid_y = bpy + ROW_HEIGHT * 0.5;
-                        renderer.fill_quad(renderer::Quad {
-                            bounds: Rectangle { x: bpx - 0.5, y: bpy + NODE_R, width: 1.0, height: mid_y - bpy - NODE_R },
-                            ..Default::default()
-                        }, Color::from_rgb(0.35, 0.35, 0.35));
-                        let (lx, rx) = if ax > bpx { (bpx, ax) } else { (ax, bpx) };
-                        renderer.fill_quad(renderer::Quad {
-                            bounds: Rectangle { x: lx, y: mid_y - 0.5, width: rx - lx + 1.0, height: 1.0 },
-                            ..Default::default()
-                        }, Color::from_rgb(0.35, 0.35, 0.35));
-                        renderer.fill_quad(renderer::Quad {
-                            bounds: Rectangle { x: ax - 0.5, y: mid_y, width: 1.0, height: ay - mid_y - NODE_R },
-                            ..Default::default()
-                        }, Color::from_rgb(0.35, 0.35, 0.35));
-                    }
-                }
-            }
-
             let is_current = id == self.current;
             let is_selected = self.selected == Some(id);
+            let is_filtered_out = self.filter_hits.is_some_and(|hits| !hits.contains(&id));
             let node_color = if is_current {
-                Color::from_rgb(1.0, 0.75, 0.0)
+                self.theme.accent
             } else if is_selected {
-                Color::from_rgb(0.4, 0.7, 1.0)
+                self.theme.palette.primary
+            } else if is_filtered_out {
+                self.theme.gutter.scale_alpha(0.5)
+            } else if self.filter_hits.is_some() {
+                self.theme.palette.success
             } else {
-                Color::from_rgb(0.4, 0.4, 0.4)
+                self.theme.gutter
             };
 
+            let is_collapsed = self.collapsed.contains(&id) && !self.nodes[id].children.is_empty();
             renderer.fill_quad(renderer::Quad {
-                bounds: Rectangle { x: ax - NODE_R, y: ay - NODE_R, width: NODE_R * 2.0, height: NODE_R * 2.0 },
-                border: iced::Border { radius: NODE_R.into(), ..Default::default() },
+                bounds: Rectangle { x: ax - node_r, y: ay - node_r, width: node_r * 2.0, height: node_r * 2.0 },
+                border: iced::Border {
+                    radius: node_r.into(),
+                    width: if is_collapsed { 2.0 } else { 0.0 },
+                    color: node_color,
+                },
                 ..Default::default()
-            }, node_color);
+            }, if is_collapsed { Color::TRANSPARENT } else { node_color });
         }
     }
 
@@ -157,10 +203,10 @@ impl<Message: Clone> Widget<Message, Theme, Renderer> for UndoTreeWidget<'_, Mes
         if let Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) = event {
             if let Some(pos) = cursor.position() {
                 let bounds = layout.bounds();
-                for (id, x, y) in node_positions(self.nodes) {
+                for (id, x, y) in node_positions(self.nodes, self.collapsed, self.zoom) {
                     let cx = bounds.x + x;
                     let cy = bounds.y + y;
-                    if ((pos.x - cx).powi(2) + (pos.y - cy).powi(2)).sqrt() <= CLICK_RADIUS {
+                    if ((pos.x - cx).powi(2) + (pos.y - cy).powi(2)).sqrt() <= CLICK_RADIUS * self.zoom {
                         shell.publish((self.on_select)(id));
                         return;
                     }
@@ -175,10 +221,10 @@ impl<Message: Clone> Widget<Message, Theme, Renderer> for UndoTreeWidget<'_, Mes
     ) -> mouse::Interaction {
         if let Some(pos) = cursor.position() {
             let bounds = layout.bounds();
-            for (_, x, y) in node_positions(self.nodes) {
+            for (_, x, y) in node_positions(self.nodes, self.collapsed, self.zoom) {
                 let cx = bounds.x + x;
                 let cy = bounds.y + y;
-                if ((pos.x - cx).powi(2) + (pos.y - cy).powi(2)).sqrt() <= CLICK_RADIUS {
+                if ((pos.x - cx).powi(2) + (pos.y - cy).powi(2)).sqrt() <= CLICK_RADIUS * self.zoom {
                     return mouse::Interaction::Pointer;
                 }
             }
@@ -198,3 +244,137 @@ impl<'a, Message: Clone + 'a> From<UndoTreeWidget<'a, Message>> for Element<'a,
     fn from(w: UndoTreeWidget<'a, Message>) -> Element<'a, Message> { Element::new(w) }
 }
 
+pub const MINIMAP_WIDTH: f32 = 24.0;
+
+pub struct UndoTreeMinimap<'a, Message> {
+    nodes: &'a [UndoNode],
+    current: NodeId,
+    collapsed: &'a HashSet<NodeId>,
+    viewport: Option<(f32, f32, f32)>,
+    theme: Arc<EditorTheme>,
+    on_jump: Box<dyn Fn(f32) -> Message + 'a>,
+}
+
+impl<'a, Message> UndoTreeMinimap<'a, Message> {
+    pub fn new(
+        nodes: &'a [UndoNode],
+        current: NodeId,
+        collapsed: &'a HashSet<NodeId>,
+        viewport: Option<(f32, f32, f32)>,
+        theme: Arc<EditorTheme>,
+        on_jump: impl Fn(f32) -> Message + 'a,
+    ) -> Self {
+        Self { nodes, current, collapsed, viewport, theme, on_jump: Box::new(on_jump) }
+    }
+
+    fn publish_jump(&self, bounds: Rectangle, cursor: mouse::Cursor, shell: &mut Shell<'_, Message>) {
+        if let Some(pos) = cursor.position_in(bounds) {
+            shell.publish((self.on_jump)((pos.y / bounds.height).clamp(0.0, 1.0)));
+        }
+    }
+}
+
+impl<Message: Clone> Widget<Message, Theme, Renderer> for UndoTreeMinimap<'_, Message> {
+    fn tag(&self) -> widget::tree::Tag { widget::tree::Tag::of::<bool>() }
+    fn state(&self) -> widget::tree::State { widget::tree::State::new(false) }
+    fn children(&self) -> Vec<widget::Tree> { vec![] }
+    fn diff(&self, _tree: &mut widget::Tree) {}
+
+    fn size(&self) -> Size<Length> {
+        Size { width: Length::Fixed(MINIMAP_WIDTH), height: Length::Fill }
+    }
+
+    fn layout(&mut self, _tree: &mut widget::Tree, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.resolve(MINIMAP_WIDTH, Length::Fill, Size::ZERO))
+    }
+
+    fn draw(
+        &self, _tree: &widget::Tree, renderer: &mut Renderer,
+        _theme: &Theme, _style: &renderer::Style,
+        layout: Layout<'_>, _cursor: mouse::Cursor, _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let positions = node_positions(self.nodes, self.collapsed, 1.0);
+        let total_height = START_Y + positions.len() as f32 * ROW_HEIGHT + 8.0;
+        let scale_y = (bounds.height / total_height).min(1.0);
+        let max_x = positions.iter().map(|&(_, x, _)| x).fold(PANEL_WIDTH, f32::max);
+        let scale_x = (bounds.width - 4.0) / max_x;
+
+        renderer.fill_quad(renderer::Quad { bounds, ..Default::default() }, self.theme.gutter.scale_alpha(0.2));
+
+        if let Some((offset, visible, content)) = self.viewport
+            && content > 0.0
+        {
+            let ratio = bounds.height.min(total_height * scale_y) / content;
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle {
+                    x: bounds.x,
+                    y: bounds.y + offset * ratio,
+                    width: bounds.width,
+                    height: (visible * ratio).max(2.0),
+                },
+                ..Default::default()
+            }, self.theme.selection);
+        }
+
+        for &(id, x, y) in &positions {
+            let color = if id == self.current { self.theme.accent } else { self.theme.muted };
+            let size = if id == self.current { 4.0 } else { 2.0 };
+            renderer.fill_quad(renderer::Quad {
+                bounds: Rectangle {
+                    x: bounds.x + 2.0 + x * scale_x - size / 2.0,
+                    y: bounds.y + y * scale_y - size / 2.0,
+                    width: size,
+                    height: size,
+                },
+                ..Default::default()
+            }, color);
+        }
+    }
+
+    fn update(
+        &mut self, tree: &mut widget::Tree, event: &Event,
+        layout: Layout<'_>, cursor: mouse::Cursor, _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard, shell: &mut Shell<'_, Message>, _viewport: &Rectangle,
+    ) {
+        let dragging = tree.state.downcast_mut::<bool>();
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left))
+                if cursor.is_over(layout.bounds()) =>
+            {
+                *dragging = true;
+                self.publish_jump(layout.bounds(), cursor, shell);
+                shell.capture_event();
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) if *dragging => {
+                self.publish_jump(layout.bounds(), cursor, shell);
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                *dragging = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn mouse_interaction(
+        &self, _tree: &widget::Tree, layout: Layout<'_>,
+        cursor: mouse::Cursor, _viewport: &Rectangle, _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        if cursor.is_over(layout.bounds()) {
+            mouse::Interaction::Pointer
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+
+    fn operate(&mut self, _: &mut widget::Tree, _: Layout<'_>, _: &Renderer, _: &mut dyn widget::Operation) {}
+
+    fn overlay<'b>(
+        &'b mut self, _: &'b mut widget::Tree, _: Layout<'b>,
+        _: &Renderer, _: &Rectangle, _: iced::Vector,
+    ) -> Option<iced::advanced::overlay::Element<'b, Message, Theme, Renderer>> { None }
+}
+
+impl<'a, Message: Clone + 'a> From<UndoTreeMinimap<'a, Message>> for Element<'a, Message> {
+    fn from(w: UndoTreeMinimap<'a, Message>) -> Element<'a, Message> { Element::new(w) }
+}
@@ -1,13 +1,25 @@
 use iced::widget::text_editor;
-use iced::widget::operation;
+use iced::widget::operation::{self, AbsoluteOffset};
 use iced::Task;
+use regex::Regex;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::app::{App, EDITOR_ID};
-use crate::format::format_document;
+use crate::ex::{self, Global, LineCommand, Substitute, SubstituteSession};
+use crate::format::{self, FormatOutcome, format_document, strip_trailing_whitespace};
+use crate::history::HistoryKind;
 use crate::message::{LineNumbers, Message, PendingAction, VimMode, VimPending};
+use crate::line_map::RowEdit;
+use crate::search::{
+    EditSpan, FIND_INPUT_ID, LineIndex, REPLACE_INPUT_ID, build_search_regex, char_boundary, expand_replacement, match_byte_range, replace_all_matches,
+};
 use crate::subscription::{COMMAND_INPUT_ID, SEARCH_INPUT_ID};
-use crate::undo_tree;
+use crate::syntax::Language;
+use crate::ui::{UNDO_FILTER_INPUT_ID, UNDO_TREE_SCROLL_ID};
+use crate::undo_tree::{self, Coalescer, EditKind, UndoGranularity};
+use crate::undo_tree_widget::{node_positions, ROW_HEIGHT};
 
 impl App {
     fn vim_do_delete_lines(&mut self, count: usize) {
@@ -39,7 +51,7 @@ impl App {
             self.vim_register = sel.clone();
             if op == 'd' || op == 'c' {
                 self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-                self.is_modified = true;
+                self.finish_operator(op);
             }
         }
         if op == 'c' {
@@ -64,6 +76,45 @@ impl App {
                 self.vim_do_word_object(op, modifier);
                 return;
             }
+            'p' => {
+                let lines: Vec<&str> = text.split('\n').collect();
+                let line = cursor.position.line.min(lines.len() - 1);
+                let blank = |l: usize| lines[l].trim().is_empty();
+                let kind = blank(line);
+                let mut start = line;
+                while start > 0 && blank(start - 1) == kind {
+                    start -= 1;
+                }
+                let mut end = line + 1;
+                while end < lines.len() && blank(end) == kind {
+                    end += 1;
+                }
+                if modifier == 'a' {
+                    while end < lines.len() && blank(end) != kind {
+                        end += 1;
+                    }
+                }
+                let sel_start = self.byte_offset_of(start, 0, &text);
+                let sel_end = self.byte_offset_of(end, 0, &text).min(text.len());
+                self.vim_select_object(op, sel_start, sel_end, &text);
+                return;
+            }
+            #[cfg(feature = "tree-sitter")]
+            'f' | 'c' | 'a' => {
+                self.refresh_syntax_tree(None);
+                let line = cursor.position.line;
+                let Some((start, end)) = self
+                    .syntax_tree
+                    .as_ref()
+                    .and_then(|t| t.text_object(line, cursor.position.column, object, modifier == 'i'))
+                else {
+                    return;
+                };
+                let sel_start = self.byte_offset_of(start.0, start.1, &text);
+                let sel_end = self.byte_offset_of(end.0, end.1, &text);
+                self.vim_select_object(op, sel_start, sel_end, &text);
+                return;
+            }
             _ => return,
         };
 
@@ -90,11 +141,16 @@ impl App {
             (start, end + 1)
         };
 
-        let yanked = text[sel_start..sel_end].to_string();
-        self.vim_register = yanked;
+        self.vim_select_object(op, sel_start, sel_end, &text);
+    }
+
+    fn vim_select_object(&mut self, op: char, sel_start: usize, sel_end: usize, text: &str) {
+        if op != '=' {
+            self.vim_register = text[sel_start..sel_end].to_string();
+        }
 
-        let start_pos = self.position_of_byte_offset(sel_start, &text);
-        let end_pos = self.position_of_byte_offset(sel_end, &text);
+        let start_pos = self.position_of_byte_offset(sel_start, text);
+        let end_pos = self.position_of_byte_offset(sel_end, text);
 
         self.content.move_to(text_editor::Cursor {
             position: text_editor::Position { line: start_pos.0, column: start_pos.1 },
@@ -103,7 +159,7 @@ impl App {
 
         if op == 'd' || op == 'c' {
             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-            self.is_modified = true;
+            self.finish_operator(op);
         }
         if op == 'c' {
             self.vim_mode = VimMode::Insert;
@@ -144,7 +200,7 @@ impl App {
 
         if op == 'd' || op == 'c' {
             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-            self.is_modified = true;
+            self.finish_operator(op);
         }
         if op == 'c' {
             self.vim_mode = VimMode::Insert;
@@ -184,9 +240,14 @@ impl App {
     }
 
     fn vim_move_to_with_block(&mut self, line: usize, col: usize) {
-        let text = self.content.text();
-        let char_count = text.lines().nth(line).map(|l| l.chars().count()).unwrap_or(0);
-        let col = if char_count == 0 { 0 } else { col.min(char_count.saturating_sub(1)) };
+        let col = self
+            .content
+            .line(line)
+            .and_then(|l| {
+                let last_char = l.text.char_indices().last()?.0;
+                Some(char_boundary(&l.text, col.min(last_char)))
+            })
+            .unwrap_or(0);
         self.vim_col = col;
         self.content.move_to(text_editor::Cursor {
             position: text_editor::Position { line, column: col },
@@ -194,6 +255,14 @@ impl App {
         });
     }
 
+    fn vim_move_to_first_non_blank(&mut self, line: usize) {
+        let column = self
+            .content
+            .line(line)
+            .map_or(0, |l| l.text.len() - l.text.trim_start_matches([' ', '\t']).len());
+        self.vim_move_to_with_block(line, column);
+    }
+
     fn vim_apply_block_cursor(&mut self) {
         let cursor = self.content.cursor();
         self.vim_col = cursor.position.column;
@@ -407,7 +476,7 @@ impl App {
         }
     }
 
-    fn vim_word_under_cursor(&self) -> String {
+    pub fn vim_word_under_cursor(&self) -> String {
         let text = self.content.text();
         let cursor = self.content.cursor();
         let pos = self.byte_offset_of(cursor.position.line, cursor.position.column, &text);
@@ -454,25 +523,884 @@ impl App {
         let snap = self.take_snapshot();
         self.undo_tree.push(snap);
         self.last_snapshot_tick = self.changedtick;
+        if !self.undo_search_query.is_empty() {
+            self.refresh_undo_search();
+        }
         if let Some(path) = &self.current_file {
             crate::persistence::save_undo_tree(path, &self.undo_tree);
         }
     }
 
+    fn mark_changed(&mut self) {
+        self.is_modified = true;
+        self.changedtick += 1;
+    }
+
+    fn commit_undo_step(&mut self) {
+        if let Some((tick, deferred)) = &mut self.undo_batch
+            && self.changedtick != *tick
+        {
+            *deferred = true;
+            return;
+        }
+        if self.changedtick != self.last_snapshot_tick {
+            self.push_snapshot();
+        }
+    }
+
+    fn removed_char(&self, edit: &text_editor::Edit) -> Option<char> {
+        let cursor = self.content.cursor();
+        if cursor.selection.is_some() {
+            return None;
+        }
+        let position = cursor.position;
+        let line = self.content.line(position.line)?;
+        let column = char_boundary(&line.text, position.column);
+        match edit {
+            text_editor::Edit::Backspace if column == 0 => (position.line > 0).then_some('\n'),
+            text_editor::Edit::Backspace => line.text[..column].chars().next_back(),
+            text_editor::Edit::Delete if column == line.text.len() => {
+                (position.line + 1 < self.content.line_count()).then_some('\n')
+            }
+            text_editor::Edit::Delete => line.text[column..].chars().next(),
+            _ => None,
+        }
+    }
+
+    fn edit_span(
+        &self,
+        edit: &text_editor::Edit,
+        before: text_editor::Cursor,
+        line_len: usize,
+        line_count: usize,
+    ) -> Option<EditSpan> {
+        let position = |p: text_editor::Position| (p.line, p.column);
+        let cursor = position(before.position);
+        let (a, b) = match before.selection.map(position) {
+            Some(sel) => (cursor.min(sel), cursor.max(sel)),
+            None => (cursor, cursor),
+        };
+        let after = position(self.content.cursor().position);
+        let start = a.min(after);
+        let old_end = match edit {
+            text_editor::Edit::Indent | text_editor::Edit::Unindent => return None,
+            _ if a != b => b,
+            text_editor::Edit::Delete if self.content.line_count() < line_count => (a.0 + 1, 0),
+            text_editor::Edit::Delete => {
+                let len = self.content.line(a.0).map_or(0, |l| l.text.len());
+                (a.0, a.1 + line_len.saturating_sub(len))
+            }
+            _ => b,
+        };
+        let new_end = match edit {
+            text_editor::Edit::Backspace | text_editor::Edit::Delete => start,
+            _ => after,
+        };
+        Some(EditSpan { start, old_end, new_end })
+    }
+
+    fn cursor_lines(&self) -> std::ops::Range<usize> {
+        let cursor = self.content.cursor();
+        let line = cursor.position.line;
+        std::iter::once(cursor)
+            .chain(self.extra_cursors.iter().copied())
+            .flat_map(|c| std::iter::once(c.position).chain(c.selection))
+            .fold(line..line + 1, |lines, p| lines.start.min(p.line)..lines.end.max(p.line + 1))
+    }
+
+    fn touch_lines(&mut self, lines: std::ops::Range<usize>) {
+        self.edited_lines = Some(match self.edited_lines.take() {
+            Some(touched) => touched.start.min(lines.start)..touched.end.max(lines.end),
+            None => lines,
+        });
+    }
+
+    fn replace_content(&mut self, text: &str) {
+        self.content = text_editor::Content::with_text(text);
+        self.touch_lines(0..usize::MAX);
+    }
+
+    fn row_edit(&mut self, base: u64, line_count: usize, before: std::ops::Range<usize>) -> RowEdit {
+        let after = self.cursor_lines();
+        let mut lines = before.start.min(after.start)..before.end.max(after.end);
+        if let Some(touched) = self.edited_lines.take() {
+            lines = lines.start.min(touched.start)..lines.end.max(touched.end);
+        }
+        RowEdit::new(base, lines, line_count, self.content.line_count())
+    }
+
+    fn shift_find_scope(&mut self, span: EditSpan) {
+        if let Some(scope) = &mut self.find_scope {
+            *scope = span.map(scope.start)..span.map(scope.end);
+        }
+    }
+
+    fn finish_change(&mut self) {
+        self.mark_changed();
+        self.commit_undo_step();
+    }
+
+    fn finish_operator(&mut self, op: char) {
+        if op == 'c' {
+            self.mark_changed();
+        } else {
+            self.finish_change();
+        }
+    }
+
+    fn vim_record_visual_marks(&mut self) {
+        if let Some((anchor_line, _)) = self.vim_visual_anchor {
+            let head_line = self.vim_visual_head.0;
+            self.vim_visual_marks = Some((anchor_line.min(head_line), anchor_line.max(head_line)));
+        }
+    }
+
+    fn ex_command(&mut self, cmd: &str) -> Task<Message> {
+        let ctx = ex::LineContext {
+            current: self.content.cursor().position.line,
+            last: self.content.line_count().saturating_sub(1),
+            visual: self.vim_visual_marks,
+        };
+        let (range, rest) = match ex::parse_range(cmd, &ctx) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.status_message = err;
+                return Task::none();
+            }
+        };
+        let rest = rest.trim();
+        if let Some(options) = rest.strip_prefix("set ").or_else(|| rest.strip_prefix("se ")) {
+            let (mut indent, mut defaults) = (self.indent, self.indent_defaults);
+            let set = |option| indent.set_option(option).and_then(|()| defaults.set_option(option));
+            match options.split_whitespace().try_for_each(set) {
+                Ok(()) => {
+                    self.indent = indent;
+                    self.indent_defaults = defaults;
+                    self.save_settings();
+                }
+                Err(err) => self.status_message = err,
+            }
+            return Task::none();
+        }
+        let lines = range.unwrap_or((ctx.current, ctx.current));
+        if rest.is_empty() {
+            if range.is_some() {
+                self.vim_move_to_with_block(lines.1, 0);
+            }
+            return Task::none();
+        }
+        if let Some(global) = ex::parse_global(rest) {
+            let lines = range.unwrap_or((0, ctx.last));
+            match global {
+                Ok(global) => self.ex_global(lines, global),
+                Err(err) => self.status_message = err,
+            }
+            return Task::none();
+        }
+        if rest.len() >= 3 && "format".starts_with(rest) {
+            return self.format_lines(range.map(|(start, end)| start..end + 1));
+        }
+        match self.parse_substitute_command(rest) {
+            Some(Ok(sub)) => self.ex_substitute(lines, sub),
+            Some(Err(err)) => self.status_message = err,
+            None => self.status_message = format!("E492: Not an editor command: {}", cmd),
+        }
+        Task::none()
+    }
+
+    fn parse_substitute_command(&self, command: &str) -> Option<Result<Substitute, String>> {
+        let repeat = |global: bool| {
+            Some(match self.vim_last_substitute.clone() {
+                Some(sub) => Ok(Substitute { global: global && sub.global, confirm: false, count_only: false, ..sub }),
+                None => Err(String::from("E35: No previous regular expression")),
+            })
+        };
+        match command {
+            "s" | "&" => repeat(false),
+            "&&" => repeat(true),
+            _ => ex::parse_substitute(command),
+        }
+    }
+
+    fn prepare_substitute(&mut self, mut sub: Substitute) -> Result<(Regex, String, Substitute), String> {
+        if sub.pattern.is_empty() {
+            sub.pattern = match self.vim_last_substitute.as_ref() {
+                _ if !self.vim_search_pattern.is_empty() => self.vim_search_pattern.clone(),
+                Some(last) => last.pattern.clone(),
+                None => return Err(String::from("E35: No previous regular expression")),
+            };
+        }
+        let template = ex::resolve_tilde(&sub.replacement, &self.vim_last_replacement);
+        self.vim_last_replacement = template.clone();
+        self.vim_last_substitute = Some(sub.clone());
+        let regex = build_search_regex(&sub.pattern, sub.case_sensitive, true, false).map_err(|err| {
+            format!("E383: Invalid search string: {}", err.to_string().lines().last().unwrap_or(""))
+        })?;
+        self.vim_search_pattern = sub.pattern.clone();
+        self.vim_search_tick = None;
+        Ok((regex, template, sub))
+    }
+
+    fn ex_substitute(&mut self, lines: ex::LineRange, sub: Substitute) {
+        let (regex, template, sub) = match self.prepare_substitute(sub) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                self.status_message = err;
+                return;
+            }
+        };
+        let snapshot = self.text_snapshot();
+        let (text, index) = (snapshot.text(), snapshot.index());
+        let edits = ex::plan_substitution(&regex, &text, index, lines, sub.global, &template);
+        if edits.is_empty() {
+            self.status_message = format!("E486: Pattern not found: {}", sub.pattern);
+            return;
+        }
+
+        if sub.count_only {
+            let changed = ex::changed_lines(&edits, index);
+            self.status_message = format!(
+                "{} match{} on {} line{}",
+                edits.len(),
+                if edits.len() == 1 { "" } else { "es" },
+                changed,
+                if changed == 1 { "" } else { "s" },
+            );
+            return;
+        }
+
+        if sub.confirm {
+            let matches = Arc::new(ex::edit_matches(&edits, index));
+            self.vim_substitute = Some(SubstituteSession {
+                text,
+                accepted: vec![false; edits.len()],
+                edits,
+                matches,
+                current: 0,
+            });
+            self.vim_mode = VimMode::Confirm;
+            self.show_substitute_prompt();
+            return;
+        }
+
+        self.apply_substitution(&text, &edits);
+    }
+
+    fn show_substitute_prompt(&mut self) {
+        let Some(session) = &self.vim_substitute else { return };
+        let m = session.matches[session.current];
+        self.status_message = format!("replace with {} (y/n/a/q/l)?", session.edits[session.current].1);
+        self.vim_move_to_with_block(m.line, m.col);
+    }
+
+    fn finish_substitute(&mut self) {
+        let Some(session) = self.vim_substitute.take() else { return };
+        self.vim_mode = VimMode::Normal;
+        self.status_message.clear();
+        if self.content.text() != session.text {
+            return;
+        }
+        let edits: Vec<_> = session
+            .edits
+            .into_iter()
+            .zip(session.accepted)
+            .filter_map(|(edit, accepted)| accepted.then_some(edit))
+            .collect();
+        if edits.is_empty() {
+            self.vim_apply_block_cursor();
+        } else {
+            self.apply_substitution(&session.text, &edits);
+        }
+    }
+
+    fn apply_substitution(&mut self, text: &str, edits: &[(std::ops::Range<usize>, String)]) {
+        let index = LineIndex::new(text);
+        let changed = ex::changed_lines(edits, &index);
+        let replaced = ex::apply_edits(text, edits.iter());
+        let delta: isize = edits[..edits.len() - 1]
+            .iter()
+            .map(|(range, replacement)| replacement.len() as isize - range.len() as isize)
+            .sum();
+        let last_start = edits[edits.len() - 1].0.start.saturating_add_signed(delta);
+        let last_line = replaced[..last_start].matches('\n').count();
+
+        self.commit_undo_step();
+        self.replace_content(&replaced);
+        self.finish_change();
+        self.vim_move_to_with_block(last_line, 0);
+        self.status_message = format!(
+            "{} substitution{} on {} line{}",
+            edits.len(),
+            if edits.len() == 1 { "" } else { "s" },
+            changed,
+            if changed == 1 { "" } else { "s" },
+        );
+    }
+
+    fn ex_global(&mut self, lines: ex::LineRange, global: Global) {
+        let pattern = if global.pattern.is_empty() { self.vim_search_pattern.clone() } else { global.pattern.clone() };
+        if pattern.is_empty() {
+            self.status_message = String::from("E35: No previous regular expression");
+            return;
+        }
+        let regex = match build_search_regex(&pattern, true, true, false) {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.status_message = format!("E383: Invalid search string: {}", err.to_string().lines().last().unwrap_or(""));
+                return;
+            }
+        };
+        self.vim_search_pattern = pattern.clone();
+        self.vim_search_tick = None;
+        let command = match ex::parse_line_command(&global.command) {
+            Ok(command) => command,
+            Err(err) => {
+                self.status_message = err;
+                return;
+            }
+        };
+        let substitute = match &command {
+            LineCommand::Substitute(cmd) => match self.parse_substitute_command(cmd).map(|sub| sub.and_then(|sub| self.prepare_substitute(sub))) {
+                Some(Ok(prepared)) => Some(prepared),
+                Some(Err(err)) => {
+                    self.status_message = err;
+                    return;
+                }
+                None => {
+                    self.status_message = format!("E492: Not an editor command: {}", cmd);
+                    return;
+                }
+            },
+            _ => None,
+        };
+
+        let text = self.content.text();
+        let mut buf: Vec<String> = text.split('\n').map(String::from).collect();
+        let mut marks: Vec<bool> = buf
+            .iter()
+            .enumerate()
+            .map(|(i, line)| i >= lines.0 && i <= lines.1 && regex.is_match(line) != global.invert)
+            .collect();
+        if !marks.contains(&true) {
+            self.status_message = if global.invert {
+                format!("Pattern found in every line: {}", pattern)
+            } else {
+                format!("Pattern not found: {}", pattern)
+            };
+            return;
+        }
+
+        self.commit_undo_step();
+        self.touch_lines(0..usize::MAX);
+        self.undo_batch = Some((self.changedtick, false));
+        let original_lines = buf.len();
+        let mut current = self.content.cursor().position.line;
+        let mut substitutions = 0;
+        let mut substituted_lines = 0;
+        let mut printed = Vec::new();
+        while let Some(i) = marks.iter().position(|&marked| marked) {
+            marks[i] = false;
+            match &command {
+                LineCommand::Delete => {
+                    buf.remove(i);
+                    marks.remove(i);
+                    if buf.is_empty() {
+                        buf.push(String::new());
+                        marks.push(false);
+                    }
+                    current = i.min(buf.len() - 1);
+                }
+                LineCommand::Move(address) | LineCommand::Copy(address) => {
+                    let ctx = ex::LineContext { current: i, last: buf.len() - 1, visual: self.vim_visual_marks };
+                    let target = match ex::parse_line_address(address, &ctx) {
+                        Ok(target) => target,
+                        Err(err) => {
+                            self.status_message = err;
+                            break;
+                        }
+                    };
+                    if matches!(command, LineCommand::Copy(_)) {
+                        let dest = (target + 1) as usize;
+                        buf.insert(dest, buf[i].clone());
+                        marks.insert(dest, false);
+                        current = dest;
+                    } else if target < i as isize - 1 || target > i as isize {
+                        let line = buf.remove(i);
+                        marks.remove(i);
+                        let dest = if target > i as isize { target as usize } else { (target + 1) as usize };
+                        buf.insert(dest, line);
+                        marks.insert(dest, false);
+                        current = dest;
+                    }
+                }
+                LineCommand::Substitute(_) => {
+                    let Some((regex, template, sub)) = &substitute else { break };
+                    let index = LineIndex::new(&buf[i]);
+                    let edits = ex::plan_substitution(regex, &buf[i], &index, (0, 0), sub.global, template);
+                    if edits.is_empty() {
+                        continue;
+                    }
+                    substitutions += edits.len();
+                    substituted_lines += 1;
+                    let replaced = ex::apply_edits(&buf[i], edits.iter());
+                    let parts: Vec<String> = replaced.split('\n').map(String::from).collect();
+                    let added = parts.len();
+                    buf.splice(i..=i, parts);
+                    marks.splice(i..=i, std::iter::repeat_n(false, added));
+                    current = i + added - 1;
+                }
+                LineCommand::Print => {
+                    printed.push(format!("{} {}", i + 1, buf[i].trim()));
+                    current = i;
+                }
+                LineCommand::Normal(keys) => {
+                    let before = buf.len();
+                    self.vim_move_to_with_block(i, 0);
+                    self.vim_normal_keys(keys);
+                    buf = self.content.text().split('\n').map(String::from).collect();
+                    let tail = (before - i - 1).min(buf.len().saturating_sub(i + 1));
+                    let mut realigned = marks[..i].to_vec();
+                    realigned.resize(buf.len() - tail, false);
+                    realigned.extend_from_slice(&marks[before - tail..]);
+                    marks = realigned;
+                    current = self.content.cursor().position.line;
+                }
+            }
+        }
+
+        let deferred = matches!(self.undo_batch.take(), Some((_, true)));
+        let replaced = buf.join("\n");
+        if replaced != self.content.text() {
+            self.replace_content(&replaced);
+        }
+        if replaced != text {
+            self.finish_change();
+        } else if deferred {
+            self.commit_undo_step();
+        }
+        self.vim_move_to_with_block(current.min(buf.len() - 1), 0);
+        match command {
+            LineCommand::Delete => {
+                let removed = original_lines - buf.len().max(1);
+                self.status_message = format!("{} fewer line{}", removed, if removed == 1 { "" } else { "s" });
+            }
+            LineCommand::Substitute(_) if substitutions > 0 => {
+                self.status_message = format!(
+                    "{} substitution{} on {} line{}",
+                    substitutions,
+                    if substitutions == 1 { "" } else { "s" },
+                    substituted_lines,
+                    if substituted_lines == 1 { "" } else { "s" },
+                );
+            }
+            LineCommand::Print => self.status_message = printed.join(" | "),
+            _ => {}
+        }
+    }
+
+    fn vim_normal_keys(&mut self, keys: &str) {
+        self.vim_mode = VimMode::Normal;
+        for c in keys.chars() {
+            let message = if self.vim_mode == VimMode::Insert {
+                let edit = if c == '\n' { text_editor::Edit::Enter } else { text_editor::Edit::Insert(c) };
+                Message::Edit(text_editor::Action::Edit(edit))
+            } else if self.vim_pending.is_some() || self.vim_operator.is_some() {
+                Message::VimKey(c)
+            } else {
+                match c {
+                    'i' => Message::VimEnterInsert,
+                    'a' => Message::VimEnterInsertAppend,
+                    'o' => Message::VimEnterInsertNewlineBelow,
+                    'I' => Message::VimEnterInsertLineStart,
+                    'A' => Message::VimEnterInsertLineEnd,
+                    'O' => Message::VimEnterInsertNewlineAbove,
+                    _ => Message::VimKey(c),
+                }
+            };
+            let _ = self.handle_message(message);
+        }
+        if self.vim_mode == VimMode::Insert {
+            let _ = self.handle_message(Message::VimEnterNormal);
+        }
+        self.vim_mode = VimMode::Normal;
+    }
+
+    fn vim_incsearch_jump(&mut self) {
+        let (line, col) = self.vim_search_origin;
+        self.vim_search_current = None;
+        match self.vim_match_from((line, col), self.vim_search_forward) {
+            Some((i, _)) => {
+                let m = self.vim_search_matches[i];
+                self.vim_search_current = Some(i);
+                self.vim_move_to_with_block(m.line, m.col);
+            }
+            None => self.vim_move_to_with_block(line, col),
+        }
+    }
+
+    fn vim_search_jump(&mut self, forward: bool, count: usize) {
+        if self.vim_search_pattern.is_empty() {
+            self.status_message = String::from("E35: No previous regular expression");
+            return;
+        }
+        self.ensure_vim_search_matches();
+        self.vim_hlsearch = true;
+        let cursor = self.content.cursor();
+        let mut from = (cursor.position.line, cursor.position.column);
+        let mut wrapped = false;
+        let mut target = None;
+        for _ in 0..count.max(1) {
+            let Some((i, wrap)) = self.vim_match_from(from, forward) else { break };
+            let m = self.vim_search_matches[i];
+            from = (m.line, m.col);
+            wrapped |= wrap;
+            target = Some(m);
+        }
+        let Some(m) = target else {
+            self.status_message = format!("E486: Pattern not found: {}", self.vim_search_pattern);
+            return;
+        };
+        self.status_message = match (wrapped, forward) {
+            (false, _) => String::new(),
+            (true, true) => String::from("search hit BOTTOM, continuing at TOP"),
+            (true, false) => String::from("search hit TOP, continuing at BOTTOM"),
+        };
+        self.vim_move_to_with_block(m.line, m.col);
+    }
+
+    fn remember(&mut self, kind: HistoryKind, entry: &str) {
+        self.history.push(kind, entry);
+        crate::persistence::save_history(&self.history);
+    }
+
+    fn cursor_anchor(&self, position: text_editor::Position) -> (usize, usize) {
+        let len = self.content.line(position.line).map_or(0, |l| l.text.len());
+        let lines_after = self.content.line_count().saturating_sub(position.line + 1);
+        (lines_after, len.saturating_sub(position.column))
+    }
+
+    fn resolve_anchor(&self, (lines_after, bytes_after): (usize, usize)) -> text_editor::Position {
+        let line = self.content.line_count().saturating_sub(lines_after + 1);
+        let len = self.content.line(line).map_or(0, |l| l.text.len());
+        text_editor::Position { line, column: len.saturating_sub(bytes_after) }
+    }
+
+    fn applies_per_cursor(&self, message: &Message) -> bool {
+        match message {
+            Message::Edit(action) => matches!(
+                action,
+                text_editor::Action::Edit(_) | text_editor::Action::Move(_) | text_editor::Action::Select(_)
+            ),
+            Message::Paste | Message::Delete => true,
+            Message::VimKey(c) => {
+                self.vim_mode == VimMode::Normal
+                    && (self.vim_pending.is_some()
+                        || self.vim_operator.is_some()
+                        || !matches!(c, 'u' | '\x12' | '\x04' | '\x15'))
+            }
+            Message::VimEnterInsertAppend
+            | Message::VimEnterInsertLineStart
+            | Message::VimEnterInsertLineEnd
+            | Message::VimEnterInsertNewlineBelow
+            | Message::VimEnterInsertNewlineAbove => true,
+            _ => false,
+        }
+    }
+
+    // Runs the message once per cursor, bottom-up, so earlier edits never shift
+    // cursors that are still waiting. Cursors already handled are re-anchored
+    // by their distance from the end of the document, which edits above them
+    // leave untouched.
+    fn for_each_cursor(&mut self, message: Message) -> Task<Message> {
+        let primary = self.content.cursor();
+        let mut cursors = std::mem::take(&mut self.extra_cursors);
+        cursors.push(primary);
+        cursors.sort_by_key(|c| (c.position.line, c.position.column));
+        cursors.dedup_by_key(|c| (c.position.line, c.position.column));
+        let vim_state = (self.vim_pending.clone(), self.vim_count.clone(), self.vim_operator);
+        self.undo_batch = Some((self.changedtick, false));
+        let mut done: Vec<(text_editor::Cursor, bool)> = Vec::new();
+        let mut tasks = Vec::new();
+        for cursor in cursors.into_iter().rev() {
+            let anchors: Vec<_> = done
+                .iter()
+                .map(|(c, is_primary)| {
+                    (self.cursor_anchor(c.position), c.selection.map(|s| self.cursor_anchor(s)), *is_primary)
+                })
+                .collect();
+            (self.vim_pending, self.vim_count, self.vim_operator) = vim_state.clone();
+            self.content.move_to(cursor);
+            self.vim_col = cursor.position.column;
+            tasks.push(self.handle_message(message.clone()));
+            done = anchors
+                .into_iter()
+                .map(|(position, selection, is_primary)| {
+                    let cursor = text_editor::Cursor {
+                        position: self.resolve_anchor(position),
+                        selection: selection.map(|s| self.resolve_anchor(s)),
+                    };
+                    (cursor, is_primary)
+                })
+                .collect();
+            done.push((self.content.cursor(), cursor.position == primary.position));
+        }
+        let primary = done
+            .iter()
+            .find(|(_, is_primary)| *is_primary)
+            .map_or(primary, |(c, _)| *c);
+        for (cursor, _) in done {
+            if cursor.position != primary.position
+                && !self.extra_cursors.iter().any(|c| c.position == cursor.position)
+            {
+                self.extra_cursors.push(cursor);
+            }
+        }
+        self.content.move_to(primary);
+        self.vim_col = primary.position.column;
+        if let Some((_, true)) = self.undo_batch.take() {
+            self.commit_undo_step();
+        }
+        Task::batch(tasks)
+    }
+
+    fn add_next_occurrence(&mut self) {
+        let cursor = self.content.cursor();
+        let Some(selected) = self.content.selection().filter(|s| !s.is_empty()) else {
+            self.content.perform(text_editor::Action::SelectWord);
+            return;
+        };
+        let snapshot = self.text_snapshot();
+        let text = snapshot.text();
+        let index = snapshot.index();
+        let offset = |p: text_editor::Position| index.line_start(p.line).saturating_add(p.column);
+        let taken: Vec<usize> = self
+            .extra_cursors
+            .iter()
+            .chain(std::iter::once(&cursor))
+            .filter_map(|c| c.selection.map(|s| offset(s).min(offset(c.position))))
+            .collect();
+        let after = cursor.selection.map_or(offset(cursor.position), |s| offset(s).max(offset(cursor.position)));
+        let starts: Vec<usize> = text
+            .match_indices(selected.as_str())
+            .map(|(i, _)| i)
+            .filter(|i| !taken.contains(i))
+            .collect();
+        let Some(&start) = starts.iter().find(|&&i| i >= after).or(starts.first()) else {
+            self.status_message = String::from("No more occurrences");
+            return;
+        };
+        let position = |offset: usize| {
+            let (line, column) = index.position(offset);
+            text_editor::Position { line, column }
+        };
+        self.extra_cursors.push(cursor);
+        self.content.move_to(text_editor::Cursor {
+            position: position(start + selected.len()),
+            selection: Some(position(start)),
+        });
+    }
+
     fn apply_snapshot(&mut self, snap: &undo_tree::Snapshot) {
-        self.content = text_editor::Content::with_text(&snap.text);
+        self.replace_content(&snap.text);
+        self.changedtick += 1;
+        self.last_snapshot_tick = self.changedtick;
         self.vim_move_to_with_block(snap.cursor_line, snap.cursor_col);
     }
 
+    fn formatter(&self) -> Option<(String, std::path::PathBuf)> {
+        let extension = self
+            .current_file
+            .as_ref()
+            .and_then(|p| p.extension())
+            .map(|e| e.to_string_lossy().to_lowercase());
+        let language = self.language.map(|l| l.name().to_lowercase());
+        let key = [extension, language].into_iter().flatten().find(|key| self.formatters.contains_key(key))?;
+        let file = self.current_file.clone().unwrap_or_else(|| format!("untitled.{}", key).into());
+        Some((self.formatters[&key].clone(), file))
+    }
+
+    fn selected_lines(&self) -> Option<std::ops::Range<usize>> {
+        let cursor = self.content.cursor();
+        let selection = cursor.selection?;
+        let key = |p: text_editor::Position| (p.line, p.column);
+        let (start, end) = if key(selection) < key(cursor.position) {
+            (selection, cursor.position)
+        } else {
+            (cursor.position, selection)
+        };
+        let last = if end.column == 0 && end.line > start.line { end.line - 1 } else { end.line };
+        Some(start.line..last + 1)
+    }
+
+    fn vim_format_lines(&mut self, from: usize, to: usize) -> Task<Message> {
+        let last = self.content.line_count().saturating_sub(1);
+        let (start, end) = (from.min(to).min(last), from.max(to).min(last));
+        self.vim_operator = None;
+        self.vim_count = String::new();
+        self.vim_move_to_with_block(start, 0);
+        Task::batch([self.format_lines(Some(start..end + 1)), operation::focus(EDITOR_ID)])
+    }
+
+    fn format_lines(&mut self, lines: Option<std::ops::Range<usize>>) -> Task<Message> {
+        let Some((command, file)) = self.formatter() else {
+            self.format_builtin(lines);
+            return Task::none();
+        };
+        self.cancel_format();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.format_cancel = Some(cancel.clone());
+        self.format_generation += 1;
+        let generation = self.format_generation;
+        let program = command.split_whitespace().next().unwrap_or_default();
+        self.status_message = format!("Formatting with {}; press F5 to cancel", program);
+        Task::perform(
+            format::format_in_background(command, self.content.text(), file, cancel),
+            move |outcome| Message::DocumentFormatted(generation, lines.clone(), outcome),
+        )
+    }
+
+    fn cancel_format(&mut self) -> bool {
+        let Some(cancel) = self.format_cancel.take() else { return false };
+        cancel.store(true, Ordering::Relaxed);
+        self.format_generation += 1;
+        true
+    }
+
+    fn format_builtin(&mut self, lines: Option<std::ops::Range<usize>>) -> bool {
+        let original = self.content.text();
+        let file = self.current_file.as_deref();
+        let Some(mut formatted) = format_document(&original, &self.indent.unit(), self.language, file) else {
+            self.status_message = String::from("No formatter configured for this file type; left unchanged");
+            return false;
+        };
+        if original.ends_with('\n') {
+            formatted.push('\n');
+        }
+        let changed = self.apply_formatted(&original, &formatted, lines);
+        self.status_message = formatted_status(changed);
+        true
+    }
+
+    fn apply_formatted(&mut self, original: &str, formatted: &str, lines: Option<std::ops::Range<usize>>) -> usize {
+        let mut hunks = format::line_hunks(original, formatted);
+        if let Some(lines) = &lines {
+            hunks = format::restrict_hunks(hunks, lines);
+        }
+        if hunks.is_empty() {
+            return 0;
+        }
+        let old_lines: Vec<&str> = original.split('\n').collect();
+        let new_lines = &formatted.split('\n').collect::<Vec<_>>();
+        let position = self.content.cursor().position;
+        let at = |line: usize, column: usize| text_editor::Position { line, column };
+        self.commit_undo_step();
+        for hunk in hunks.iter().rev() {
+            let replaced = &new_lines[hunk.new.clone()];
+            let last = old_lines.len() - 1;
+            let (start, end, replacement) = if hunk.old.end <= last {
+                (at(hunk.old.start, 0), at(hunk.old.end, 0), replaced.iter().map(|l| format!("{}\n", l)).collect())
+            } else if hunk.old.start > 0 {
+                let before = hunk.old.start - 1;
+                let replacement = replaced.iter().map(|l| format!("\n{}", l)).collect();
+                (at(before, old_lines[before].len()), at(last, old_lines[last].len()), replacement)
+            } else {
+                (at(0, 0), at(last, old_lines[last].len()), replaced.join("\n"))
+            };
+            self.content.move_to(text_editor::Cursor { position: end, selection: Some(start) });
+            if !replacement.is_empty() {
+                self.content.perform(text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(replacement))));
+            } else if start != end {
+                self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
+            }
+        }
+        if let (Some(first), Some(last)) = (hunks.first(), hunks.last()) {
+            self.touch_lines(first.new.start.saturating_sub(1)..last.new.end + 1);
+        }
+        let (line, shift) = format::map_line(&hunks, original, formatted, position.line);
+        let column = self
+            .content
+            .line(line)
+            .map_or(0, |l| position.column.saturating_add_signed(shift).min(l.text.len()));
+        if self.vim_enabled && self.vim_mode == VimMode::Normal {
+            self.vim_move_to_with_block(line, column);
+        } else {
+            self.content.move_to(text_editor::Cursor { position: at(line, column), selection: None });
+        }
+        self.finish_change();
+        hunks.iter().map(|h| h.new.len().max(h.old.len())).sum()
+    }
+
     fn save_settings(&self) {
         crate::persistence::save_settings(&crate::persistence::Settings {
             vim_enabled: self.vim_enabled,
             line_numbers: self.line_numbers.clone(),
             word_wrap: self.word_wrap,
             scale: self.scale,
+            undo_granularity: self.undo_granularity,
+            undo_pause_ms: self.undo_pause_ms,
+            undo_insert_session: self.undo_insert_session,
+            find_case_sensitive: self.case_sensitive,
+            find_regex: self.use_regex,
+            find_whole_word: self.whole_word,
+            find_in_selection: self.find_in_selection,
+            find_show_results: self.show_find_results,
+            rainbow_brackets: self.rainbow_brackets,
+            highlight_current_line: self.highlight_current_line,
+            show_rulers: self.show_rulers,
+            rulers: self.rulers.clone(),
+            indent_guides: self.indent_guides,
+            show_whitespace: self.show_whitespace,
+            highlight_word: self.highlight_word,
+            theme: self.theme_name.clone(),
+            follow_system_theme: self.follow_system_theme,
+            formatters: self.formatters.clone(),
+            shiftwidth: self.indent_defaults.shiftwidth,
+            expandtab: self.indent_defaults.expandtab,
+            softtabstop: self.indent_defaults.softtabstop,
         });
     }
 
+    fn select_undo_node(&mut self, id: usize) {
+        self.selected_undo_node = Some(id);
+        let current = self.content.text();
+        self.undo_preview_text = self.undo_tree.nodes.get(id)
+            .map(|n| Self::preview_text(&n.snapshot, &current))
+            .unwrap_or_default();
+        self.undo_label_input = self.undo_tree.nodes.get(id)
+            .and_then(|n| n.label.clone())
+            .unwrap_or_default();
+    }
+
+    fn reveal_undo_node(&mut self, id: usize) {
+        let mut node = id;
+        while let Some(parent) = self.undo_tree.nodes[node].parent {
+            self.undo_collapsed.remove(&parent);
+            node = parent;
+        }
+    }
+
+    fn refresh_undo_search(&mut self) {
+        self.undo_search_hits = self.undo_tree.search(&self.undo_search_query);
+    }
+
+    fn scroll_to_selected_undo_node(&self) -> Task<Message> {
+        let id = self.selected_undo_node.unwrap_or(self.undo_tree.current);
+        let positions = node_positions(&self.undo_tree.nodes, &self.undo_collapsed, self.undo_zoom);
+        let Some(&(_, _, y)) = positions.iter().find(|&&(n, _, _)| n == id) else {
+            return Task::none();
+        };
+        if let Some((offset, visible, _)) = self.undo_viewport {
+            if y >= offset && y <= offset + visible - ROW_HEIGHT * self.undo_zoom {
+                return Task::none();
+            }
+            return operation::scroll_to(
+                UNDO_TREE_SCROLL_ID,
+                AbsoluteOffset { x: None, y: Some((y - visible / 2.0).max(0.0)) },
+            );
+        }
+        operation::scroll_to(UNDO_TREE_SCROLL_ID, AbsoluteOffset { x: None, y: Some((y - 100.0).max(0.0)) })
+    }
+
     fn preview_text(snapshot: &crate::undo_tree::Snapshot, current: &str) -> String {
         let snap_lines: Vec<&str> = snapshot.text.lines().collect();
         let cur_lines: Vec<&str> = current.lines().collect();
@@ -488,6 +1416,45 @@ impl App {
 
 impl App {
     pub fn update(&mut self, message: Message) -> Task<Message> {
+        let tick = self.changedtick;
+        let line_count = self.content.line_count();
+        let touched = self.cursor_lines();
+        let cursor_line = self.content.cursor().position.line;
+        let preview_synced = matches!(message, Message::Edit(_));
+        let task = if !self.extra_cursors.is_empty() && self.applies_per_cursor(&message) {
+            self.for_each_cursor(message)
+        } else {
+            let task = self.handle_message(message);
+            if self.changedtick != tick {
+                self.extra_cursors.clear();
+            }
+            task
+        };
+        let edit = (self.changedtick != tick).then(|| self.row_edit(tick, line_count, touched));
+        if edit.is_some() && self.cancel_format() {
+            self.status_message = String::from("Buffer changed while formatting; result discarded");
+        }
+        self.refresh_text_lines(edit);
+        let search = if edit.is_some() && self.show_panel && self.find_tick != Some(self.changedtick) {
+            self.find_all_matches()
+        } else {
+            Task::none()
+        };
+        let vim_search = self.refresh_vim_search();
+        self.refresh_brackets(edit);
+        self.track_word_under_cursor();
+        self.refresh_preview();
+        let preview = if !preview_synced && self.content.cursor().position.line != cursor_line {
+            self.sync_preview(None)
+        } else {
+            Task::none()
+        };
+        #[cfg(feature = "tree-sitter")]
+        self.refresh_syntax_tree(edit);
+        Task::batch([task, search, vim_search, preview])
+    }
+
+    fn handle_message(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::Edit(action) => {
                 if self.ctrl_held {
@@ -495,38 +1462,93 @@ impl App {
                         return Task::none();
                     }
                 }
-                if self.vim_enabled && self.vim_mode == VimMode::Normal {
+                if self.vim_enabled && matches!(self.vim_mode, VimMode::Normal | VimMode::Confirm) {
                     if let text_editor::Action::Edit(_) = &action {
                         return Task::none();
                     }
                 }
-                let is_edit = action.is_edit();
+                match &action {
+                    text_editor::Action::Click(_) if self.alt_held => {
+                        let cursor = self.content.cursor();
+                        self.extra_cursors.push(cursor);
+                    }
+                    text_editor::Action::Click(_) | text_editor::Action::SelectAll => {
+                        self.extra_cursors.clear();
+                    }
+                    _ => {}
+                }
+                let edit_kind = match &action {
+                    text_editor::Action::Edit(edit) => Some(EditKind::of(edit, self.removed_char(edit))),
+                    _ => None,
+                };
+                let insert_session = self.vim_enabled
+                    && self.vim_mode == VimMode::Insert
+                    && self.undo_insert_session;
+                match edit_kind {
+                    Some(kind) if !insert_session => {
+                        let pause = Duration::from_millis(self.undo_pause_ms);
+                        if self.undo_coalescer.breaks_before(kind, Instant::now(), self.undo_granularity, pause) {
+                            self.commit_undo_step();
+                        }
+                    }
+                    None if !matches!(action, text_editor::Action::Scroll { .. }) => {
+                        self.undo_coalescer.interrupt();
+                    }
+                    _ => {}
+                }
+                let before = self.find_scope.is_some().then(|| {
+                    let cursor = self.content.cursor();
+                    let line_len = self.content.line(cursor.position.line).map_or(0, |l| l.text.len());
+                    (cursor, line_len, self.content.line_count())
+                });
+                if let text_editor::Action::Edit(text_editor::Edit::Backspace) = action {
+                    for _ in 1..self.soft_backspace() {
+                        self.content.perform(text_editor::Action::Edit(text_editor::Edit::Backspace));
+                    }
+                }
+                let scrolled = match &action {
+                    text_editor::Action::Scroll { lines } => Some(*lines),
+                    _ => None,
+                };
+                let edit = match &action {
+                    text_editor::Action::Edit(edit) => Some(edit.clone()),
+                    _ => None,
+                };
                 self.content.perform(action);
-                if is_edit {
-                    self.is_modified = true;
-                    self.changedtick += 1;
-                    if self.show_panel {
-                        self.find_all_matches();
+                if let Some(kind) = edit_kind {
+                    if let (Some(edit), Some((cursor, line_len, line_count))) = (edit, before)
+                        && let Some(span) = self.edit_span(&edit, cursor, line_len, line_count)
+                    {
+                        self.shift_find_scope(span);
+                    }
+                    self.mark_changed();
+                    if !insert_session && Coalescer::breaks_after(kind, self.undo_granularity) {
+                        self.commit_undo_step();
                     }
                 }
+                let preview = self.sync_preview(scrolled);
                 if self.vim_enabled && self.vim_mode == VimMode::Normal {
                     self.vim_apply_block_cursor();
-                    return operation::focus(EDITOR_ID);
+                    return Task::batch([preview, operation::focus(EDITOR_ID)]);
                 }
-                Task::none()
+                preview
             }
             Message::New => {
                 if self.is_modified {
                     self.pending_action = Some(PendingAction::New);
                     return Task::none();
                 }
-                self.content = text_editor::Content::new();
+                self.replace_content("");
                 self.current_file = None;
+                self.language = None;
+                self.indent = self.indent_defaults;
                 self.is_modified = false;
                 self.show_panel = false;
-                self.find_matches.clear();
-                self.current_match = None;
+                self.clear_find();
                 self.undo_tree.reset(undo_tree::Snapshot { text: String::new(), cursor_line: 0, cursor_col: 0 });
+                self.changedtick += 1;
+                self.last_snapshot_tick = self.changedtick;
+                self.undo_coalescer.reset();
                 Task::none()
             }
             Message::Open => {
@@ -549,36 +1571,44 @@ impl App {
             )
             }
             Message::FileOpened(Some((path, text))) => {
-                self.content = text_editor::Content::with_text(&text);
+                self.replace_content(&text);
                 self.is_modified = false;
                 if let Some(tree) = crate::persistence::load_undo_tree(&path) {
                     self.undo_tree = tree;
                 } else {
                     self.undo_tree.reset(undo_tree::Snapshot { text: text.clone(), cursor_line: 0, cursor_col: 0 });
                 }
+                self.language = Language::detect(Some(&path), &text);
                 self.current_file = Some(path);
+                self.detect_indent();
+                self.changedtick += 1;
+                self.last_snapshot_tick = self.changedtick;
+                self.undo_coalescer.reset();
+                self.undo_collapsed.clear();
+                self.refresh_undo_search();
                 Task::none()
             }
             Message::FileOpened(None) => Task::none(),
             Message::Save => {
-                if self.changedtick != self.last_snapshot_tick {
-                    self.push_snapshot();
-                }
+                self.commit_undo_step();
                 if let Some(path) = self.current_file.clone() {
                     let text = self.content.text();
+                    let (tick, node) = (self.changedtick, self.undo_tree.current);
                     Task::perform(
                         async move {
                             std::fs::write(&path, &text).ok()?;
                             Some(path)
                         },
-                        Message::FileSaved,
+                        move |path| Message::FileSaved(tick, node, path),
                     )
                 } else {
                     self.update(Message::SaveAs)
                 }
             }
             Message::SaveAs => {
+                self.commit_undo_step();
                 let text = self.content.text();
+                let (tick, node) = (self.changedtick, self.undo_tree.current);
                 Task::perform(
                     async move {
                         let handle = rfd::AsyncFileDialog::new()
@@ -590,23 +1620,28 @@ impl App {
                         std::fs::write(&path, &text).ok()?;
                         Some(path)
                     },
-                    Message::FileSaved,
+                    move |path| Message::FileSaved(tick, node, path),
                 )
             }
-            Message::FileSaved(Some(path)) => {
+            Message::FileSaved(tick, node, Some(path)) => {
+                self.language = Language::detect(Some(&path), &self.content.text());
                 self.current_file = Some(path);
-                self.is_modified = false;
+                self.is_modified = self.changedtick != tick;
+                self.undo_tree.mark_saved(node);
+                if let Some(path) = &self.current_file {
+                    crate::persistence::save_undo_tree(path, &self.undo_tree);
+                }
                 if let Some(action) = self.pending_action.take() {
                     match action {
                         PendingAction::New => self.update(Message::New),
                         PendingAction::Open => self.update(Message::Open),
-                        PendingAction::Exit => iced::exit(),
+                        PendingAction::Exit => self.update(Message::Exit),
                     }
                 } else {
                     Task::none()
                 }
             }
-            Message::FileSaved(None) => Task::none(),
+            Message::FileSaved(_, _, None) => Task::none(),
             Message::Exit => {
                 if self.is_modified {
                     self.pending_action = Some(PendingAction::Exit);
@@ -615,6 +1650,7 @@ impl App {
                 iced::exit()
             }
             Message::Undo => {
+                self.commit_undo_step();
                 if let Some(snap) = self.undo_tree.undo() {
                     self.apply_snapshot(&snap);
                     self.is_modified = true;
@@ -622,13 +1658,37 @@ impl App {
                 Task::none()
             }
             Message::Redo => {
+                self.commit_undo_step();
                 if let Some(snap) = self.undo_tree.redo() {
                     self.apply_snapshot(&snap);
                     self.is_modified = true;
                 }
                 Task::none()
             }
-            Message::Tick => Task::none(),
+            Message::Tick => self.refresh_word_highlight(),
+            Message::WordMatchesReady(generation, results) => {
+                if let Some(results) = results
+                    && generation == self.word_generation
+                {
+                    self.word_matches = results.matches;
+                }
+                Task::none()
+            }
+            Message::CycleUndoGranularity => {
+                self.commit_undo_step();
+                self.undo_granularity = match self.undo_granularity {
+                    UndoGranularity::Pause => UndoGranularity::Word,
+                    UndoGranularity::Word => UndoGranularity::Edit,
+                    UndoGranularity::Edit => UndoGranularity::Pause,
+                };
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleUndoInsertSession => {
+                self.undo_insert_session = !self.undo_insert_session;
+                self.save_settings();
+                Task::none()
+            }
             Message::ToggleUndoPanel => {
                 self.show_undo_panel = !self.show_undo_panel;
                 if !self.show_undo_panel {
@@ -636,6 +1696,15 @@ impl App {
                 }
                 Task::none()
             }
+            Message::TogglePreview => {
+                self.show_preview = !self.show_preview;
+                self.refresh_preview();
+                self.sync_preview(None)
+            }
+            Message::PreviewLinkClicked(url) => {
+                self.preview_link(&url);
+                Task::none()
+            }
             Message::UndoPanelFocusToggle => {
                 if self.show_undo_panel {
                     self.undo_panel_focused = !self.undo_panel_focused;
@@ -649,21 +1718,81 @@ impl App {
                 if !self.show_undo_panel || !self.undo_panel_focused {
                     return Task::none();
                 }
-                let n = self.undo_tree.nodes.len();
-                if n == 0 { return Task::none(); }
+                let positions = node_positions(&self.undo_tree.nodes, &self.undo_collapsed, self.undo_zoom);
+                if positions.is_empty() { return Task::none(); }
                 let current = self.selected_undo_node.unwrap_or(self.undo_tree.current);
-                let new_id = if delta > 0 {
-                    (current + 1).min(n - 1)
+                let row = positions.iter().position(|&(id, _, _)| id == current).unwrap_or(0);
+                let new_row = if delta > 0 {
+                    (row + delta as usize).min(positions.len() - 1)
                 } else {
-                    current.saturating_sub(1)
+                    row.saturating_sub(delta.unsigned_abs() as usize)
                 };
-                self.selected_undo_node = Some(new_id);
-                let current = self.content.text();
-                self.undo_preview_text = self.undo_tree.nodes.get(new_id)
-                    .map(|n| Self::preview_text(&n.snapshot, &current))
-                    .unwrap_or_default();
+                self.select_undo_node(positions[new_row].0);
+                self.scroll_to_selected_undo_node()
+            }
+            Message::UndoPanelMoveSibling(delta) => {
+                if !self.show_undo_panel || !self.undo_panel_focused {
+                    return Task::none();
+                }
+                let mut id = self.selected_undo_node.unwrap_or(self.undo_tree.current);
+                while let Some(parent) = self.undo_tree.nodes[id].parent {
+                    let siblings = &self.undo_tree.nodes[parent].children;
+                    if siblings.len() > 1 {
+                        let idx = siblings.iter().position(|&c| c == id).unwrap_or(0) as i32;
+                        let target = siblings[(idx + delta).rem_euclid(siblings.len() as i32) as usize];
+                        self.reveal_undo_node(target);
+                        self.select_undo_node(target);
+                        return self.scroll_to_selected_undo_node();
+                    }
+                    id = parent;
+                }
+                Task::none()
+            }
+            Message::UndoPanelToggleCollapse => {
+                let id = self.selected_undo_node.unwrap_or(self.undo_tree.current);
+                if !self.undo_collapsed.remove(&id) && !self.undo_tree.nodes[id].children.is_empty() {
+                    self.undo_collapsed.insert(id);
+                }
                 Task::none()
             }
+            Message::UndoPanelCollapseInactive => {
+                if !self.undo_collapsed.is_empty() {
+                    self.undo_collapsed.clear();
+                    return Task::none();
+                }
+                let path = self.undo_tree.active_path();
+                for &id in &path {
+                    for &child in &self.undo_tree.nodes[id].children {
+                        if !path.contains(&child) && !self.undo_tree.nodes[child].children.is_empty() {
+                            self.undo_collapsed.insert(child);
+                        }
+                    }
+                }
+                if let Some(selected) = self.selected_undo_node {
+                    self.reveal_undo_node(selected);
+                }
+                Task::none()
+            }
+            Message::UndoPanelZoom(delta) => {
+                self.undo_zoom = (self.undo_zoom + delta as f32 * 0.25).clamp(0.5, 2.0);
+                self.scroll_to_selected_undo_node()
+            }
+            Message::UndoPanelFocusFilter => operation::focus(UNDO_FILTER_INPUT_ID),
+            Message::UndoPanelScrolled(viewport) => {
+                self.undo_viewport = Some((
+                    viewport.absolute_offset().y,
+                    viewport.bounds().height,
+                    viewport.content_bounds().height,
+                ));
+                Task::none()
+            }
+            Message::UndoMinimapJump(fraction) => {
+                let (visible, content) = self.undo_viewport
+                    .map(|(_, visible, content)| (visible, content))
+                    .unwrap_or((0.0, 0.0));
+                let y = (fraction * content - visible / 2.0).max(0.0);
+                operation::scroll_to(UNDO_TREE_SCROLL_ID, AbsoluteOffset { x: None, y: Some(y) })
+            }
             Message::UndoPanelConfirm => {
                 if let Some(id) = self.selected_undo_node {
                     return self.update(Message::UndoTreeJump(id));
@@ -674,14 +1803,44 @@ impl App {
                 if self.selected_undo_node == Some(id) {
                     return self.update(Message::UndoTreeJump(id));
                 }
-                self.selected_undo_node = Some(id);
-                let current = self.content.text();
-                self.undo_preview_text = self.undo_tree.nodes.get(id)
-                    .map(|n| Self::preview_text(&n.snapshot, &current))
-                    .unwrap_or_default();
-                Task::none()
+                self.select_undo_node(id);
+                Task::none()
+            }
+            Message::UndoLabelChanged(label) => {
+                self.undo_label_input = label;
+                Task::none()
+            }
+            Message::UndoLabelSubmit => {
+                let id = self.selected_undo_node.unwrap_or(self.undo_tree.current);
+                self.undo_tree.set_label(id, &self.undo_label_input);
+                self.refresh_undo_search();
+                if let Some(path) = &self.current_file {
+                    crate::persistence::save_undo_tree(path, &self.undo_tree);
+                }
+                Task::none()
+            }
+            Message::UndoSearchChanged(query) => {
+                self.undo_search_query = query;
+                self.refresh_undo_search();
+                Task::none()
+            }
+            Message::UndoSearchStep(delta) => {
+                if self.undo_search_hits.is_empty() {
+                    return Task::none();
+                }
+                let from = self.selected_undo_node.unwrap_or(self.undo_tree.current);
+                let hits = &self.undo_search_hits;
+                let target = if delta > 0 {
+                    hits.iter().copied().find(|&id| id > from).unwrap_or(hits[0])
+                } else {
+                    hits.iter().copied().rev().find(|&id| id < from).unwrap_or(hits[hits.len() - 1])
+                };
+                self.reveal_undo_node(target);
+                self.select_undo_node(target);
+                self.scroll_to_selected_undo_node()
             }
             Message::UndoTreeJump(id) => {
+                self.commit_undo_step();
                 if let Some(snap) = self.undo_tree.jump_to(id) {
                     self.apply_snapshot(&snap);
                     self.is_modified = true;
@@ -694,8 +1853,9 @@ impl App {
                 if let Some(selected) = self.content.selection() {
                     let _ = arboard::Clipboard::new()
                         .and_then(|mut cb| cb.set_text(selected));
+                    self.commit_undo_step();
                     self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-                    self.is_modified = true;
+                    self.finish_change();
                 }
                 Task::none()
             }
@@ -709,18 +1869,19 @@ impl App {
             Message::Paste => {
                 if let Ok(mut cb) = arboard::Clipboard::new() {
                     if let Ok(content) = cb.get_text() {
+                        self.commit_undo_step();
                         self.content.perform(text_editor::Action::Edit(
                             text_editor::Edit::Paste(Arc::new(content)),
                         ));
-                        self.is_modified = true;
-                        self.push_snapshot();
+                        self.finish_change();
                     }
                 }
                 Task::none()
             }
             Message::Delete => {
+                self.commit_undo_step();
                 self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-                self.is_modified = true;
+                self.finish_change();
                 Task::none()
             }
             Message::SelectAll => {
@@ -728,34 +1889,161 @@ impl App {
                 Task::none()
             }
             Message::FormatDocument => {
-                let original = self.content.text();
-                let formatted = format_document(&original);
-                if formatted != original.trim_end_matches('\n') {
-                    self.content = text_editor::Content::with_text(&formatted);
-                    self.is_modified = true;
-                    self.push_snapshot();
+                if self.cancel_format() {
+                    self.status_message = String::from("Formatting cancelled");
+                    return Task::none();
+                }
+                let lines = self.selected_lines();
+                self.format_lines(lines)
+            }
+            Message::DocumentFormatted(generation, lines, outcome) => {
+                if generation != self.format_generation {
+                    return Task::none();
+                }
+                self.format_cancel = None;
+                match outcome {
+                    FormatOutcome::Formatted(formatted) => {
+                        let original = self.content.text();
+                        let changed = self.apply_formatted(&original, &formatted, lines);
+                        self.status_message = formatted_status(changed);
+                    }
+                    FormatOutcome::Failed(error) => self.status_message = error,
+                    FormatOutcome::Missing(program) => {
+                        let used = self.format_builtin(lines);
+                        let fallback = if used { "used the built-in indenter" } else { "left unchanged" };
+                        self.status_message = format!("{} not found; {}", program, fallback);
+                    }
+                }
+                Task::none()
+            }
+            Message::Indent => {
+                let cursor = self.content.cursor();
+                if cursor.selection.is_some_and(|s| s.line != cursor.position.line) {
+                    self.commit_undo_step();
+                    if self.shift_selection(1) {
+                        self.finish_change();
+                    }
+                    return Task::none();
+                }
+                let tab = self.tab_text();
+                let inserts: Vec<_> = tab
+                    .chars()
+                    .map(|c| self.handle_message(Message::Edit(text_editor::Action::Edit(text_editor::Edit::Insert(c)))))
+                    .collect();
+                Task::batch(inserts)
+            }
+            Message::Unindent => {
+                self.commit_undo_step();
+                if self.shift_selection(-1) {
+                    self.finish_change();
                 }
                 Task::none()
             }
+            Message::ToggleExpandTab => {
+                self.indent.expandtab = !self.indent.expandtab;
+                self.indent_defaults.expandtab = self.indent.expandtab;
+                self.save_settings();
+                Task::none()
+            }
+            Message::SetIndentWidth(width) => {
+                self.indent.set_width(width);
+                self.indent_defaults.set_width(width);
+                self.save_settings();
+                Task::none()
+            }
+            Message::DetectIndent => {
+                self.detect_indent();
+                self.status_message = format!("Indentation: {}", self.indent.label());
+                Task::none()
+            }
+            Message::StripTrailingWhitespace => {
+                let original = self.content.text();
+                let stripped = strip_trailing_whitespace(&original);
+                self.status_message = match self.apply_formatted(&original, &stripped, None) {
+                    0 => String::from("No trailing whitespace"),
+                    changed => format!("Stripped trailing whitespace from {}", plural_lines(changed)),
+                };
+                Task::none()
+            }
             Message::TogglePanel => {
                 self.show_panel = !self.show_panel;
                 if self.show_panel {
-                    self.find_all_matches();
-                } else {
-                    self.find_matches.clear();
-                    self.current_match = None;
+                    let cursor = self.content.cursor();
+                    self.find_scope = cursor.selection.map(|sel| {
+                        let a = (cursor.position.line, cursor.position.column);
+                        let b = (sel.line, sel.column);
+                        a.min(b)..a.max(b)
+                    });
+                    return self.find_all_matches();
                 }
+                self.clear_find();
                 Task::none()
             }
             Message::ClosePanel => {
                 self.show_panel = false;
-                self.find_matches.clear();
-                self.current_match = None;
+                self.clear_find();
                 Task::none()
             }
             Message::FindQueryChanged(query) => {
                 self.find_query = query;
-                self.find_all_matches();
+                self.find_all_matches()
+            }
+            Message::HistoryKey(delta) => {
+                if self.vim_enabled && self.vim_mode == VimMode::Command {
+                    return self.update(Message::HistoryStep(HistoryKind::Command, delta));
+                }
+                if self.vim_enabled && self.vim_mode == VimMode::Search {
+                    return self.update(Message::HistoryStep(HistoryKind::Search, delta));
+                }
+                if !self.show_panel {
+                    return Task::none();
+                }
+                operation::is_focused(FIND_INPUT_ID).then(move |find| {
+                    if find {
+                        return Task::done(Message::HistoryStep(HistoryKind::Find, delta));
+                    }
+                    operation::is_focused(REPLACE_INPUT_ID).then(move |replace| {
+                        if replace {
+                            Task::done(Message::HistoryStep(HistoryKind::Replace, delta))
+                        } else {
+                            Task::none()
+                        }
+                    })
+                })
+            }
+            Message::HistoryStep(kind, delta) => {
+                let current = match kind {
+                    HistoryKind::Find => &self.find_query,
+                    HistoryKind::Replace => &self.replace_text,
+                    HistoryKind::Command => &self.vim_command,
+                    HistoryKind::Search => &self.vim_search_query,
+                };
+                let Some(value) = self.history.step(kind, delta, &current.clone()) else { return Task::none() };
+                match kind {
+                    HistoryKind::Find => {
+                        self.find_query = value;
+                        Task::batch([self.find_all_matches(), operation::move_cursor_to_end(FIND_INPUT_ID)])
+                    }
+                    HistoryKind::Replace => {
+                        self.replace_text = value;
+                        operation::move_cursor_to_end(REPLACE_INPUT_ID)
+                    }
+                    HistoryKind::Command => {
+                        self.vim_command = value;
+                        operation::move_cursor_to_end(COMMAND_INPUT_ID)
+                    }
+                    HistoryKind::Search => {
+                        let search = self.update(Message::VimSearchChanged(value));
+                        Task::batch([search, operation::move_cursor_to_end(SEARCH_INPUT_ID)])
+                    }
+                }
+            }
+            Message::FindResultsReady(generation, results) => {
+                if let Some(results) = results
+                    && generation == self.find_generation
+                {
+                    self.apply_find_results(results);
+                }
                 Task::none()
             }
             Message::ReplaceTextChanged(text) => {
@@ -768,10 +2056,54 @@ impl App {
             }
             Message::ToggleCaseSensitive(val) => {
                 self.case_sensitive = val;
-                self.find_all_matches();
-                Task::none()
+                self.save_settings();
+                self.find_all_matches()
+            }
+            Message::ToggleRegex(val) => {
+                self.use_regex = val;
+                self.save_settings();
+                self.find_all_matches()
+            }
+            Message::ToggleWholeWord(val) => {
+                self.whole_word = val;
+                self.save_settings();
+                self.find_all_matches()
+            }
+            Message::ToggleInSelection(val) => {
+                self.find_in_selection = val;
+                self.save_settings();
+                self.find_all_matches()
+            }
+            Message::ToggleFindResults(val) => {
+                self.show_find_results = val;
+                self.find_results_focused &= val;
+                self.save_settings();
+                self.scroll_find_results()
+            }
+            Message::FindResultSelected(index) => {
+                self.find_results_focused = false;
+                self.navigate_to_match(index);
+                Task::batch([self.scroll_find_results(), operation::focus(EDITOR_ID)])
+            }
+            Message::FindResultsFocusToggle => {
+                if self.find_results_focused {
+                    self.find_results_focused = false;
+                    return operation::focus(FIND_INPUT_ID);
+                }
+                self.focus_find_results()
+            }
+            Message::FindResultsMove(delta) => {
+                if !self.find_results_focused {
+                    return Task::none();
+                }
+                self.move_result_selection(delta)
             }
+            Message::FindResultsConfirm => match self.selected_result_match().filter(|_| self.find_results_focused) {
+                Some(index) => self.update(Message::FindResultSelected(index)),
+                None => Task::none(),
+            },
             Message::FindNext => {
+                self.remember(HistoryKind::Find, &self.find_query.clone());
                 if self.find_matches.is_empty() {
                     return Task::none();
                 }
@@ -780,9 +2112,10 @@ impl App {
                     None => 0,
                 };
                 self.navigate_to_match(next);
-                Task::none()
+                self.scroll_find_results()
             }
             Message::FindPrevious => {
+                self.remember(HistoryKind::Find, &self.find_query.clone());
                 if self.find_matches.is_empty() {
                     return Task::none();
                 }
@@ -792,26 +2125,36 @@ impl App {
                     None => self.find_matches.len() - 1,
                 };
                 self.navigate_to_match(prev);
-                Task::none()
+                self.scroll_find_results()
             }
             Message::ReplaceOne => {
+                self.remember(HistoryKind::Find, &self.find_query.clone());
+                self.remember(HistoryKind::Replace, &self.replace_text.clone());
                 if let Some(idx) = self.current_match {
-                    if idx < self.find_matches.len() {
-                        let (line, col) = self.find_matches[idx];
-                        let end_col = col + self.find_query.len();
+                    if let Some(m) = self.find_matches.get(idx).copied() {
+                        let Ok(regex) = self.search_regex() else { return Task::none() };
+                        let text = self.content.text();
+                        let range = match_byte_range(&text, &m);
+                        let replacement = expand_replacement(&regex, &text, range.start, &self.replace_text, self.use_regex);
                         self.content.move_to(text_editor::Cursor {
-                            position: text_editor::Position { line, column: col },
+                            position: text_editor::Position { line: m.line, column: m.col },
                             selection: Some(text_editor::Position {
-                                line,
-                                column: end_col,
+                                line: m.end_line,
+                                column: m.end_col,
                             }),
                         });
+                        self.commit_undo_step();
                         self.content.perform(text_editor::Action::Edit(
-                            text_editor::Edit::Paste(Arc::new(self.replace_text.clone())),
+                            text_editor::Edit::Paste(Arc::new(replacement)),
                         ));
-                        self.is_modified = true;
-                        self.push_snapshot();
-                        self.find_all_matches();
+                        let after = self.content.cursor().position;
+                        self.shift_find_scope(EditSpan {
+                            start: (m.line, m.col),
+                            old_end: (m.end_line, m.end_col),
+                            new_end: (after.line, after.column),
+                        });
+                        self.finish_change();
+                        self.find_all_matches_now();
                         if !self.find_matches.is_empty() {
                             let next = idx.min(self.find_matches.len() - 1);
                             self.navigate_to_match(next);
@@ -823,28 +2166,24 @@ impl App {
                 Task::none()
             }
             Message::ReplaceAll => {
+                self.remember(HistoryKind::Find, &self.find_query.clone());
+                self.remember(HistoryKind::Replace, &self.replace_text.clone());
                 if self.find_matches.is_empty() || self.find_query.is_empty() {
                     return Task::none();
                 }
+                let Ok(regex) = self.search_regex() else { return Task::none() };
                 let original = self.content.text();
-                let replaced = if self.case_sensitive {
-                    original.replace(&self.find_query, &self.replace_text)
-                } else {
-                    let mut result = original.clone();
-                    let lower_query = self.find_query.to_lowercase();
-                    let mut search_start = 0;
-                    while let Some(pos) = result[search_start..].to_lowercase().find(&lower_query) {
-                        let abs_pos = search_start + pos;
-                        result.replace_range(abs_pos..abs_pos + self.find_query.len(), &self.replace_text);
-                        search_start = abs_pos + self.replace_text.len();
-                    }
-                    result
-                };
+                let index = LineIndex::new(&original);
+                let scope = self.active_find_scope(&index);
+                let replaced = replace_all_matches(&regex, &original, &self.replace_text, self.use_regex, scope);
                 if replaced != original {
-                    self.content = text_editor::Content::with_text(&replaced);
-                    self.is_modified = true;
-                    self.find_all_matches();
-                    self.push_snapshot();
+                    if let Some(scope) = &mut self.find_scope {
+                        let end = (index.offset(scope.end) + replaced.len()).saturating_sub(original.len());
+                        scope.end = LineIndex::new(&replaced).position(end.min(replaced.len()));
+                    }
+                    self.commit_undo_step();
+                    self.replace_content(&replaced);
+                    self.finish_change();
                 }
                 Task::none()
             }
@@ -884,6 +2223,69 @@ impl App {
                 self.ctrl_held = false;
                 Task::none()
             }
+            Message::ModifiersChanged(modifiers) => {
+                if !modifiers.control() {
+                    self.ctrl_held = false;
+                }
+                self.alt_held = modifiers.alt();
+                Task::none()
+            }
+            Message::AddNextOccurrence => {
+                self.add_next_occurrence();
+                operation::focus(EDITOR_ID)
+            }
+            Message::CursorsFromMatches => {
+                let vim_normal = self.vim_enabled && self.vim_mode == VimMode::Normal;
+                let mut cursors: Vec<text_editor::Cursor> = self
+                    .find_matches
+                    .iter()
+                    .map(|m| {
+                        let start = text_editor::Position { line: m.line, column: m.col };
+                        let end = text_editor::Position { line: m.end_line, column: m.end_col };
+                        if vim_normal {
+                            text_editor::Cursor { position: start, selection: None }
+                        } else {
+                            text_editor::Cursor { position: end, selection: Some(start) }
+                        }
+                    })
+                    .collect();
+                if cursors.is_empty() {
+                    return Task::none();
+                }
+                let primary = cursors.remove(self.current_match.unwrap_or(0).min(cursors.len() - 1));
+                self.extra_cursors = cursors;
+                self.content.move_to(primary);
+                self.vim_col = primary.position.column;
+                self.show_panel = false;
+                self.clear_find();
+                operation::focus(EDITOR_ID)
+            }
+            #[cfg(feature = "tree-sitter")]
+            Message::ExpandSelection => {
+                self.refresh_syntax_tree(None);
+                let cursor = self.content.cursor();
+                let anchor = cursor.selection.unwrap_or(cursor.position);
+                let (start, end) = if (anchor.line, anchor.column) <= (cursor.position.line, cursor.position.column) {
+                    (anchor, cursor.position)
+                } else {
+                    (cursor.position, anchor)
+                };
+                if let Some((from, to)) = self
+                    .syntax_tree
+                    .as_ref()
+                    .and_then(|t| t.enclosing((start.line, start.column), (end.line, end.column)))
+                {
+                    self.content.move_to(text_editor::Cursor {
+                        position: text_editor::Position { line: to.0, column: to.1 },
+                        selection: Some(text_editor::Position { line: from.0, column: from.1 }),
+                    });
+                }
+                Task::none()
+            }
+            Message::ClearCursors => {
+                self.extra_cursors.clear();
+                Task::none()
+            }
             Message::ShowAbout => {
                 self.show_about = true;
                 Task::none()
@@ -893,6 +2295,7 @@ impl App {
                 Task::none()
             }
             Message::WindowCloseRequested => {
+                self.commit_undo_step();
                 if self.is_modified {
                     self.pending_action = Some(PendingAction::Exit);
                     Task::none()
@@ -926,40 +2329,104 @@ impl App {
                 Task::none()
             }
             Message::VimEnterSearch(forward) => {
+                self.status_message.clear();
                 self.vim_mode = VimMode::Search;
                 self.vim_search_forward = forward;
                 self.vim_search_query = String::new();
+                let cursor = self.content.cursor();
+                self.vim_search_origin = (cursor.position.line, cursor.position.column);
+                self.cancel_vim_search();
+                self.vim_search_matches = Arc::default();
+                self.vim_search_current = None;
                 operation::focus(SEARCH_INPUT_ID)
             }
             Message::VimSearchChanged(q) => {
                 self.vim_search_query = q;
+                let search = if self.vim_search_query.is_empty() {
+                    self.cancel_vim_search();
+                    self.vim_search_matches = Arc::default();
+                    Task::none()
+                } else {
+                    self.start_vim_search(&self.vim_search_query.clone())
+                };
+                self.vim_incsearch_jump();
+                search
+            }
+            Message::VimSearchResults(generation, results) => {
+                if let Some(results) = results
+                    && generation == self.vim_search_generation
+                {
+                    self.vim_search_pending = false;
+                    self.vim_search_matches = results.matches;
+                    if self.vim_mode == VimMode::Search {
+                        self.vim_incsearch_jump();
+                    }
+                }
                 Task::none()
             }
             Message::VimSearchSubmit => {
-                let query = self.vim_search_query.clone();
+                let query = std::mem::take(&mut self.vim_search_query);
+                self.remember(HistoryKind::Search, &query);
                 self.vim_mode = VimMode::Normal;
+                self.vim_search_current = None;
+                let (line, col) = self.vim_search_origin;
+                self.vim_move_to_with_block(line, col);
                 if !query.is_empty() {
-                    self.find_query = query;
-                    self.case_sensitive = false;
-                    self.find_all_matches();
-                    if !self.find_matches.is_empty() {
-                        let cursor = self.content.cursor();
-                        let cur_line = cursor.position.line;
-                        let cur_col = cursor.position.column;
-                        let next = if self.vim_search_forward {
-                            self.find_matches.iter().position(|&(l, c)| {
-                                (l, c) > (cur_line, cur_col)
-                            }).unwrap_or(0)
-                        } else {
-                            self.find_matches.iter().rposition(|&(l, c)| {
-                                (l, c) < (cur_line, cur_col)
-                            }).unwrap_or(self.find_matches.len().saturating_sub(1))
-                        };
-                        self.navigate_to_match(next);
+                    if let Err(err) = build_search_regex(&query, false, true, false) {
+                        self.status_message = format!("E383: Invalid search string: {}", err.to_string().lines().last().unwrap_or(""));
+                        self.vim_search_tick = None;
+                        return operation::focus(EDITOR_ID);
                     }
+                    self.vim_search_pattern = query;
                 }
+                self.vim_search_tick = None;
+                self.vim_search_jump(self.vim_search_forward, 1);
                 operation::focus(EDITOR_ID)
             }
+            Message::ToggleRainbowBrackets => {
+                self.rainbow_brackets = !self.rainbow_brackets;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleCurrentLine => {
+                self.highlight_current_line = !self.highlight_current_line;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleRulers => {
+                self.show_rulers = !self.show_rulers;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleIndentGuides => {
+                self.indent_guides = !self.indent_guides;
+                self.save_settings();
+                Task::none()
+            }
+            Message::SelectTheme(name) => {
+                self.theme_name = name;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleFollowSystemTheme => {
+                self.follow_system_theme = !self.follow_system_theme;
+                self.save_settings();
+                Task::none()
+            }
+            Message::SystemThemeChanged(mode) => {
+                self.system_theme = mode;
+                Task::none()
+            }
+            Message::ToggleWordHighlight => {
+                self.highlight_word = !self.highlight_word;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleWhitespace => {
+                self.show_whitespace = !self.show_whitespace;
+                self.save_settings();
+                Task::none()
+            }
             Message::ToggleLineNumbers => {
                 self.line_numbers = match self.line_numbers {
                     LineNumbers::None => LineNumbers::Absolute,
@@ -982,11 +2449,19 @@ impl App {
                 Task::none()
             }
             Message::VimEnterNormal => {
+                if self.vim_mode == VimMode::Normal {
+                    self.extra_cursors.clear();
+                }
                 if self.vim_mode == VimMode::Insert {
-                    if self.changedtick != self.last_snapshot_tick {
-                        self.push_snapshot();
-                    }
+                    self.commit_undo_step();
+                }
+                if self.vim_mode == VimMode::Search {
+                    let (line, col) = self.vim_search_origin;
+                    self.vim_move_to_with_block(line, col);
+                    self.vim_search_current = None;
+                    self.vim_search_tick = None;
                 }
+                self.vim_record_visual_marks();
                 self.vim_mode = VimMode::Normal;
                 self.vim_pending = None;
                 self.vim_count = String::new();
@@ -996,8 +2471,7 @@ impl App {
                 self.vim_visual_head = (0, 0);
                 if self.show_panel {
                     self.show_panel = false;
-                    self.find_matches.clear();
-                    self.current_match = None;
+                    self.clear_find();
                 }
                 self.vim_apply_block_cursor();
                 operation::focus(EDITOR_ID)
@@ -1025,6 +2499,7 @@ impl App {
                 operation::focus(EDITOR_ID)
             }
             Message::VimEnterCommand => {
+                self.status_message.clear();
                 self.vim_mode = VimMode::Command;
                 self.vim_command = String::new();
                 operation::focus(COMMAND_INPUT_ID)
@@ -1035,8 +2510,20 @@ impl App {
             }
             Message::VimCommandSubmit => {
                 let cmd = self.vim_command.trim().to_string();
+                self.remember(HistoryKind::Command, &cmd);
                 self.vim_mode = VimMode::Normal;
                 self.vim_command = String::new();
+                if let Some(name) = cmd.strip_prefix("checkpoint")
+                    && (name.is_empty() || name.starts_with(' '))
+                {
+                    self.commit_undo_step();
+                    let current = self.undo_tree.current;
+                    self.undo_tree.set_label(current, name);
+                    if let Some(path) = &self.current_file {
+                        crate::persistence::save_undo_tree(path, &self.undo_tree);
+                    }
+                    return operation::focus(EDITOR_ID);
+                }
                 match cmd.as_str() {
                     "w" => return self.update(Message::Save),
                     "w!" => return self.update(Message::SaveAs),
@@ -1046,7 +2533,45 @@ impl App {
                         let save = self.update(Message::Save);
                         return save;
                     }
-                    _ => {}
+                    "noh" | "nohl" | "nohls" | "nohlsearch" => self.vim_hlsearch = false,
+                    "" => {}
+                    _ => {
+                        let task = self.ex_command(&cmd);
+                        if self.vim_mode != VimMode::Confirm {
+                            return task;
+                        }
+                    }
+                }
+                if self.vim_mode == VimMode::Confirm {
+                    return operation::focus(EDITOR_ID);
+                }
+                Task::none()
+            }
+            Message::VimConfirm(c) => {
+                let Some(session) = &mut self.vim_substitute else { return Task::none() };
+                let i = session.current;
+                let done = match c {
+                    'y' => {
+                        session.accepted[i] = true;
+                        false
+                    }
+                    'l' => {
+                        session.accepted[i] = true;
+                        true
+                    }
+                    'a' => {
+                        session.accepted[i..].fill(true);
+                        true
+                    }
+                    'n' => false,
+                    'q' => true,
+                    _ => return Task::none(),
+                };
+                session.current += 1;
+                if done || session.current >= session.edits.len() {
+                    self.finish_substitute();
+                } else {
+                    self.show_substitute_prompt();
                 }
                 Task::none()
             }
@@ -1074,7 +2599,7 @@ impl App {
                 self.vim_mode = VimMode::Insert;
                 self.content.perform(text_editor::Action::Move(text_editor::Motion::End));
                 self.content.perform(text_editor::Action::Edit(text_editor::Edit::Enter));
-                self.is_modified = true;
+                self.mark_changed();
                 operation::focus(EDITOR_ID)
             }
             Message::VimEnterInsertNewlineAbove => {
@@ -1082,10 +2607,11 @@ impl App {
                 self.content.perform(text_editor::Action::Move(text_editor::Motion::Home));
                 self.content.perform(text_editor::Action::Edit(text_editor::Edit::Enter));
                 self.content.perform(text_editor::Action::Move(text_editor::Motion::Up));
-                self.is_modified = true;
+                self.mark_changed();
                 operation::focus(EDITOR_ID)
             }
             Message::VimKey(c) => {
+                self.status_message.clear();
                 let count = self.vim_count.parse::<usize>().unwrap_or(1);
 
                 if self.vim_mode == VimMode::Visual || self.vim_mode == VimMode::VisualLine {
@@ -1172,7 +2698,17 @@ impl App {
                         return Task::none();
                     }
 
+                    if matches!(c, 'y' | 'd' | 'x' | 'c' | ':' | '=') {
+                        self.vim_record_visual_marks();
+                    }
                     match c {
+                        ':' => {
+                            self.vim_mode = VimMode::Command;
+                            self.vim_visual_anchor = None;
+                            self.vim_command = String::from("'<,'>");
+                            self.vim_count = String::new();
+                            return operation::focus(COMMAND_INPUT_ID);
+                        }
                         'y' => {
                             self.vim_register = self.vim_visual_selected_text(hl, hc);
                             self.vim_visual_apply_selection(hl, hc);
@@ -1185,8 +2721,22 @@ impl App {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                             self.vim_mode = VimMode::Normal;
                             self.vim_visual_anchor = None;
-                            self.is_modified = true;
-                            self.push_snapshot();
+                            self.finish_change();
+                        }
+                        '>' | '<' => {
+                            let anchor = self.vim_visual_anchor.map_or(hl, |(line, _)| line);
+                            self.vim_mode = VimMode::Normal;
+                            self.vim_visual_anchor = None;
+                            if self.shift_lines(anchor.min(hl)..anchor.max(hl) + 1, if c == '>' { 1 } else { -1 }) {
+                                self.finish_change();
+                            }
+                            self.vim_move_to_first_non_blank(anchor.min(hl));
+                        }
+                        '=' => {
+                            let anchor = self.vim_visual_anchor.map_or(hl, |(line, _)| line);
+                            self.vim_mode = VimMode::Normal;
+                            self.vim_visual_anchor = None;
+                            return self.vim_format_lines(anchor, hl);
                         }
                         'c' => {
                             self.vim_register = self.vim_visual_selected_text(hl, hc);
@@ -1194,7 +2744,7 @@ impl App {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                             self.vim_mode = VimMode::Insert;
                             self.vim_visual_anchor = None;
-                            self.is_modified = true;
+                            self.mark_changed();
                             return operation::focus(EDITOR_ID);
                         }
                         _ => {}
@@ -1209,7 +2759,7 @@ impl App {
                     self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                     self.content.perform(text_editor::Action::Edit(text_editor::Edit::Insert(c)));
                     self.content.perform(text_editor::Action::Move(text_editor::Motion::Left));
-                    self.is_modified = true;
+                    self.finish_change();
                     return Task::none();
                 }
 
@@ -1227,6 +2777,11 @@ impl App {
                     let op = self.vim_operator.take().unwrap_or('y');
                     self.vim_count = String::new();
                     self.vim_do_text_object(op, modifier, c);
+                    if op == '='
+                        && let Some(lines) = self.selected_lines()
+                    {
+                        return self.vim_format_lines(lines.start, lines.end - 1);
+                    }
                     return Task::none();
                 }
 
@@ -1239,7 +2794,12 @@ impl App {
 
                 match c {
                     'h' | 'j' | 'k' | 'l' | 'w' | 'e' | 'b' | '0' | '$' | 'G' => {
-                        if let Some(op) = self.vim_operator.take() {
+                        if self.vim_operator == Some('=') {
+                            let start = self.content.cursor().position.line;
+                            self.vim_normal_move(c, count);
+                            let end = self.content.cursor().position.line;
+                            return self.vim_format_lines(start, end);
+                        } else if let Some(op) = self.vim_operator.take() {
                             let motion = match c {
                                 'h' => text_editor::Motion::Left,
                                 'j' => text_editor::Motion::Down,
@@ -1260,6 +2820,10 @@ impl App {
                     'g' => {
                         if self.vim_pending == Some(VimPending::G) {
                             self.vim_pending = None;
+                            if self.vim_operator == Some('=') {
+                                let line = self.content.cursor().position.line;
+                                return self.vim_format_lines(0, line);
+                            }
                             self.vim_operator = None;
                             self.vim_normal_move('G', 1);
                             self.vim_move_to_with_block(0, 0);
@@ -1306,29 +2870,17 @@ impl App {
                     '*' => {
                         let word = self.vim_word_under_cursor();
                         if !word.is_empty() {
-                            self.find_query = word;
-                            self.find_all_matches();
-                            let next = self.current_match.map(|i| (i + 1) % self.find_matches.len().max(1)).unwrap_or(0);
-                            if !self.find_matches.is_empty() {
-                                self.navigate_to_match(next);
-                            }
+                            self.vim_search_pattern = format!(r"\b{}\b", regex::escape(&word));
+                            self.vim_search_forward = true;
+                            self.vim_search_tick = None;
+                            self.vim_search_jump(true, count);
                         }
                     }
                     'n' => {
-                        if !self.find_matches.is_empty() {
-                            let next = self.current_match.map(|i| (i + 1) % self.find_matches.len()).unwrap_or(0);
-                            self.navigate_to_match(next);
-                        }
+                        self.vim_search_jump(self.vim_search_forward, count);
                     }
                     'N' => {
-                        if !self.find_matches.is_empty() {
-                            let prev = match self.current_match {
-                                Some(0) => self.find_matches.len() - 1,
-                                Some(i) => i - 1,
-                                None => self.find_matches.len() - 1,
-                            };
-                            self.navigate_to_match(prev);
-                        }
+                        self.vim_search_jump(!self.vim_search_forward, count);
                     }
                     'J' => {
                         for _ in 0..count {
@@ -1337,7 +2889,7 @@ impl App {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Insert(' ')));
                             self.content.perform(text_editor::Action::Move(text_editor::Motion::Left));
                         }
-                        self.is_modified = true;
+                        self.finish_change();
                     }
                     '~' => {
                         let text = self.content.text();
@@ -1352,7 +2904,7 @@ impl App {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Insert(toggled)));
                             self.content.perform(text_editor::Action::Move(text_editor::Motion::Left));
-                            self.is_modified = true;
+                            self.finish_change();
                         }
                     }
                     'u' => { return self.update(Message::Undo); }
@@ -1371,8 +2923,7 @@ impl App {
                         if self.vim_operator == Some('d') {
                             self.vim_do_delete_lines(count);
                             self.vim_operator = None;
-                            self.is_modified = true;
-                            self.push_snapshot();
+                            self.finish_change();
                         } else {
                             self.vim_operator = Some('d');
                             self.vim_count = String::new();
@@ -1381,8 +2932,16 @@ impl App {
                     }
                     'D' => {
                         self.vim_do_motion_op('d', text_editor::Motion::End, 1);
-                        self.is_modified = true;
-                        self.push_snapshot();
+                    }
+                    '=' => {
+                        if self.vim_operator == Some('=') {
+                            let line = self.content.cursor().position.line;
+                            return self.vim_format_lines(line, line + count - 1);
+                        } else {
+                            self.vim_operator = Some('=');
+                            self.vim_count = String::new();
+                            return Task::none();
+                        }
                     }
                     'y' => {
                         if self.vim_operator == Some('y') {
@@ -1399,7 +2958,7 @@ impl App {
                             self.vim_do_delete_lines(count);
                             self.vim_operator = None;
                             self.vim_mode = VimMode::Insert;
-                            self.is_modified = true;
+                            self.mark_changed();
                         } else {
                             self.vim_operator = Some('c');
                             self.vim_count = String::new();
@@ -1408,20 +2967,19 @@ impl App {
                     }
                     'C' => {
                         self.vim_do_motion_op('c', text_editor::Motion::End, 1);
-                        self.is_modified = true;
                     }
                     'x' => {
                         for _ in 0..count {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                         }
-                        self.is_modified = true;
+                        self.finish_change();
                     }
                     's' => {
                         for _ in 0..count {
                             self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
                         }
                         self.vim_mode = VimMode::Insert;
-                        self.is_modified = true;
+                        self.mark_changed();
                     }
                     'p' => {
                         if !self.vim_register.is_empty() {
@@ -1439,8 +2997,7 @@ impl App {
                                     text_editor::Edit::Paste(Arc::new(text)),
                                 ));
                             }
-                            self.is_modified = true;
-                            self.push_snapshot();
+                            self.finish_change();
                         }
                     }
                     'P' => {
@@ -1459,30 +3016,15 @@ impl App {
                                     text_editor::Edit::Paste(Arc::new(text)),
                                 ));
                             }
-                            self.is_modified = true;
-                            self.push_snapshot();
+                            self.finish_change();
                         }
                     }
-                    '>' => {
-                        for _ in 0..count {
-                            self.content.perform(text_editor::Action::Move(text_editor::Motion::Home));
-                            self.content.perform(text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new("    ".to_string()))));
-                        }
-                        self.is_modified = true;
-                    }
-                    '<' => {
-                        for _ in 0..count {
-                            self.content.perform(text_editor::Action::Move(text_editor::Motion::Home));
-                            let text = self.content.text();
-                            let cursor = self.content.cursor();
-                            let line_text = text.lines().nth(cursor.position.line).unwrap_or("");
-                            let spaces = line_text.len() - line_text.trim_start_matches(' ').len();
-                            let remove = spaces.min(4);
-                            for _ in 0..remove {
-                                self.content.perform(text_editor::Action::Edit(text_editor::Edit::Delete));
-                            }
+                    '>' | '<' => {
+                        let line = self.content.cursor().position.line;
+                        if self.shift_lines(line..line + count, if c == '>' { 1 } else { -1 }) {
+                            self.finish_change();
                         }
-                        self.is_modified = true;
+                        self.vim_move_to_first_non_blank(line);
                     }
                     _ => {}
                 }
@@ -1499,3 +3041,384 @@ impl App {
         }
     }
 }
+
+fn plural_lines(count: usize) -> String {
+    format!("{} line{}", count, if count == 1 { "" } else { "s" })
+}
+
+fn formatted_status(changed: usize) -> String {
+    match changed {
+        0 => String::from("Already formatted"),
+        changed => format!("Formatted {}", plural_lines(changed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vim_app(text: &str) -> App {
+        let (mut app, _) = App::new();
+        app.content = text_editor::Content::with_text(text);
+        app.undo_tree.reset(undo_tree::Snapshot { text: app.content.text(), cursor_line: 0, cursor_col: 0 });
+        app.vim_enabled = true;
+        app.vim_mode = VimMode::Normal;
+        app.undo_insert_session = true;
+        app.undo_granularity = UndoGranularity::Word;
+        app
+    }
+
+    fn plain_app(text: &str, line: usize, column: usize) -> App {
+        let mut app = vim_app(text);
+        app.vim_enabled = false;
+        app.vim_mode = VimMode::Insert;
+        place_cursor(&mut app, line, column);
+        app
+    }
+
+    fn place_cursor(app: &mut App, line: usize, column: usize) {
+        app.content.move_to(text_editor::Cursor { position: text_editor::Position { line, column }, selection: None });
+    }
+
+    fn keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let _ = app.update(Message::VimKey(c));
+        }
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            let edit = match c {
+                '\n' => text_editor::Edit::Enter,
+                '\x08' => text_editor::Edit::Backspace,
+                c => text_editor::Edit::Insert(c),
+            };
+            let _ = app.update(Message::Edit(text_editor::Action::Edit(edit)));
+        }
+    }
+
+    fn assert_one_step(app: &mut App, original: &str) {
+        assert_eq!(app.undo_tree.nodes.len(), 2);
+        let _ = app.update(Message::Undo);
+        assert_eq!(app.content.text(), original);
+    }
+
+    #[test]
+    fn insert_session_is_one_undo_step() {
+        let mut app = vim_app("first line\nsecond line");
+        let original = app.content.text();
+        let _ = app.update(Message::VimEnterInsert);
+        type_text(&mut app, "one two\nthree  \x08\x08four ");
+        let _ = app.update(Message::VimEnterNormal);
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn operator_is_one_undo_step() {
+        let mut app = vim_app("alpha beta gamma\ndelta\nepsilon");
+        let original = app.content.text();
+        keys(&mut app, "dw");
+        assert_one_step(&mut app, &original);
+
+        let mut app = vim_app("alpha beta gamma\ndelta\nepsilon");
+        keys(&mut app, "2dd");
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn change_operator_and_its_insert_are_one_undo_step() {
+        let mut app = vim_app("alpha beta gamma");
+        let original = app.content.text();
+        keys(&mut app, "cw");
+        type_text(&mut app, "omega psi ");
+        let _ = app.update(Message::VimEnterNormal);
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn substitute_is_one_undo_step() {
+        let mut app = vim_app("foo bar\nfoo baz\nqux foo");
+        let original = app.content.text();
+        let _ = app.ex_command("%s/foo/zap/g");
+        assert_eq!(app.content.text(), "zap bar\nzap baz\nqux zap");
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn find_scope_follows_typing() {
+        let mut app = plain_app("one\ntwo three four\nfive", 0, 0);
+        app.find_scope = Some((1, 4)..(1, 9));
+        type_text(&mut app, "x\n");
+        assert_eq!(app.find_scope, Some((2, 4)..(2, 9)));
+
+        place_cursor(&mut app, 2, 6);
+        type_text(&mut app, "ab\x08");
+        assert_eq!(app.find_scope, Some((2, 4)..(2, 10)));
+
+        place_cursor(&mut app, 1, 0);
+        type_text(&mut app, "\x08");
+        assert_eq!(app.find_scope, Some((1, 4)..(1, 10)));
+        assert_eq!(&app.content.line(1).unwrap().text[4..10], "tharee");
+    }
+
+    #[test]
+    fn text_lines_follow_edits() {
+        let mut app = vim_app("alpha\nbeta\ngamma");
+        let _ = app.text_snapshot();
+        let check = |app: &App| {
+            assert_eq!(app.text_lines_tick, Some(app.changedtick));
+            assert_eq!(app.text_lines.text(), app.content.text());
+            assert_eq!(app.text_lines.index(), &LineIndex::new(&app.content.text()));
+        };
+        keys(&mut app, "jdd");
+        check(&app);
+        keys(&mut app, "Go");
+        type_text(&mut app, "x\ny\x08\x08z");
+        let _ = app.update(Message::VimEnterNormal);
+        check(&app);
+        keys(&mut app, "ggyyGpkJ");
+        check(&app);
+        keys(&mut app, "uu");
+        check(&app);
+    }
+
+    #[test]
+    fn line_search_agrees_with_whole_text_search() {
+        let mut app = vim_app("abc\nbcd abc\r\n\nxabc");
+        let lines = app.text_snapshot();
+        let text = app.content.text();
+        let scope = Some((1, 2)..(3, 3));
+        for pattern in ["abc", "c$", "^b", r"\bab", "c\\s*b", r"\Aa", "d.a", r"[^a]bc"] {
+            let regex = build_search_regex(pattern, true, true, false).unwrap();
+            let cancel = AtomicBool::new(false);
+            let expected = crate::search::search_text(&regex, &text, lines.index(), None, &cancel).unwrap();
+            assert_eq!(lines.search(&regex, None, &cancel).unwrap().matches, expected.matches, "{pattern}");
+            let offsets = Some(lines.index().offset((1, 2))..lines.index().offset((3, 3)));
+            let expected = crate::search::search_text(&regex, &text, lines.index(), offsets, &cancel).unwrap();
+            assert_eq!(lines.search(&regex, scope.clone(), &cancel).unwrap().matches, expected.matches, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn global_normal_is_one_undo_step() {
+        let mut app = vim_app("a1\nb\na2\na3");
+        let original = app.content.text();
+        let _ = app.ex_command("g/a/normal Ax");
+        assert_eq!(app.content.text(), "a1x\nb\na2x\na3x");
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn global_line_commands_are_one_undo_step() {
+        let cases = [
+            ("g/a/d", "a1\nb\na2\na3", "b"),
+            ("v/a/d", "a1\nb\na2\nc", "a1\na2"),
+            ("g/a/m0", "a1\nb\na2\na3", "a3\na2\na1\nb"),
+            ("g/a/t$", "a1\nb\na2", "a1\nb\na2\na1\na2"),
+            ("g/a/s//x/", "a1 a\nb\na2", "x1 a\nb\nx2"),
+            (r"g/a/s/a/&\r/g", "a1a\nb\na2", "a\n1a\n\nb\na\n2"),
+            ("2,$g/a/d", "a1\nb\na2\na3", "a1\nb"),
+        ];
+        for (command, text, expected) in cases {
+            let mut app = vim_app(text);
+            let _ = app.ex_command(command);
+            assert_eq!(app.content.text(), expected, "{command}");
+            assert_one_step(&mut app, text);
+        }
+    }
+
+    #[test]
+    fn global_without_a_command_prints() {
+        let mut app = vim_app("a1\nb\n  a2");
+        let _ = app.ex_command("g/a/");
+        assert_eq!(app.status_message, "1 a1 | 3 a2");
+        assert_eq!(app.content.cursor().position.line, 2);
+        assert_eq!(app.undo_tree.nodes.len(), 1);
+    }
+
+    #[test]
+    fn vim_search_waits_for_n_after_noh() {
+        let mut app = vim_app("foo bar\nbar foo");
+        app.vim_search_pattern = String::from("bar");
+        keys(&mut app, "n");
+        assert_eq!(app.content.cursor().position.column, 4);
+        app.vim_hlsearch = false;
+        let tick = app.vim_search_tick;
+        keys(&mut app, "x");
+        assert_eq!(app.vim_search_tick, tick);
+        keys(&mut app, "n");
+        assert_eq!(app.content.cursor().position.line, 1);
+        assert_eq!(app.vim_search_tick, Some(app.changedtick));
+    }
+
+    #[test]
+    fn find_results_follow_arrow_keys() {
+        let mut app = vim_app("foo\nbar\nfoo bar\nfoo");
+        let _ = app.update(Message::TogglePanel);
+        let _ = app.update(Message::FindQueryChanged(String::from("foo")));
+        app.show_find_results = true;
+        let _ = app.update(Message::FindResultsFocusToggle);
+        assert_eq!(app.selected_result, Some(0));
+        let _ = app.update(Message::FindResultsMove(1));
+        let _ = app.update(Message::FindResultsMove(5));
+        assert_eq!(app.selected_result, Some(2));
+        let _ = app.update(Message::FindResultsMove(-1));
+        let _ = app.update(Message::FindResultsConfirm);
+        assert!(!app.find_results_focused);
+        assert_eq!(app.content.cursor().position.line, 2);
+    }
+
+    #[test]
+    fn strip_trailing_whitespace_edits_changed_lines() {
+        let mut app = vim_app("a  \nb\nc\t\nd");
+        let original = app.content.text();
+        keys(&mut app, "jj");
+        let _ = app.update(Message::StripTrailingWhitespace);
+        assert_eq!(app.content.text(), "a\nb\nc\nd");
+        assert_eq!(app.content.cursor().position.line, 2);
+        assert_eq!(app.status_message, "Stripped trailing whitespace from 2 lines");
+        assert_one_step(&mut app, &original);
+    }
+
+    #[test]
+    fn preview_follows_vim_motions() {
+        let mut app = vim_app("# Title\n\npara\n\n- item\n- item");
+        let _ = app.update(Message::TogglePreview);
+        keys(&mut app, "jj");
+        assert_eq!(app.preview_line, 2);
+        keys(&mut app, "G");
+        assert_eq!(app.preview_line, 5);
+    }
+
+    #[test]
+    fn word_highlight_is_kept_within_the_same_word() {
+        let mut app = plain_app("alpha beta\nalpha", 0, 1);
+        app.highlight_word = true;
+        let rest = |app: &mut App| {
+            app.track_word_under_cursor();
+            app.word_due = Some(Instant::now());
+            let _ = app.refresh_word_highlight();
+            app.word_matches.clone()
+        };
+        let first = rest(&mut app);
+        assert_eq!(first.len(), 2);
+        place_cursor(&mut app, 0, 3);
+        assert!(Arc::ptr_eq(&first, &rest(&mut app)));
+        place_cursor(&mut app, 0, 7);
+        assert_eq!(rest(&mut app).len(), 1);
+        place_cursor(&mut app, 1, 2);
+        assert_eq!(rest(&mut app).len(), 2);
+        assert!(!Arc::ptr_eq(&first, &app.word_matches));
+    }
+
+    #[test]
+    fn builtin_formatter_leaves_makefiles_alone() {
+        let original = "all:\n\tcc -o all main.c\n";
+        let mut app = plain_app(original, 0, 0);
+        app.current_file = Some(std::path::PathBuf::from("Makefile"));
+        app.formatters.clear();
+        let _ = app.update(Message::FormatDocument);
+        assert_eq!(app.content.text(), original);
+        assert_eq!(app.status_message, "No formatter configured for this file type; left unchanged");
+    }
+
+    #[test]
+    fn backspace_removes_a_soft_tab() {
+        let mut app = plain_app("        x\n\tx\n   x", 0, 8);
+        app.indent = crate::indent::Indent { shiftwidth: 4, expandtab: true, softtabstop: 4 };
+        type_text(&mut app, "\x08");
+        assert_eq!(app.content.text(), "    x\n\tx\n   x");
+        type_text(&mut app, "\x08");
+        assert_eq!(app.content.text(), "x\n\tx\n   x");
+        place_cursor(&mut app, 1, 1);
+        type_text(&mut app, "\x08");
+        assert_eq!(app.content.text(), "x\nx\n   x");
+        place_cursor(&mut app, 2, 3);
+        type_text(&mut app, "\x08");
+        assert_eq!(app.content.text(), "x\nx\nx");
+        type_text(&mut app, "a  \x08");
+        assert_eq!(app.content.text(), "x\nx\na x");
+    }
+
+    #[test]
+    fn brackets_follow_edits() {
+        let mut app = plain_app("fn main() {\n    let x = (1, [2]);\n}\n\nfn other() {\n    \"}\"\n}", 1, 12);
+        app.language = Some(crate::syntax::Language::Rust);
+        app.refresh_brackets(None);
+        let check = |app: &mut App| {
+            let brackets: Vec<_> = app.brackets.iter().cloned().collect();
+            let unmatched = app.unmatched_marks.clone();
+            app.brackets_key = None;
+            app.refresh_brackets(None);
+            assert_eq!(brackets, app.brackets.iter().cloned().collect::<Vec<_>>());
+            assert_eq!(unmatched, app.unmatched_marks);
+        };
+        type_text(&mut app, "ab ");
+        check(&mut app);
+        type_text(&mut app, "{\n");
+        check(&mut app);
+        type_text(&mut app, "\"");
+        check(&mut app);
+        type_text(&mut app, "\x08\x08\x08");
+        check(&mut app);
+        assert!(app.unmatched_marks.is_empty());
+
+        type_text(&mut app, "\n\n");
+        check(&mut app);
+        type_text(&mut app, "}");
+        check(&mut app);
+        assert!(!app.unmatched_marks.is_empty());
+        type_text(&mut app, "\x08\x08");
+        check(&mut app);
+        type_text(&mut app, "\x08");
+        check(&mut app);
+        place_cursor(&mut app, 0, 0);
+        type_text(&mut app, "(\n");
+        check(&mut app);
+        assert_eq!(app.unmatched_marks, vec![(0, 0)]);
+        type_text(&mut app, "\x08");
+        check(&mut app);
+        type_text(&mut app, "\x08");
+        check(&mut app);
+        (app.vim_enabled, app.vim_mode) = (true, VimMode::Normal);
+        place_cursor(&mut app, 4, 0);
+        keys(&mut app, "dd");
+        check(&mut app);
+        keys(&mut app, "jJ");
+        check(&mut app);
+        keys(&mut app, "uu");
+        check(&mut app);
+        (app.vim_enabled, app.vim_mode) = (false, VimMode::Insert);
+        place_cursor(&mut app, 1, 4);
+        type_text(&mut app, "[{\n\n");
+        check(&mut app);
+        type_text(&mut app, "}]");
+        check(&mut app);
+        assert_eq!(app.content.text().lines().nth(3), Some("}]let x = ab (1, [2]);"));
+        assert_eq!(app.unmatched_marks, vec![(0, 0)]);
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn syntax_spans_follow_edits() {
+        let original = "fn main() {\n    let x = 1;\n}\n\nfn other() -> u8 {\n    2\n}";
+        let mut app = plain_app(original, 1, 14);
+        app.language = Some(crate::syntax::Language::Rust);
+        app.refresh_syntax_tree(None);
+        let check = |app: &App| {
+            let fresh = crate::treesitter::SyntaxTree::new(crate::syntax::Language::Rust, app.content.text()).unwrap();
+            let spans: Vec<_> = app.syntax_spans.as_ref().unwrap().iter().cloned().collect();
+            let expected: Vec<_> = fresh.highlights.unwrap().iter().cloned().collect();
+            assert_eq!(spans, expected);
+        };
+        type_text(&mut app, "\n    /* open");
+        check(&app);
+        type_text(&mut app, " */\x08\x08\x08\x08");
+        check(&app);
+        while app.content.text().len() > original.len() {
+            type_text(&mut app, "\x08");
+        }
+        assert_eq!(app.content.text(), original);
+        check(&app);
+    }
+}